@@ -1,4 +1,5 @@
 use crate::codegen::delimeted::delimeted_codegen;
+use crate::codegen::grammar::{descent_codegen, grammar_codegen};
 use crate::codegen::lexable::infer_codegen;
 use crate::codegen::syntx::Syntx;
 use crate::codegen::syntx::*;
@@ -14,24 +15,59 @@ fn capitalize(s: &str) -> String {
 
 fn trim_quotes(s: &str) -> &str {
     let s = s.trim_start();
-    let s = if s.ends_with(';') {
-        &s[..s.len() - 1]
-    } else {
-        s
-    };
-    let s = if s.starts_with('"') { &s[1..] } else { s };
-    let s = if s.ends_with('"') && s.len() >= 1 {
-        &s[..s.len() - 1]
-    } else {
-        s
-    };
-    s
+    let s = s.strip_suffix(';').unwrap_or(s);
+    let s = s.strip_prefix('"').unwrap_or(s);
+    s.strip_suffix('"').unwrap_or(s)
+}
+
+/// Splits `"a", "b\"c", word;` into its items. Quoted items can hold commas and escaped quotes,
+/// bare ones are flags like `nested`.
+fn list_items(s: &str) -> Vec<String> {
+    let s = s.trim().trim_end_matches(';');
+    let s = s.trim_start_matches('[').trim_end_matches(']');
+    let mut items = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let mut item = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some('n') => item.push('\n'),
+                            Some('t') => item.push('\t'),
+                            Some(c) => item.push(c),
+                            None => {}
+                        },
+                        c => item.push(c),
+                    }
+                }
+                items.push(item);
+            }
+            c if c == ',' || c.is_whitespace() => {}
+            c => {
+                let mut item = c.to_string();
+                while let Some(c) = chars.next_if(|c| *c != ',' && !c.is_whitespace()) {
+                    item.push(c);
+                }
+                items.push(item);
+            }
+        }
+    }
+    items
+}
+
+/// Single char item of a `[strings]` entry
+fn single_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    chars.next().filter(|_| chars.next().is_none())
 }
 
 pub fn codegen(src: &str) {
     let mut stx: Syntx = Syntx::default();
 
-    for l in src.lines() {
+    for (n, l) in src.lines().enumerate() {
         let l = l.trim();
         if l.is_empty() || l.starts_with('#') {
             continue;
@@ -58,6 +94,14 @@ pub fn codegen(src: &str) {
                 stx.state = Some(CurrentState::Comments);
                 continue;
             }
+            "[strings]" => {
+                stx.state = Some(CurrentState::Strings);
+                continue;
+            }
+            "[identifiers]" => {
+                stx.state = Some(CurrentState::Identifiers);
+                continue;
+            }
             "[keywords]" => {
                 stx.state = Some(CurrentState::Keywords);
                 continue;
@@ -70,6 +114,14 @@ pub fn codegen(src: &str) {
                 stx.state = Some(CurrentState::Numbers);
                 continue;
             }
+            "[precedence]" => {
+                stx.state = Some(CurrentState::Precedence);
+                continue;
+            }
+            "[grammar]" => {
+                stx.state = Some(CurrentState::Grammar);
+                continue;
+            }
             _ => {}
         }
 
@@ -108,32 +160,84 @@ pub fn codegen(src: &str) {
                 }
                 CurrentState::Comments => {
                     if let Some((key, value)) = l.split_once('=') {
-                        let key = key.trim();
-                        let value = value.trim().trim_end_matches(';');
-
-                        if key == "line" {
-                            let line_comment = value.trim_matches('"');
-                            let chars: Vec<u8> = line_comment.chars().map(|c| c as u8).collect();
-                            if chars.len() == 2 {
-                                stx.comments[0] = [chars[0], chars[1]];
+                        match (key.trim(), list_items(value).as_slice()) {
+                            // line = "//", "#";
+                            ("line", markers) => stx
+                                .comments
+                                .extend(markers.iter().map(|m| Comment::Line(m.clone()))),
+                            // block = "/*", "*/"; or block = "{-", "-}", nested;
+                            ("block", [open, close, flags @ ..]) => {
+                                stx.comments.push(Comment::Block {
+                                    open: open.clone(),
+                                    close: close.clone(),
+                                    nested: flags.iter().any(|f| f == "nested"),
+                                })
                             }
-                        } else if key == "block" {
-                            let parts: Vec<&str> = value
-                                .trim_matches(|c| c == '[' || c == ']')
-                                .split(',')
-                                .map(|s| s.trim().trim_matches('"'))
-                                .collect();
-
-                            if parts.len() == 2 {
-                                let start = parts[0];
-                                let end = parts[1];
-                                let start_char = start.chars().next().unwrap_or('\0') as u8;
-                                let end_char = end.chars().next().unwrap_or('\0') as u8;
-                                stx.comments[1] = [start_char, end_char];
+                            _ => {}
+                        }
+                    }
+                }
+                CurrentState::Strings => {
+                    if let Some((key, value)) = l.split_once('=') {
+                        match (key.trim(), list_items(value).as_slice()) {
+                            // quoted = "\"\"\"", "\"\"\"", escapes, multiline;
+                            ("quoted", [open, close, flags @ ..]) => {
+                                stx.strings.push(StringKind::Quoted {
+                                    open: open.clone(),
+                                    close: close.clone(),
+                                    escapes: flags.iter().any(|f| f == "escapes"),
+                                    multiline: flags.iter().any(|f| f == "multiline"),
+                                })
+                            }
+                            // template = "`", "`", "${", "}", escapes, multiline;
+                            ("template", [open, close, embed, embed_close, flags @ ..]) => {
+                                stx.strings.push(StringKind::Template {
+                                    open: open.clone(),
+                                    close: close.clone(),
+                                    embed: embed.clone(),
+                                    embed_close: embed_close.clone(),
+                                    escapes: flags.iter().any(|f| f == "escapes"),
+                                    multiline: flags.iter().any(|f| f == "multiline"),
+                                })
                             }
+                            // padded = "r", "#", "\""; for r#"..."#
+                            ("padded", [prefix, pad, quote]) => {
+                                if let (Some(pad), Some(quote)) =
+                                    (single_char(pad), single_char(quote))
+                                {
+                                    stx.strings.push(StringKind::Padded {
+                                        prefix: prefix.clone(),
+                                        pad,
+                                        quote,
+                                    })
+                                }
+                            }
+                            // tagged = "R\"", "(", ")", "\""; for R"x(...)x"
+                            ("tagged", [prefix, open, close, quote]) => {
+                                if let (Some(open), Some(close), Some(quote)) =
+                                    (single_char(open), single_char(close), single_char(quote))
+                                {
+                                    stx.strings.push(StringKind::Tagged {
+                                        prefix: prefix.clone(),
+                                        open,
+                                        close,
+                                        quote,
+                                    })
+                                }
+                            }
+                            _ => {}
                         }
                     }
                 }
+                CurrentState::Identifiers => {
+                    // extras = "$", "_";
+                    if let Some(("extras", value)) =
+                        l.split_once('=').map(|(key, value)| (key.trim(), value))
+                    {
+                        stx.ident_extras
+                            .extend(list_items(value).iter().filter_map(|c| single_char(c)));
+                    }
+                }
                 CurrentState::Keywords => {
                     let keyword = l.trim_end_matches(';').to_string();
                     stx.keywords.insert(capitalize(&keyword), keyword.clone());
@@ -161,10 +265,23 @@ pub fn codegen(src: &str) {
                         })
                         .collect::<Vec<String>>();
                 }
+                // loosest first: left = Plus, Minus;
+                CurrentState::Precedence => {
+                    if let Err(e) = stx.grammar.push_precedence(n + 1, l) {
+                        eprintln!("{}", e);
+                    }
+                }
+                CurrentState::Grammar => {
+                    if let Err(e) = stx.grammar.push_rule(n + 1, l) {
+                        eprintln!("{}", e);
+                    }
+                }
             }
         }
     }
     enum_codegen(stx.clone());
     delimeted_codegen(stx.clone());
     infer_codegen(stx.clone());
+    grammar_codegen(stx.clone());
+    descent_codegen(stx.clone());
 }
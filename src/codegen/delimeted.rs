@@ -8,7 +8,7 @@ pub fn delimeted_codegen(stx: Syntx) {
     write!(
         f,
         "use crate::langs::{0}::{0}_tokenset::*;
-use crate::tokens::token_traits::Delimeted;\n
+use crate::tokens::token_traits::{{CommentStyle, Delimeted, StringStyle}};\n
 impl Delimeted for {}TokenSet {{
     #[inline(always)]
     fn allowed_unicode_char(c: &char) -> Option<usize> {{
@@ -29,82 +29,133 @@ impl Delimeted for {}TokenSet {{
     )
     .unwrap();
     for (escape_char, real_char) in &stx.scapes {
-        write!(f, "\t\t\t'{}' => Some('{}'),\n", escape_char, real_char).unwrap();
+        writeln!(f, "\t\t\t'{}' => Some('{}'),", escape_char, real_char).unwrap();
     }
     write!(f, "\t\t\t_ => None\n\t\t}}\n").unwrap();
     write!(f, "\t}}\n\n").unwrap();
     write!(
         f,
         "\t#[inline(always)]
-    fn may_trigger_line_comment(c: char) -> Option<usize> {{
-        if c == '{}' {{ Some({}) }} else {{ None }}
-    }}\n
-",
-        stx.comments[0][0] as char,
-        stx.comments[0].len(),
+    fn comments() -> &'static [CommentStyle] {{
+        &[\n"
     )
     .unwrap();
-
+    for comment in &stx.comments {
+        match comment {
+            Comment::Line(open) => writeln!(f, "\t\t\tCommentStyle::Line({:?}),", open),
+            Comment::Block {
+                open,
+                close,
+                nested,
+            } => writeln!(
+                f,
+                "\t\t\tCommentStyle::Block {{ open: {:?}, close: {:?}, nested: {} }},",
+                open, close, nested
+            ),
+        }
+        .unwrap();
+    }
+    write!(f, "\t\t]\n\t}}\n\n").unwrap();
     write!(
         f,
         "\t#[inline(always)]
-    fn trigger_comment_line(input: &[char]) -> bool {{
-        input == ['{}', '{}']
-    }}
-",
-        stx.comments[0][0] as char, stx.comments[0][1] as char,
-    )
-    .unwrap();
-    write!(
-        f,
-        "
-    #[inline(always)]
-    fn may_trigger_block_comment(c: char) -> Option<(Vec<char>, usize)> {{
-        if c == '{}' {{
-            Some((vec!['{}', '{}'], 2))
-        }} else {{
-            None
-        }}
-    }}\n\n",
-        stx.comments[1][0] as char, stx.comments[1][0] as char, stx.comments[1][1] as char,
+    fn strings() -> &'static [StringStyle] {{
+        &[\n"
     )
     .unwrap();
+    // plain double quoted strings unless the spec says otherwise
+    let default = [StringKind::Quoted {
+        open: "\"".into(),
+        close: "\"".into(),
+        escapes: true,
+        multiline: false,
+    }];
+    let strings = if stx.strings.is_empty() {
+        &default[..]
+    } else {
+        &stx.strings[..]
+    };
+    for string in strings {
+        match string {
+            StringKind::Quoted {
+                open,
+                close,
+                escapes,
+                multiline,
+            } => writeln!(
+                f,
+                "\t\t\tStringStyle::Quoted {{ open: {:?}, close: {:?}, escapes: {}, multiline: {} }},",
+                open, close, escapes, multiline
+            ),
+            StringKind::Template {
+                open,
+                close,
+                embed,
+                embed_close,
+                escapes,
+                multiline,
+            } => writeln!(
+                f,
+                "\t\t\tStringStyle::Template {{ open: {:?}, close: {:?}, embed: {:?}, embed_close: {:?}, escapes: {}, multiline: {} }},",
+                open, close, embed, embed_close, escapes, multiline
+            ),
+            StringKind::Padded { prefix, pad, quote } => writeln!(
+                f,
+                "\t\t\tStringStyle::Padded {{ prefix: {:?}, pad: {:?}, quote: {:?} }},",
+                prefix, pad, quote
+            ),
+            StringKind::Tagged {
+                prefix,
+                open,
+                close,
+                quote,
+            } => writeln!(
+                f,
+                "\t\t\tStringStyle::Tagged {{ prefix: {:?}, open: {:?}, close: {:?}, quote: {:?} }},",
+                prefix, open, close, quote
+            ),
+        }
+        .unwrap();
+    }
+    write!(f, "\t\t]\n\t}}\n\n").unwrap();
     write!(
         f,
         "\t#[inline(always)]
-    fn is_delimeter(c: &u8) -> bool {{
+    fn is_delimeter(c: &char) -> bool {{
         matches!(
             c,\n"
     )
     .unwrap();
     let first = stx.delimiters.first().unwrap();
-    writeln!(f, "\t\t\tb'{}'", stx.tokens.get(first).unwrap()).unwrap();
+    writeln!(f, "\t\t\t'{}'", stx.tokens.get(first).unwrap()).unwrap();
 
     for del in stx.delimiters.iter().skip(1) {
-        writeln!(f, "\t\t\t| b'{}'", stx.tokens.get(del).unwrap()).unwrap();
+        writeln!(f, "\t\t\t| '{}'", stx.tokens.get(del).unwrap()).unwrap();
     }
     write!(f, "\t\t)\n\t}}\n\n").unwrap();
-    write!(
-        f,
-        "\t#[inline(always)]
-    fn trigger_comment_block(input: &[char]) -> bool {{
-        input == ['{}', '{}']
+    if !stx.ident_extras.is_empty() {
+        write!(
+            f,
+            "\t#[inline(always)]
+    fn ident_extras() -> &'static [char] {{
+        &{:?}
     }}\n\n",
-        stx.comments[1][0] as char, stx.comments[1][1] as char
-    )
-    .unwrap();
+            stx.ident_extras
+        )
+        .unwrap();
+    }
     write!(
         f,
         "\t#[inline(always)]
-    fn is_operator(s: &[u8]) -> bool {{
+    fn is_operator(s: &str) -> bool {{
         matches!(
             s,\n"
     )
     .unwrap();
     let op = stx.operators.first().unwrap();
-    writeln!(f, "\t\t\tb\"{}\"", stx.tokens.get(op).unwrap()).unwrap();
+    writeln!(f, "\t\t\t\"{}\"", stx.tokens.get(op).unwrap()).unwrap();
     for op in stx.operators[1..].iter() {
-        writeln!(f, "\t\t\t | b\"{}\"", stx.tokens.get(op).unwrap()).unwrap();
+        writeln!(f, "\t\t\t | \"{}\"", stx.tokens.get(op).unwrap()).unwrap();
     }
     write!(f, "\t\t)\n\t}}\n\n").unwrap();
     write!(
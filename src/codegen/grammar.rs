@@ -0,0 +1,377 @@
+use crate::codegen::syntx::Syntx;
+use crate::engine::descent::predictions;
+use crate::engine::errors::GrammarError;
+use crate::engine::grammar::{Assoc, Grammar, Item, Lowered, Symbol};
+use crate::engine::parser::Parser;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::Write;
+
+/// Checks `[grammar]` builds LALR(1) tables and writes the module that builds them at runtime,
+/// along with the kinds of the nodes for trees of the grammar.
+/// Nothing is written for a spec without rules, or one whose grammar doesn't make a parser.
+pub fn grammar_codegen(stx: Syntx) {
+    let mut grammar = stx.grammar;
+    if grammar.rules.is_empty() {
+        return;
+    }
+    grammar.tokens = stx.tokens;
+    for level in &grammar.precedence {
+        for operator in &level.operators {
+            if let Item::Name(name) = operator
+                && !stx.operators.contains(name)
+            {
+                eprintln!("line {}: `{}` is not in [operators]", level.line, name);
+            }
+        }
+    }
+    let parser = match Parser::new(&grammar) {
+        Ok(parser) => parser,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    let mut f = File::create(format!("langs/{0}/{0}_grammar.rs", stx.name)).unwrap();
+    let rules: Vec<String> = grammar
+        .precedence
+        .iter()
+        .map(|l| l.to_string())
+        .chain(grammar.rules.iter().map(|r| r.to_string()))
+        .collect();
+    let mut tokens: Vec<_> = grammar.tokens.iter().collect();
+    tokens.sort();
+
+    writeln!(
+        f,
+        "use crate::engine::grammar::Grammar;
+use crate::engine::parser::Parser;
+use crate::tokens::token_traits::Parseable;
+
+/// `[precedence]` and `[grammar]` of the spec, LALR(1) in {} states
+pub const GRAMMAR: &str = {:?};
+pub const TOKENS: &[(&str, &str)] = &[",
+        parser.states(),
+        rules.join("\n")
+    )
+    .unwrap();
+    for (name, spelling) in tokens {
+        writeln!(f, "    ({:?}, {:?}),", name, spelling).unwrap();
+    }
+    writeln!(
+        f,
+        "];
+
+pub fn grammar() -> Grammar {{
+    let mut grammar = Grammar::parse(GRAMMAR).expect(\"checked by codegen\");
+    grammar.tokens = TOKENS
+        .iter()
+        .map(|&(name, spelling)| (name.to_string(), spelling.to_string()))
+        .collect();
+    grammar
+}}
+
+pub fn parser() -> Parser {{
+    Parser::new(&grammar()).expect(\"checked by codegen\")
+}}"
+    )
+    .unwrap();
+    f.write_all(node_source(&parser.grammar).as_bytes())
+        .unwrap();
+}
+
+/// `Node`, the kinds of the nodes the parsers make for `grammar` in a concrete syntax tree. The
+/// groups spliced into their parents get none.
+fn node_source(grammar: &Lowered) -> String {
+    let kinds: Vec<(usize, &String)> = grammar
+        .nonterminals
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|&(n, _)| grammar.productions.iter().any(|p| p.lhs == n && !p.inline))
+        .collect();
+    let mut out = String::from(
+        "
+/// Kinds of the nodes in a `Cst` of the grammar
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Node {
+",
+    );
+    for (_, name) in &kinds {
+        out.push_str(&format!("    {},\n", name));
+    }
+    out.push_str(
+        "}
+
+impl Parseable for Node {
+    fn name(&self) -> &'static str {
+        match self {
+",
+    );
+    for (_, name) in &kinds {
+        out.push_str(&format!("            Node::{0} => {0:?},\n", name));
+    }
+    out.push_str(
+        "        }
+    }
+
+    fn from_symbol(symbol: usize) -> Option<Self> {
+        match symbol {
+",
+    );
+    for (n, name) in &kinds {
+        out.push_str(&format!("            {} => Some(Node::{}),\n", n, name));
+    }
+    out.push_str("            _ => None,\n        }\n    }\n}\n");
+    out
+}
+
+/// Writes the recursive descent parser for `[grammar]`, unless the grammar is not LL(1)
+pub fn descent_codegen(stx: Syntx) {
+    let mut grammar = stx.grammar;
+    if grammar.rules.is_empty() {
+        return;
+    }
+    grammar.tokens = stx.tokens;
+    match descent_source(&grammar) {
+        Ok(source) => {
+            let mut f = File::create(format!("langs/{0}/{0}_parser.rs", stx.name)).unwrap();
+            f.write_all(source.as_bytes()).unwrap();
+        }
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+/// `parse_section_list` for `SectionList`, `parse_section_list_1` for its first group
+fn function_name(nonterminal: &str) -> String {
+    let mut name = String::from("parse_");
+    let mut prev = '_';
+    for c in nonterminal.chars() {
+        if c.is_uppercase() && (prev.is_lowercase() || prev.is_ascii_digit()) {
+            name.push('_');
+        }
+        match c {
+            '#' => name.push('_'),
+            c => name.extend(c.to_lowercase()),
+        }
+        prev = c;
+    }
+    name
+}
+
+/// Source of a recursive descent parser for `grammar`: `parse` for the whole input and a
+/// `parse_` function per nonterminal. Optional groups get a function of their own that adds
+/// to the children of the node they are in. A nonterminal with operator productions gets a
+/// `_above` one too, taking only the operators that bind at least as tight as it is told.
+pub fn descent_source(grammar: &Grammar) -> Result<String, GrammarError> {
+    let lowered = grammar.lower()?;
+    let predict = predictions(&lowered)?;
+    let eof = lowered.terminals.iter().any(|t| t == "EOF");
+    let names: Vec<String> = lowered
+        .nonterminals
+        .iter()
+        .map(|n| function_name(n))
+        .collect();
+    let inline: Vec<bool> = (0..lowered.nonterminals.len())
+        .map(|n| lowered.productions.iter().any(|p| p.lhs == n && p.inline))
+        .collect();
+    // what the parser matches a terminal by, and how the grammar writes it
+    let keys = |terminals: &BTreeSet<usize>| {
+        let list = |v: &[String]| {
+            terminals
+                .iter()
+                .map(|&t| format!("{:?}", v[t]))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        (list(&lowered.terminals), list(&lowered.terminal_names))
+    };
+
+    let mut out = String::from(
+        "use crate::engine::descent::Descent;
+use crate::engine::errors::ParseError;
+use crate::engine::parser::ParseTree;
+use crate::tokens::span::Spanned;
+use crate::tokens::token_traits::Token;
+
+/// Names of the nodes, for `ParseTree::render`
+pub const NONTERMINALS: &[&str] = &[",
+    );
+    // laid out the way rustfmt would, short lists on one line
+    let quoted: Vec<String> = lowered
+        .nonterminals
+        .iter()
+        .map(|n| format!("{:?}", n))
+        .collect();
+    match quoted.join(", ") {
+        line if line.len() <= 60 => out.push_str(&line),
+        _ => {
+            for name in &quoted {
+                out.push_str(&format!("\n    {},", name));
+            }
+            out.push('\n');
+        }
+    }
+    let Symbol::Nonterminal(start) = lowered.productions[0].rhs[0] else {
+        unreachable!("the start symbol is a nonterminal");
+    };
+    out.push_str(&format!(
+        "];
+
+pub fn parse<K: Token>(
+    tokens: impl IntoIterator<Item = Spanned<K>>,
+) -> Result<ParseTree<K>, ParseError> {{
+    let mut p = Descent::new(tokens, {eof});
+    let tree = {start}(&mut p)?;
+    p.finish()?;
+    Ok(tree)
+}}
+",
+        eof = eof,
+        start = names[start]
+    ));
+
+    for n in 1..lowered.nonterminals.len() {
+        let productions: Vec<usize> = (0..lowered.productions.len())
+            .filter(|&p| lowered.productions[p].lhs == n)
+            .collect();
+        let (operators, operands): (Vec<usize>, Vec<usize>) = productions
+            .iter()
+            .partition(|&&p| lowered.infix(p).is_some());
+        let pratt = !operators.is_empty();
+        out.push('\n');
+        for &p in &productions {
+            out.push_str(&format!("/// `{}`\n", lowered.item(p, None)));
+        }
+        // the list nodes gather their children in
+        let children = if inline[n] {
+            out.push_str(&format!(
+                "fn {}<K: Token>(\n    p: &mut Descent<K>,\n    children: &mut Vec<ParseTree<K>>,\n) -> Result<(), ParseError> {{\n",
+                names[n]
+            ));
+            "children"
+        } else if pratt {
+            out.push_str(&format!(
+                "pub fn {0}<K: Token>(p: &mut Descent<K>) -> Result<ParseTree<K>, ParseError> {{\n    {0}_above(p, 0)\n}}\n\n/// `{1}` with the operators that bind at least as tight as `min`\n",
+                names[n], lowered.nonterminals[n]
+            ));
+            let signature = format!(
+                "fn {}_above<K: Token>(p: &mut Descent<K>, min: usize) -> Result<ParseTree<K>, ParseError> {{",
+                names[n]
+            );
+            out.push_str(&match signature.len() <= 100 {
+                true => signature,
+                false => format!(
+                    "fn {}_above<K: Token>(\n    p: &mut Descent<K>,\n    min: usize,\n) -> Result<ParseTree<K>, ParseError> {{",
+                    names[n]
+                ),
+            });
+            out.push_str(
+                "\n    let mut left = 'operand: {\n        let mut children = Vec::new();\n",
+            );
+            "&mut children"
+        } else {
+            out.push_str(&format!(
+                "pub fn {}<K: Token>(p: &mut Descent<K>) -> Result<ParseTree<K>, ParseError> {{\n    let mut children = Vec::new();\n",
+                names[n]
+            ));
+            "&mut children"
+        };
+        let pad = if pratt { "        " } else { "    " };
+        // calls matching the symbols of `p` from `from` on
+        let sequence = |p: usize, from: usize, pad: &str| {
+            let rhs = &lowered.productions[p].rhs;
+            let mut out = String::new();
+            for (i, symbol) in rhs.iter().enumerate().skip(from) {
+                let list = children.trim_start_matches("&mut ");
+                out.push_str(&match *symbol {
+                    Symbol::Terminal(t) => format!(
+                        "{pad}    {}.push(p.expect({:?}, {:?})?);\n",
+                        list, lowered.terminals[t], lowered.terminal_names[t]
+                    ),
+                    Symbol::Nonterminal(m) if inline[m] => {
+                        format!("{pad}    {}(p, {})?;\n", names[m], children)
+                    }
+                    // an operand closing an operator production binds as tight as it
+                    Symbol::Nonterminal(m) if m == n && pratt && i > 0 && i + 1 == rhs.len() => {
+                        match lowered.production_precedence(p) {
+                            Some((level, assoc)) => format!(
+                                "{pad}    {}.push({}_above(p, {})?);\n",
+                                list,
+                                names[m],
+                                if assoc == Assoc::Right {
+                                    level
+                                } else {
+                                    level + 1
+                                }
+                            ),
+                            None => format!("{pad}    {}.push({}(p)?);\n", list, names[m]),
+                        }
+                    }
+                    Symbol::Nonterminal(m) => {
+                        format!("{pad}    {}.push({}(p)?);\n", list, names[m])
+                    }
+                });
+            }
+            out
+        };
+        let mut expected = BTreeSet::new();
+        for &p in &operands {
+            let (matches, _) = keys(&predict[p]);
+            expected.extend(&predict[p]);
+            out.push_str(&format!("{pad}if p.at(&[{}]) {{\n", matches));
+            out.push_str(&sequence(p, 0, pad));
+            out.push_str(&match (inline[n], pratt) {
+                (true, _) => format!("{pad}    return Ok(());\n{pad}}}\n"),
+                (false, true) => format!(
+                    "{pad}    break 'operand ParseTree::Node {{\n{pad}        symbol: {},\n{pad}        production: {},\n{pad}        children,\n{pad}    }};\n{pad}}}\n",
+                    n, p
+                ),
+                (false, false) => format!(
+                    "{pad}    return Ok(ParseTree::Node {{\n{pad}        symbol: {},\n{pad}        production: {},\n{pad}        children,\n{pad}    }});\n{pad}}}\n",
+                    n, p
+                ),
+            });
+        }
+        let (_, names) = keys(&expected);
+        if !pratt {
+            out.push_str(&format!("    Err(p.error(&[{}]))\n}}\n", names));
+            continue;
+        }
+        out.push_str(&format!(
+            "        return Err(p.error(&[{}]));\n    }};\n",
+            names
+        ));
+        // a nonassoc operator can't follow one of its level
+        let nonassoc = operators
+            .iter()
+            .any(|&p| matches!(lowered.production_precedence(p), Some((_, Assoc::NonAssoc))));
+        if nonassoc {
+            out.push_str("    let mut last = None;\n");
+        }
+        out.push_str("    loop {\n");
+        for &p in &operators {
+            let t = lowered.infix(p).expect("an operator production");
+            let (level, _) = lowered.precedence[t].expect("operators have a precedence");
+            let repeats = match lowered.production_precedence(p) {
+                Some((_, Assoc::NonAssoc)) => format!(" && last != Some({})", level),
+                _ => String::new(),
+            };
+            out.push_str(&format!(
+                "        if min <= {}{} && p.at(&[{:?}]) {{\n            let mut children = vec![left];\n",
+                level, repeats, lowered.terminals[t]
+            ));
+            out.push_str(&sequence(p, 1, "        "));
+            out.push_str(&format!(
+                "            left = ParseTree::Node {{\n                symbol: {},\n                production: {},\n                children,\n            }};\n",
+                n, p
+            ));
+            if nonassoc {
+                out.push_str(&format!("            last = Some({});\n", level));
+            }
+            out.push_str("            continue;\n        }\n");
+        }
+        out.push_str("        return Ok(left);\n    }\n}\n");
+    }
+    Ok(out)
+}
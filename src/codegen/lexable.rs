@@ -6,7 +6,7 @@ pub fn infer_codegen(stx: Syntx) {
     let mut f = File::create(format!("langs/{0}/{0}_inference.rs", stx.name)).unwrap();
     write!(
         f,
-        "use crate::engine::normalize::normalize;
+        "use crate::engine::interner::Ident;
 use crate::engine::states::State;
 use crate::langs::{0}::{0}_tokenset::*;
 use crate::tokens::token_traits::{{Lexable, Delimeted }};
@@ -18,9 +18,14 @@ use std::borrow::Cow;\n\n",
     write!(
         f,
         "impl Lexable for {0}TokenSet {{
-    type Token = {0}Token;
-    fn infer_token(s: String, state: &mut State<Self>) -> Option<Self::Token> {{
-        let raw = s.as_str();
+    type Token<'a> = {0}Token<'a>;
+
+    fn into_owned<'a, 'b>(token: {0}Token<'a>) -> {0}Token<'b> {{
+        token.into_owned()
+    }}
+
+    fn infer_token<'a>(s: Cow<'a, str>, state: &mut State<Self>) -> Option<{0}Token<'a>> {{
+        let raw: &str = &s;
         if state.in_char {{
             return Some({0}Token::Identifier({0}Identifier::CharLiteral(s)));
         }}
@@ -33,7 +38,7 @@ use std::borrow::Cow;\n\n",
 
     write!(
         f,
-        "        if {0}TokenSet::is_operator(s.as_bytes()) {{
+        "        if {0}TokenSet::is_operator(&s) {{
             return Some(match s.as_bytes() {{\n",
         stx.name
     )
@@ -50,7 +55,7 @@ use std::borrow::Cow;\n\n",
     }
     writeln!(
         f,
-        "                _ => {0}Token::Identifier({0}Identifier::Unknown(normalize(&s.as_bytes(), state.brace_level))),
+        "                _ => {0}Token::Identifier({0}Identifier::Unknown(Ident::new(&s, state.brace_level))),
             }});
         }}",
         stx.name
@@ -58,7 +63,7 @@ use std::borrow::Cow;\n\n",
 
     write!(
         f,
-        "        if let Some(&first) = s.as_bytes().first() {{
+        "        if let Some(first) = s.chars().next() {{
             if {0}TokenSet::is_delimeter(&first) {{
                 return Some(match s.as_bytes() {{\n",
         stx.name
@@ -76,7 +81,7 @@ use std::borrow::Cow;\n\n",
     }
     writeln!(
         f,
-        "                    _ => {0}Token::Identifier({0}Identifier::Unknown(normalize(&s.as_bytes(), state.brace_level))),
+        "                    _ => {0}Token::Identifier({0}Identifier::Unknown(Ident::new(&s, state.brace_level))),
                 }});
             }}
         }}",
@@ -98,7 +103,7 @@ use std::borrow::Cow;\n\n",
 
     write!(
         f,
-        "        if !state.in_str && !state.in_char {{
+        "        if !state.in_str && !state.in_char && raw.as_bytes().first().is_some_and(u8::is_ascii_digit) {{
             let mut is_float = false;
             let mut all_digits = true;
             for b in raw.chars() {{
@@ -120,7 +125,7 @@ use std::borrow::Cow;\n\n",
                     Cow::Borrowed(raw)
                 }};
                 if let Ok(f) = cleaned.parse::<f64>() {{
-                    return Some({0}Token::Identifier({0}Identifier::Float(f)));
+                    return Some({0}Token::Identifier({0}Identifier::Float(s, f)));
                 }}
             }} else if all_digits {{
                 return Some({0}Token::Identifier({0}Identifier::Integer(s, {0}Base::Decimal)));
@@ -140,7 +145,7 @@ use std::borrow::Cow;\n\n",
 
     writeln!(
         f,
-        "        Some({0}Token::Identifier({0}Identifier::Unknown(normalize(&s.as_bytes(), state.brace_level))))
+        "        Some({0}Token::Identifier({0}Identifier::Unknown(Ident::new(&s, state.brace_level))))
     }}
 }}",
         stx.name
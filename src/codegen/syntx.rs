@@ -1,3 +1,4 @@
+use crate::engine::grammar::Grammar;
 use std::collections::HashMap;
 #[derive(Clone)]
 pub enum CurrentState {
@@ -6,12 +7,16 @@ pub enum CurrentState {
     Delimeters,
     Operators,
     Comments,
+    Strings,
+    Identifiers,
     Keywords,
     Scapes,
     Numbers,
+    Precedence,
+    Grammar,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Syntx {
     pub name: String,
     pub tokens: HashMap<String, String>,
@@ -20,22 +25,51 @@ pub struct Syntx {
     pub numbers: Vec<String>,
     pub keywords: HashMap<String, String>,
     pub scapes: HashMap<String, String>,
-    pub comments: [[u8; 2]; 2],
+    pub comments: Vec<Comment>,
+    pub strings: Vec<StringKind>,
+    /// Chars besides the Unicode XID ones identifiers may contain
+    pub ident_extras: Vec<char>,
+    pub grammar: Grammar,
     pub state: Option<CurrentState>,
 }
 
-impl Default for Syntx {
-    fn default() -> Self {
-        Syntx {
-            scapes: HashMap::new(),
-            name: String::new(),
-            tokens: HashMap::new(),
-            delimiters: Vec::new(),
-            numbers: Vec::new(),
-            operators: Vec::new(),
-            keywords: HashMap::new(),
-            comments: [[0; 2]; 2],
-            state: None,
-        }
-    }
+/// One comment style from the `[comments]` section
+#[derive(Clone, Debug, PartialEq)]
+pub enum Comment {
+    Line(String),
+    Block {
+        open: String,
+        close: String,
+        nested: bool,
+    },
+}
+
+/// One string literal style from the `[strings]` section
+#[derive(Clone, Debug, PartialEq)]
+pub enum StringKind {
+    Quoted {
+        open: String,
+        close: String,
+        escapes: bool,
+        multiline: bool,
+    },
+    Template {
+        open: String,
+        close: String,
+        embed: String,
+        embed_close: String,
+        escapes: bool,
+        multiline: bool,
+    },
+    Padded {
+        prefix: String,
+        pad: char,
+        quote: char,
+    },
+    Tagged {
+        prefix: String,
+        open: char,
+        close: char,
+        quote: char,
+    },
 }
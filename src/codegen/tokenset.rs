@@ -1,4 +1,4 @@
-use crate::codegen::syntx::Syntx;
+use crate::codegen::syntx::{StringKind, Syntx};
 use std::fs::File;
 use std::io::Write;
 
@@ -7,10 +7,14 @@ pub fn enum_codegen(stx: Syntx) {
 
     writeln!(
         f,
-        "use crate::tokens::token_traits::{{Token, TokenKind}};
+        "use crate::engine::interner::Ident;
+use crate::tokens::printer::quote;
+use crate::tokens::span::Spanned;
+use crate::tokens::token_traits::{{Token, TokenKind}};
+use std::borrow::Cow;
 #[derive(Debug, Clone, PartialEq)]
-pub enum {0}Token {{
-    Identifier({0}Identifier),
+pub enum {0}Token<'a> {{
+    Identifier({0}Identifier<'a>),
     Operator({0}Operator),
     Delimeter({0}Delimeter),
     EOF,
@@ -18,9 +22,29 @@ pub enum {0}Token {{
         stx.name
     )
     .unwrap();
+    // string literals print with the first quotes that take escapes
+    let (open, close) = stx
+        .strings
+        .iter()
+        .find_map(|s| match s {
+            StringKind::Quoted {
+                open,
+                close,
+                escapes: true,
+                ..
+            }
+            | StringKind::Template {
+                open,
+                close,
+                escapes: true,
+                ..
+            } => Some((open.as_str(), close.as_str())),
+            _ => None,
+        })
+        .unwrap_or(("\"", "\""));
     write!(
         f,
-        "impl Token for {0}Token {{
+        "impl Token for {0}Token<'_> {{
     fn kind(&self) -> TokenKind {{
         match self {{
             {0}Token::EOF => TokenKind::Delimeter,
@@ -33,21 +57,76 @@ pub enum {0}Token {{
             {0}Token::Delimeter(_) => TokenKind::Delimeter,
         }}
     }}
+
+    fn eof() -> Self {{
+        {0}Token::EOF
+    }}
+
+    fn spelling(&self) -> Cow<'_, str> {{
+        match self {{
+            {0}Token::EOF => Cow::Borrowed(\"\"),
+            {0}Token::Operator(op) => Cow::Borrowed(op.as_str()),
+            {0}Token::Delimeter(d) => Cow::Borrowed(d.as_str()),
+            {0}Token::Identifier(id) => match id {{
+                {0}Identifier::{0}Keyword(k) => Cow::Borrowed(k.as_str()),
+                {0}Identifier::StringLiteral(s) => Cow::Owned(quote::<{0}TokenSet>(s, {1:?}, {2:?})),
+                {0}Identifier::CharLiteral(s) => Cow::Owned(quote::<{0}TokenSet>(s, \"'\", \"'\")),
+                {0}Identifier::Integer(s, _) => Cow::Borrowed(s),
+                {0}Identifier::Float(s, _) => Cow::Borrowed(s),
+                {0}Identifier::Unknown(ident) => Cow::Borrowed(ident.name().unwrap_or_default()),
+            }},
+        }}
+    }}
+
+    fn terminal(&self) -> Cow<'_, str> {{
+        match self {{
+            {0}Token::EOF => Cow::Borrowed(\"EOF\"),
+            {0}Token::Identifier(id) => match id {{
+                {0}Identifier::Integer(_, _) | {0}Identifier::Float(_, _) => Cow::Borrowed(\"NumberLiteral\"),
+                {0}Identifier::StringLiteral(_) => Cow::Borrowed(\"StringLiteral\"),
+                {0}Identifier::CharLiteral(_) => Cow::Borrowed(\"CharLiteral\"),
+                {0}Identifier::{0}Keyword(k) => Cow::Borrowed(k.as_str()),
+                {0}Identifier::Unknown(_) => Cow::Borrowed(\"Identifier\"),
+            }},
+            _ => self.spelling(),
+        }}
+    }}
+}}
+
+impl {0}Token<'_> {{
+    pub fn into_owned(self) -> {0}Token<'static> {{
+        match self {{
+            {0}Token::Identifier(id) => {0}Token::Identifier(match id {{
+                {0}Identifier::{0}Keyword(k) => {0}Identifier::{0}Keyword(k),
+                {0}Identifier::StringLiteral(s) => {0}Identifier::StringLiteral(Cow::Owned(s.into_owned())),
+                {0}Identifier::CharLiteral(s) => {0}Identifier::CharLiteral(Cow::Owned(s.into_owned())),
+                {0}Identifier::Integer(s, b) => {0}Identifier::Integer(Cow::Owned(s.into_owned()), b),
+                {0}Identifier::Float(s, f) => {0}Identifier::Float(Cow::Owned(s.into_owned()), f),
+                {0}Identifier::Unknown(h) => {0}Identifier::Unknown(h),
+            }}),
+            {0}Token::Operator(op) => {0}Token::Operator(op),
+            {0}Token::Delimeter(d) => {0}Token::Delimeter(d),
+            {0}Token::EOF => {0}Token::EOF,
+        }}
+    }}
 }}\n
 ",
-        stx.name
+        stx.name,
+        open,
+        close
     )
     .unwrap();
     writeln!(
         f,
         "#[derive(Debug, Clone, PartialEq)]
-pub enum {0}Identifier {{
+pub enum {0}Identifier<'a> {{
     {0}Keyword({0}Keyword),
-    StringLiteral(String),
-    CharLiteral(String),
-    Integer(String, {0}Base),
-    Float(f64),
-    Unknown(u64),
+    StringLiteral(Cow<'a, str>),
+    CharLiteral(Cow<'a, str>),
+    Integer(Cow<'a, str>, {0}Base),
+    /// Source text and value
+    Float(Cow<'a, str>, f64),
+    Unknown(Ident),
 }}",
         stx.name
     )
@@ -65,6 +144,12 @@ pub enum {0}Operator {{",
         writeln!(f, "    {},", op).unwrap();
     }
     writeln!(f, "}}").unwrap();
+    let spelled = |name: &String| stx.tokens.get(name).map_or("", String::as_str);
+    as_str_codegen(
+        &mut f,
+        &format!("{}Operator", stx.name),
+        stx.operators.iter().map(|op| (op.as_str(), spelled(op))),
+    );
 
     writeln!(
         f,
@@ -84,6 +169,14 @@ pub enum {0}Delimeter {{",
 }}"
     )
     .unwrap();
+    as_str_codegen(
+        &mut f,
+        &format!("{}Delimeter", stx.name),
+        stx.delimiters
+            .iter()
+            .map(|del| (del.as_str(), spelled(del)))
+            .chain([("Whitespace", " "), ("NewLine", "\n")]),
+    );
 
     writeln!(
         f,
@@ -97,6 +190,13 @@ pub enum {0}Keyword {{",
         writeln!(f, "    {},", kw).unwrap();
     }
     writeln!(f, "}}").unwrap();
+    as_str_codegen(
+        &mut f,
+        &format!("{}Keyword", stx.name),
+        stx.keywords
+            .iter()
+            .map(|(kw, text)| (kw.as_str(), text.as_str())),
+    );
 
     writeln!(
         f,
@@ -114,9 +214,32 @@ pub enum {0}Base {{
 
     writeln!(
         f,
-        "#[derive(Hash, Debug, Clone, PartialEq, Eq, Default)]
+        "pub type {0}SpannedToken<'a> = Spanned<{0}Token<'a>>;
+
+#[derive(Hash, Debug, Clone, PartialEq, Eq, Default)]
 pub struct {0}TokenSet;",
         stx.name
     )
     .unwrap();
 }
+
+/// `as_str` giving the source text of each variant of `ty`
+fn as_str_codegen<'a>(f: &mut File, ty: &str, variants: impl Iterator<Item = (&'a str, &'a str)>) {
+    writeln!(
+        f,
+        "impl {ty} {{
+    pub fn as_str(self) -> &'static str {{
+        match self {{"
+    )
+    .unwrap();
+    for (variant, text) in variants {
+        writeln!(f, "            {ty}::{variant} => {text:?},").unwrap();
+    }
+    writeln!(
+        f,
+        "        }}
+    }}
+}}"
+    )
+    .unwrap();
+}
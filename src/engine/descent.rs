@@ -0,0 +1,149 @@
+//! Recursive Descent (engine/descent.rs)
+//!
+//! What generated recursive descent parsers stand on (see codegen/grammar.rs). A parser has a
+//! function per nonterminal that looks at the next token, picks the alternative it predicts and
+//! calls its way through it. `predictions` works out which tokens predict which production, the
+//! LL(1) sets, and reports a grammar where one token predicts two alternatives of a
+//! nonterminal: left recursion, or alternatives starting alike. `Descent` is the token cursor
+//! the functions share.
+//!
+//! Left recursion through an operator with a precedence (`E = E "+" E`) is fine though: a
+//! nonterminal with such productions parses its other ones as operands and then takes operators
+//! Pratt style, as long as they bind tighter than where it was called from. Precedence and
+//! associativity come out as they do in the LALR tables.
+//!
+//! Trees and errors are the ones the LALR driver makes (see engine/parser.rs), so a grammar
+//! parses into the same tree either way. Whitespace tokens are skipped and `EOF` is handled the
+//! same way too.
+use crate::engine::errors::{Conflict, ConflictKind, GrammarError, ParseError};
+use crate::engine::grammar::{END, First, Lowered, Symbol};
+use crate::engine::parser::{ParseTree, after};
+use crate::tokens::span::{Span, Spanned};
+use crate::tokens::token_traits::*;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Terminals that predict each production, or the conflicts that make the grammar not LL(1).
+/// Operator productions (see `Lowered::infix`) take no part in conflicts.
+pub fn predictions(grammar: &Lowered) -> Result<Vec<BTreeSet<usize>>, GrammarError> {
+    let first = First::new(grammar);
+    let mut follow = vec![BTreeSet::new(); grammar.nonterminals.len()];
+    follow[0].insert(0);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for p in &grammar.productions {
+            for (i, symbol) in p.rhs.iter().enumerate() {
+                let Symbol::Nonterminal(n) = *symbol else {
+                    continue;
+                };
+                let (mut after, nullable) = first.of(&p.rhs[i + 1..]);
+                if nullable {
+                    after.extend(&follow[p.lhs]);
+                }
+                let before = follow[n].len();
+                follow[n].extend(after);
+                changed |= follow[n].len() != before;
+            }
+        }
+    }
+
+    let mut predict = Vec::with_capacity(grammar.productions.len());
+    let mut conflicts: BTreeMap<(usize, usize), Conflict> = BTreeMap::new();
+    let mut seen: BTreeMap<(usize, usize), usize> = BTreeMap::new();
+    for (p, production) in grammar.productions.iter().enumerate() {
+        let (mut terminals, nullable) = first.of(&production.rhs);
+        if nullable {
+            terminals.extend(&follow[production.lhs]);
+        }
+        for &t in terminals.iter().filter(|_| grammar.infix(p).is_none()) {
+            let Some(&q) = seen.get(&(production.lhs, t)) else {
+                seen.insert((production.lhs, t), p);
+                continue;
+            };
+            let conflict = conflicts
+                .entry((production.lhs, t))
+                .or_insert_with(|| Conflict {
+                    kind: ConflictKind::Prediction,
+                    state: production.lhs,
+                    lookahead: grammar.terminal_names[t].clone(),
+                    items: vec![grammar.item(q, None)],
+                });
+            conflict.items.push(grammar.item(p, None));
+        }
+        predict.push(terminals);
+    }
+    if !conflicts.is_empty() {
+        return Err(GrammarError::Conflicts(conflicts.into_values().collect()));
+    }
+    Ok(predict)
+}
+
+/// The tokens a generated parser works through
+#[derive(Debug, Clone)]
+pub struct Descent<K> {
+    /// Taken as they are matched
+    tokens: Vec<Option<Spanned<K>>>,
+    at: usize,
+    /// Where the input ends
+    end: Span,
+}
+
+impl<K: Token> Descent<K> {
+    /// `eof` tells whether the grammar has an `EOF` terminal, it gets one at the end of the
+    /// tokens if they come without
+    pub fn new(tokens: impl IntoIterator<Item = Spanned<K>>, eof: bool) -> Self {
+        let mut tokens: Vec<_> = tokens
+            .into_iter()
+            .filter(|t| t.token.kind() != TokenKind::Whitespace && (eof || t.token != K::eof()))
+            .collect();
+        let end = tokens.last().map_or(Span::default(), after);
+        if eof && tokens.last().is_none_or(|t| t.token != K::eof()) {
+            tokens.push(Spanned::new(K::eof(), end));
+        }
+        Descent {
+            tokens: tokens.into_iter().map(Some).collect(),
+            at: 0,
+            end,
+        }
+    }
+
+    /// Whether the next token matches one of `terminals`, `$end` matching the end of input
+    pub fn at(&self, terminals: &[&str]) -> bool {
+        match self.tokens.get(self.at).and_then(Option::as_ref) {
+            Some(t) => terminals.contains(&t.token.terminal().as_ref()),
+            None => terminals.contains(&END),
+        }
+    }
+
+    /// Takes the next token if it matches `terminal`, the grammar writing it `name`
+    pub fn expect(&mut self, terminal: &str, name: &str) -> Result<ParseTree<K>, ParseError> {
+        if !self.at(&[terminal]) || self.at >= self.tokens.len() {
+            return Err(self.error(&[name]));
+        }
+        self.at += 1;
+        Ok(ParseTree::Token(
+            self.tokens[self.at - 1].take().expect("token taken once"),
+        ))
+    }
+
+    /// The next token is none of `expected`
+    pub fn error(&self, expected: &[&str]) -> ParseError {
+        let token = self.tokens.get(self.at).and_then(Option::as_ref);
+        let span = token.map_or(self.end, |t| t.span);
+        ParseError {
+            row: span.row,
+            column: span.column,
+            offset: span.start,
+            found: token.map(|t| t.token.spelling().into_owned()),
+            expected: expected.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    /// Fails unless every token was matched
+    pub fn finish(&self) -> Result<(), ParseError> {
+        match self.at < self.tokens.len() {
+            true => Err(self.error(&[END])),
+            false => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,441 @@
+//! Input Encodings (engine/encoding.rs)
+//!
+//! The lexer works on UTF-8, this turns other inputs into it. UTF-8, UTF-16LE/BE, Latin-1 and
+//! Windows-1252 are decoded, either as configured or detected: a byte order mark decides it,
+//! otherwise zero bytes in every other position mean UTF-16 and anything else is taken for
+//! UTF-8. A guessed UTF-8 that meets an invalid byte before any non-ASCII char is read as
+//! Windows-1252 from there on, the usual case of a Latin-1 file with an ASCII header. The two
+//! agree on ASCII, so nothing decoded before changes.
+//!
+//! Invalid input stops decoding with a `DecodeError`, unless decoding is lossy: then each
+//! invalid sequence becomes U+FFFD and an `InvalidEncoding` diagnostic at its place in the
+//! decoded text.
+use crate::engine::errors::{DecodeError, LexicalError, LexicalErrorKind};
+use crate::tokens::span::Span;
+use crate::tokens::trivia::advance;
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, Read};
+use std::str::FromStr;
+
+/// Bytes looked at before guessing an encoding without a byte order mark
+const SNIFF: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// ISO-8859-1, every byte is the code point of the same value
+    Latin1,
+    /// Latin-1 with printable chars in 0x80..0x9F
+    Windows1252,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Latin1 => "ISO-8859-1",
+            Encoding::Windows1252 => "windows-1252",
+        }
+    }
+
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8 => b"\xEF\xBB\xBF",
+            Encoding::Utf16Le => b"\xFF\xFE",
+            Encoding::Utf16Be => b"\xFE\xFF",
+            Encoding::Latin1 | Encoding::Windows1252 => b"",
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Takes the usual labels, case insensitive: `utf-8`, `utf-16le`, `latin-1`, `cp1252`, ...
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "utf-16le" | "utf16le" => Ok(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Ok(Encoding::Utf16Be),
+            "latin-1" | "latin1" | "iso-8859-1" | "iso8859-1" => Ok(Encoding::Latin1),
+            "windows-1252" | "cp1252" => Ok(Encoding::Windows1252),
+            _ => Err(format!("unknown encoding `{}`", s)),
+        }
+    }
+}
+
+/// What 0x80..=0x9F stand for in Windows-1252, the five it leaves undefined keep their Latin-1
+/// control char
+const WINDOWS_1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+
+/// The byte order mark `bytes` start with, and the encoding it stands for
+fn bom_of(bytes: &[u8]) -> Option<Encoding> {
+    [Encoding::Utf8, Encoding::Utf16Le, Encoding::Utf16Be]
+        .into_iter()
+        .find(|e| bytes.starts_with(e.bom()))
+}
+
+/// Guess for input without a byte order mark. ASCII in UTF-16 has a zero in every other byte,
+/// which text in the other encodings hardly ever has.
+fn guess(bytes: &[u8]) -> Encoding {
+    let sample = &bytes[..bytes.len().min(SNIFF) & !1];
+    let pairs = sample.len() / 2;
+    let zeros = |parity: usize| {
+        sample
+            .iter()
+            .skip(parity)
+            .step_by(2)
+            .filter(|&&b| b == 0)
+            .count()
+    };
+    let (even, odd) = (zeros(0), zeros(1));
+    match () {
+        _ if pairs == 0 => Encoding::Utf8,
+        _ if odd * 4 > pairs && even * 16 < pairs => Encoding::Utf16Le,
+        _ if even * 4 > pairs && odd * 16 < pairs => Encoding::Utf16Be,
+        _ => Encoding::Utf8,
+    }
+}
+
+/// What `decode` made of a whole input
+#[derive(Debug, Clone)]
+pub struct Decoded<'a> {
+    /// Borrowed when the input was UTF-8 with nothing to replace
+    pub text: Cow<'a, str>,
+    pub encoding: Encoding,
+    /// Replacements made in lossy mode
+    pub diagnostics: Vec<LexicalError>,
+}
+
+/// Decodes a whole input. `None` detects the encoding.
+pub fn decode(
+    bytes: &[u8],
+    encoding: Option<Encoding>,
+    lossy: bool,
+) -> Result<Decoded<'_>, DecodeError> {
+    let detected = encoding.unwrap_or_else(|| bom_of(bytes).unwrap_or_else(|| guess(bytes)));
+    if detected == Encoding::Utf8 {
+        let text = bytes.strip_prefix(Encoding::Utf8.bom()).unwrap_or(bytes);
+        if let Ok(text) = std::str::from_utf8(text) {
+            return Ok(Decoded {
+                text: Cow::Borrowed(text),
+                encoding: Encoding::Utf8,
+                diagnostics: Vec::new(),
+            });
+        }
+    }
+    let mut decoder = Decoder::new(encoding, lossy);
+    let mut text = String::with_capacity(bytes.len());
+    decoder.decode(bytes, true, &mut text)?;
+    Ok(Decoded {
+        text: Cow::Owned(text),
+        encoding: decoder.encoding().unwrap_or(detected),
+        diagnostics: decoder.take_diagnostics(),
+    })
+}
+
+/// Decodes input handed over in pieces into UTF-8. A char split between two pieces is decoded
+/// once the rest of it comes.
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    encoding: Option<Encoding>,
+    /// Detected rather than configured, a guessed UTF-8 may still turn out to be Windows-1252
+    guessed: bool,
+    /// The start of the input, and its byte order mark, was seen
+    started: bool,
+    /// Nothing but ASCII decoded so far
+    ascii: bool,
+    lossy: bool,
+    /// Input bytes used by earlier calls
+    consumed: usize,
+    /// Where `out[..mark]` ends in the decoded text, kept in lossy mode to place diagnostics
+    at: Span,
+    mark: usize,
+    /// `at` stopped before a `\r` that ended the last piece, the next one says whether it is
+    /// the start of a `\r\n`
+    cr: bool,
+    diagnostics: Vec<LexicalError>,
+}
+
+impl Decoder {
+    /// `None` detects the encoding from the start of the input
+    pub fn new(encoding: Option<Encoding>, lossy: bool) -> Self {
+        Decoder {
+            encoding,
+            guessed: encoding.is_none(),
+            started: false,
+            ascii: true,
+            lossy,
+            consumed: 0,
+            at: Span::default(),
+            mark: 0,
+            cr: false,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// The encoding in use, `None` until enough input was seen to detect it
+    pub fn encoding(&self) -> Option<Encoding> {
+        self.encoding.filter(|_| self.started)
+    }
+
+    /// Replacements made in lossy mode, positions are in the decoded text
+    pub fn diagnostics(&self) -> &[LexicalError] {
+        &self.diagnostics
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<LexicalError> {
+        std::mem::take(&mut self.diagnostics)
+    }
+
+    /// Decodes what it can of `input` onto `out` and returns how many bytes it used, the rest
+    /// being the start of a char still incomplete. `last` says no more input follows.
+    pub fn decode(
+        &mut self,
+        input: &[u8],
+        last: bool,
+        out: &mut String,
+    ) -> Result<usize, DecodeError> {
+        let mut used = 0;
+        if !self.started {
+            if !last && input.len() < SNIFF {
+                return Ok(0);
+            }
+            let bom = bom_of(input);
+            if self.encoding.is_none() {
+                self.encoding = Some(bom.unwrap_or_else(|| guess(input)));
+                self.guessed = bom.is_none();
+            }
+            if bom.is_some() && bom == self.encoding {
+                used = bom.map_or(0, |e| e.bom().len());
+            }
+            self.started = true;
+        }
+        self.mark = out.len();
+        let used = self.decode_as(input, used, last, out)?;
+        if self.lossy {
+            self.walk(&out[self.mark..], last);
+        }
+        self.consumed += used;
+        Ok(used)
+    }
+
+    /// Moves `at` over `text`. A `\r` ending it waits for the next piece, unless `settled` says
+    /// nothing that could be a `\n` comes after it.
+    fn walk(&mut self, text: &str, settled: bool) {
+        if std::mem::take(&mut self.cr) {
+            self.at.start += 1;
+            if text.starts_with('\n') {
+                self.at.column += 1;
+            } else {
+                self.at.row += 1;
+                self.at.column = 0;
+            }
+        }
+        match text.strip_suffix('\r') {
+            Some(text) if !settled => {
+                advance(&mut self.at, text);
+                self.cr = true;
+            }
+            _ => advance(&mut self.at, text),
+        }
+    }
+
+    fn decode_as(
+        &mut self,
+        input: &[u8],
+        used: usize,
+        last: bool,
+        out: &mut String,
+    ) -> Result<usize, DecodeError> {
+        match self.encoding.unwrap_or(Encoding::Utf8) {
+            Encoding::Utf8 => self.utf8(input, used, last, out),
+            e @ (Encoding::Utf16Le | Encoding::Utf16Be) => self.utf16(e, input, used, last, out),
+            Encoding::Latin1 => {
+                out.extend(input[used..].iter().map(|&b| b as char));
+                Ok(input.len())
+            }
+            Encoding::Windows1252 => {
+                out.extend(input[used..].iter().map(|&b| match b {
+                    0x80..=0x9F => WINDOWS_1252[b as usize - 0x80],
+                    _ => b as char,
+                }));
+                Ok(input.len())
+            }
+        }
+    }
+
+    fn utf8(
+        &mut self,
+        input: &[u8],
+        mut used: usize,
+        last: bool,
+        out: &mut String,
+    ) -> Result<usize, DecodeError> {
+        loop {
+            let rest = &input[used..];
+            let e = match std::str::from_utf8(rest) {
+                Ok(s) => {
+                    self.ascii &= s.is_ascii();
+                    out.push_str(s);
+                    return Ok(input.len());
+                }
+                Err(e) => e,
+            };
+            let (valid, after) = rest.split_at(e.valid_up_to());
+            let valid = std::str::from_utf8(valid).unwrap_or_default();
+            self.ascii &= valid.is_ascii();
+            out.push_str(valid);
+            used += valid.len();
+            let len = match e.error_len() {
+                None if !last => return Ok(used),
+                None => after.len(),
+                Some(len) => len,
+            };
+            if self.guessed && self.ascii {
+                // Latin-1 read as UTF-8 breaks at its first non-ASCII byte
+                self.encoding = Some(Encoding::Windows1252);
+                self.guessed = false;
+                return self.decode_as(input, used, last, out);
+            }
+            self.invalid(&after[..len], used, out)?;
+            used += len;
+        }
+    }
+
+    fn utf16(
+        &mut self,
+        encoding: Encoding,
+        input: &[u8],
+        used: usize,
+        last: bool,
+        out: &mut String,
+    ) -> Result<usize, DecodeError> {
+        let unit = |pair: &[u8]| match encoding {
+            Encoding::Utf16Be => u16::from_be_bytes([pair[0], pair[1]]),
+            _ => u16::from_le_bytes([pair[0], pair[1]]),
+        };
+        let mut end = used + (input.len() - used) / 2 * 2;
+        // a high surrogate at the end waits for its low half
+        if !last && end > used && (0xD800..0xDC00).contains(&unit(&input[end - 2..end])) {
+            end -= 2;
+        }
+        let mut at = used;
+        for c in char::decode_utf16(input[used..end].chunks_exact(2).map(unit)) {
+            match c {
+                Ok(c) => {
+                    self.ascii &= c.is_ascii();
+                    out.push(c);
+                    at += c.len_utf16() * 2;
+                }
+                Err(_) => {
+                    self.invalid(&input[at..at + 2], at, out)?;
+                    at += 2;
+                }
+            }
+        }
+        if last && end < input.len() {
+            self.invalid(&input[end..], end, out)?;
+            end = input.len();
+        }
+        Ok(end)
+    }
+
+    /// Replaces the invalid `bytes` found `offset` bytes into the current input, or refuses them
+    fn invalid(
+        &mut self,
+        bytes: &[u8],
+        offset: usize,
+        out: &mut String,
+    ) -> Result<(), DecodeError> {
+        let encoding = self.encoding.unwrap_or(Encoding::Utf8);
+        if !self.lossy {
+            return Err(DecodeError {
+                encoding,
+                offset: self.consumed + offset,
+                bytes: bytes.to_vec(),
+            });
+        }
+        self.walk(&out[self.mark..], true);
+        self.diagnostics.push(LexicalError {
+            row: self.at.row,
+            column: self.at.column,
+            offset: self.at.start,
+            kind: LexicalErrorKind::InvalidEncoding,
+            expected: Some(encoding.name().to_string()),
+            found: Some(bytes.iter().map(|b| format!("\\x{:02x}", b)).collect()),
+        });
+        out.push(char::REPLACEMENT_CHARACTER);
+        self.mark = out.len() - char::REPLACEMENT_CHARACTER.len_utf8();
+        Ok(())
+    }
+}
+
+/// A `Read` that yields another one's bytes decoded to UTF-8, for `tokenize_reader` and anything
+/// else that reads text. Refused input comes back as an `InvalidData` error wrapping the
+/// `DecodeError`.
+pub struct Transcode<R> {
+    inner: R,
+    decoder: Decoder,
+    /// Input read but not decoded yet
+    input: Vec<u8>,
+    out: String,
+    sent: usize,
+    done: bool,
+}
+
+impl<R: Read> Transcode<R> {
+    /// `None` detects the encoding from the start of `inner`
+    pub fn new(inner: R, encoding: Option<Encoding>, lossy: bool) -> Self {
+        Transcode {
+            inner,
+            decoder: Decoder::new(encoding, lossy),
+            input: Vec::new(),
+            out: String::new(),
+            sent: 0,
+            done: false,
+        }
+    }
+
+    pub fn decoder(&self) -> &Decoder {
+        &self.decoder
+    }
+}
+
+impl<R: Read> Read for Transcode<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.sent == self.out.len() && !self.done {
+            self.out.clear();
+            self.sent = 0;
+            let start = self.input.len();
+            self.input.resize(start + buf.len().max(SNIFF), 0);
+            let read = self.inner.read(&mut self.input[start..]);
+            self.input.truncate(start + *read.as_ref().unwrap_or(&0));
+            let last = read? == 0;
+            let used = self
+                .decoder
+                .decode(&self.input, last, &mut self.out)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.input.drain(..used);
+            self.done = last;
+        }
+        let n = buf.len().min(self.out.len() - self.sent);
+        buf[..n].copy_from_slice(&self.out.as_bytes()[self.sent..self.sent + n]);
+        self.sent += n;
+        Ok(n)
+    }
+}
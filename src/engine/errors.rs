@@ -1,12 +1,333 @@
-///! Error Definitions (engine/errors.rs)
-///!
-///! These are the errors the lexer will raise in the real time
-///! TODO: A Display trait for this is essential.
+//! Error Definitions (engine/errors.rs)
+//!
+//! These are the errors the lexer raises in real time. Lexing never stops on them, they are
+//! pushed to the State stacktrace and the caller reads them once tokenization finishes. Only a
+//! refusing sink or an exceeded limit stops a run, those come back from `tokenize`. Grammars
+//! that don't make a parser and input a parser rejects have errors of their own at the end.
+use crate::engine::encoding::Encoding;
+use std::fmt;
+use std::time::Duration;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LexicalErrorKind {
+    UnterminatedString,
+    UnterminatedChar,
+    EmptyChar,
+    UnterminatedBlockComment,
+    InvalidEscape,
+    UnexpectedChar,
+    /// A closing brace or paren with nothing open
+    UnmatchedDelimiter,
+    /// Bytes the input encoding has no char for, replaced by U+FFFD
+    InvalidEncoding,
+}
+
+impl fmt::Display for LexicalErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LexicalErrorKind::UnterminatedString => "unterminated string literal",
+            LexicalErrorKind::UnterminatedChar => "unterminated char literal",
+            LexicalErrorKind::EmptyChar => "empty char literal",
+            LexicalErrorKind::UnterminatedBlockComment => "unterminated block comment",
+            LexicalErrorKind::InvalidEscape => "invalid escape sequence",
+            LexicalErrorKind::UnexpectedChar => "unexpected character",
+            LexicalErrorKind::UnmatchedDelimiter => "unmatched closing delimiter",
+            LexicalErrorKind::InvalidEncoding => "invalid byte sequence",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexicalError {
+    pub row: usize,
+    pub column: usize,
+    /// Byte offset where the offending construct starts
+    pub offset: usize,
+    pub kind: LexicalErrorKind,
+    pub expected: Option<String>,
+    /// What was actually there, `None` meaning end of input
+    pub found: Option<String>,
+}
+
+/// Escapes control chars so a newline or tab shows up as `\n`/`\t` in the message
+fn visible(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_control() {
+                c.escape_default().to_string()
+            } else {
+                c.to_string()
+            }
+        })
+        .collect()
+}
+
+/// `row:column: kind, expected X, found Y`, rows and columns printed 1-based.
+impl fmt::Display for LexicalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.row + 1, self.column + 1, self.kind)?;
+        if let Some(expected) = &self.expected {
+            write!(f, ", expected `{}`", visible(expected))?;
+        }
+        match &self.found {
+            Some(found) => write!(f, ", found `{}`", visible(found)),
+            None => write!(f, ", found end of input"),
+        }
+    }
+}
+
+impl std::error::Error for LexicalError {}
+
+/// A token sink refusing a batch. Unlike lexical errors these stop the lexer, there is nowhere
+/// left to put the tokens.
+#[derive(Debug)]
+pub enum SinkError {
+    /// The receiving end of a channel sink was dropped
+    Disconnected,
+    Io(std::io::Error),
+    /// Raised by a callback or pipeline stage
+    Rejected(String),
+}
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SinkError::Disconnected => write!(f, "token sink disconnected"),
+            SinkError::Io(e) => write!(f, "token sink i/o error: {}", e),
+            SinkError::Rejected(why) => write!(f, "token sink rejected batch: {}", why),
+        }
+    }
+}
+
+impl std::error::Error for SinkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SinkError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for SinkError {
+    fn from(e: std::io::Error) -> Self {
+        SinkError::Io(e)
+    }
+}
+
+/// Input that is not valid in its encoding, when decoding is not lossy (see engine/encoding.rs)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    pub encoding: Encoding,
+    /// Byte offset of the invalid sequence in the input
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid {} at byte {}: `", self.encoding, self.offset)?;
+        for b in &self.bytes {
+            write!(f, "\\x{:02x}", b)?;
+        }
+        write!(f, "`")
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// A run stopped by one of its `Limits` (see engine/limits.rs). Rows and columns are where the
+/// offending lexeme starts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitError {
+    TooManyTokens(usize),
+    TooDeep {
+        limit: usize,
+        row: usize,
+        column: usize,
+    },
+    LiteralTooLong {
+        limit: usize,
+        row: usize,
+        column: usize,
+    },
+    Timeout(Duration),
+    Cancelled,
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::TooManyTokens(limit) => write!(f, "more than {} tokens", limit),
+            LimitError::TooDeep { limit, row, column } => {
+                write!(
+                    f,
+                    "{}:{}: nesting deeper than {}",
+                    row + 1,
+                    column + 1,
+                    limit
+                )
+            }
+            LimitError::LiteralTooLong { limit, row, column } => {
+                write!(
+                    f,
+                    "{}:{}: lexeme longer than {} bytes",
+                    row + 1,
+                    column + 1,
+                    limit
+                )
+            }
+            LimitError::Timeout(after) => write!(f, "lexing took longer than {:?}", after),
+            LimitError::Cancelled => write!(f, "lexing cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+/// Why `tokenize` stopped before the end of its input
 #[derive(Debug)]
-pub struct LexicalError<T> {
+pub enum LexError {
+    Sink(SinkError),
+    Limit(LimitError),
+    /// Reading the input failed, or it was not UTF-8
+    Input(std::io::Error),
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::Sink(e) => e.fmt(f),
+            LexError::Limit(e) => e.fmt(f),
+            LexError::Input(e) => write!(f, "input error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LexError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LexError::Sink(e) => Some(e),
+            LexError::Limit(e) => Some(e),
+            LexError::Input(e) => Some(e),
+        }
+    }
+}
+
+impl From<SinkError> for LexError {
+    fn from(e: SinkError) -> Self {
+        LexError::Sink(e)
+    }
+}
+
+impl From<LimitError> for LexError {
+    fn from(e: LimitError) -> Self {
+        LexError::Limit(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConflictKind {
+    ShiftReduce,
+    ReduceReduce,
+    /// One token predicts two alternatives of a recursive descent parser
+    Prediction,
+}
+
+/// Two actions the tables would need for one state and lookahead. `items` are the offending
+/// productions, with a dot where the parser stands in them. A prediction conflict has two
+/// alternatives of the nonterminal `state` instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub kind: ConflictKind,
+    pub state: usize,
+    pub lookahead: String,
+    pub items: Vec<String>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            ConflictKind::ShiftReduce => "shift/reduce",
+            ConflictKind::ReduceReduce => "reduce/reduce",
+            ConflictKind::Prediction => {
+                return write!(
+                    f,
+                    "{} predicts more than one alternative: {}",
+                    self.lookahead,
+                    self.items.join(" | ")
+                );
+            }
+        };
+        write!(
+            f,
+            "state {}: {} conflict on {}: {}",
+            self.state,
+            kind,
+            self.lookahead,
+            self.items.join(" | ")
+        )
+    }
+}
+
+/// A grammar that doesn't make a parser (see engine/grammar.rs)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrammarError {
+    /// A rule that doesn't parse, `line` counted from 1
+    Syntax {
+        line: usize,
+        text: String,
+    },
+    /// No rules at all
+    Empty,
+    Conflicts(Vec<Conflict>),
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrammarError::Syntax { line, text } => {
+                write!(f, "grammar line {}: malformed rule `{}`", line, text)
+            }
+            GrammarError::Empty => write!(f, "grammar has no rules"),
+            GrammarError::Conflicts(conflicts) => {
+                match conflicts.first().map(|c| c.kind) {
+                    Some(ConflictKind::Prediction) => write!(f, "grammar is not LL(1)")?,
+                    _ => write!(f, "grammar is not LALR(1)")?,
+                }
+                for conflict in conflicts {
+                    write!(f, "\n  {}", conflict)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for GrammarError {}
+
+/// A token the parser has no action for, `found` being `None` at the end of input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
     pub row: usize,
     pub column: usize,
-    pub expected: T,
-    pub context: Option<Vec<T>>,
+    pub offset: usize,
+    pub found: Option<String>,
+    /// Terminals the parser could have gone on with
+    pub expected: Vec<String>,
 }
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: ", self.row + 1, self.column + 1)?;
+        match &self.found {
+            Some(found) => write!(f, "unexpected `{}`", visible(found))?,
+            None => write!(f, "unexpected end of input")?,
+        }
+        if !self.expected.is_empty() {
+            write!(f, ", expected one of {}", self.expected.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
@@ -0,0 +1,482 @@
+//! Grammars (engine/grammar.rs)
+//!
+//! The `[grammar]` section of a spec, one rule per line:
+//!
+//! ```text
+//! Body = [[Entry, Body], _];
+//! Entry = [Key, "=", Value, Semicolon];
+//! Value = [List];
+//! ```
+//!
+//! A rule is a sequence. A list nested in it is an optional group, `_` matches nothing and a
+//! name with several rules has that many alternatives. Names with rules of their own are
+//! nonterminals, the first rule's is the start symbol. Any other name is a terminal: a token
+//! from `[tokens]` matches by its spelling, like a quoted item does, the rest are classes
+//! `Token::terminal` puts tokens in (`Identifier`, `StringLiteral`, `EOF`).
+//!
+//! Operators get precedence from the `[precedence]` section, one level per line and each
+//! binding tighter than the one before: `left = Plus, Minus;`, `right = "=";`,
+//! `nonassoc = Eq;`. A production has the precedence of its last terminal that has one.
+//! Grammar text takes the levels as lines too, so `left`, `right` and `nonassoc` make no rule
+//! names there.
+//!
+//! `Grammar::lower` numbers the symbols and productions for table construction (see
+//! engine/parser.rs). Each optional group becomes a nonterminal of its own with an empty
+//! alternative, inlined into its parent's node in the parse tree.
+use crate::engine::errors::GrammarError;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+/// One item of a rule as written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+    Name(String),
+    /// A token spelled this way
+    Quoted(String),
+    /// Optional sequence
+    Group(Vec<Item>),
+    /// `_`
+    Empty,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub name: String,
+    pub items: Vec<Item>,
+    /// Line of the rule in the spec, from 1
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Assoc {
+    Left,
+    Right,
+    NonAssoc,
+}
+
+/// Operators sharing a precedence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Level {
+    pub assoc: Assoc,
+    /// Names and quoted spellings
+    pub operators: Vec<Item>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Grammar {
+    pub rules: Vec<Rule>,
+    /// Spelling of the tokens named in `[tokens]`
+    pub tokens: HashMap<String, String>,
+    /// Loosest first
+    pub precedence: Vec<Level>,
+}
+
+impl Grammar {
+    /// Parses rule and precedence lines, skipping blank ones and `#` comments
+    pub fn parse(src: &str) -> Result<Grammar, GrammarError> {
+        let mut grammar = Grammar::default();
+        for (i, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=').map(|(name, _)| name.trim()) {
+                Some("left" | "right" | "nonassoc") => grammar.push_precedence(i + 1, line)?,
+                _ => grammar.push_rule(i + 1, line)?,
+            }
+        }
+        Ok(grammar)
+    }
+
+    /// Adds the rule `Name = [...];` found on `line`
+    pub fn push_rule(&mut self, line: usize, text: &str) -> Result<(), GrammarError> {
+        let (name, items) = split(line, text, true)?;
+        self.rules.push(Rule {
+            name: name.to_string(),
+            items,
+            line,
+        });
+        Ok(())
+    }
+
+    /// Adds the level `left = Plus, "-";` found on `line`, tighter than the ones before
+    pub fn push_precedence(&mut self, line: usize, text: &str) -> Result<(), GrammarError> {
+        let (assoc, operators) = split(line, text, false)?;
+        let assoc = match assoc {
+            "left" => Assoc::Left,
+            "right" => Assoc::Right,
+            "nonassoc" => Assoc::NonAssoc,
+            _ => return Err(malformed(line, text)),
+        };
+        if operators
+            .iter()
+            .any(|o| !matches!(o, Item::Name(_) | Item::Quoted(_)))
+        {
+            return Err(malformed(line, text));
+        }
+        self.precedence.push(Level {
+            assoc,
+            operators,
+            line,
+        });
+        Ok(())
+    }
+
+    /// Numbers the symbols and productions, production 0 being `$accept = [Start]`
+    pub fn lower(&self) -> Result<Lowered, GrammarError> {
+        let start = self.rules.first().ok_or(GrammarError::Empty)?;
+        let mut lowered = Lowered {
+            terminals: vec![END.to_string()],
+            terminal_names: vec![END.to_string()],
+            nonterminals: vec!["$accept".to_string()],
+            productions: Vec::new(),
+            precedence: vec![None],
+        };
+        let mut nonterminals = HashMap::new();
+        for rule in &self.rules {
+            nonterminals.entry(rule.name.as_str()).or_insert_with(|| {
+                lowered.nonterminals.push(rule.name.clone());
+                lowered.nonterminals.len() - 1
+            });
+        }
+        lowered.productions.push(Production {
+            lhs: 0,
+            rhs: vec![Symbol::Nonterminal(nonterminals[start.name.as_str()])],
+            inline: false,
+        });
+        let mut lowering = Lowering {
+            grammar: self,
+            nonterminals: &nonterminals,
+            terminals: HashMap::from([(END.to_string(), 0)]),
+            groups: HashMap::new(),
+            lowered: &mut lowered,
+        };
+        for rule in &self.rules {
+            let lhs = nonterminals[rule.name.as_str()];
+            let rhs = lowering.sequence(&rule.name, &rule.items);
+            lowering.lowered.productions.push(Production {
+                lhs,
+                rhs,
+                inline: false,
+            });
+        }
+        for (level, declared) in self.precedence.iter().enumerate() {
+            for operator in &declared.operators {
+                let matches = match operator {
+                    Item::Quoted(text) => text.as_str(),
+                    Item::Name(name) => spelling(&self.tokens, name),
+                    _ => continue,
+                };
+                // operators no rule uses have nothing to resolve
+                if let Some(&t) = lowering.terminals.get(matches) {
+                    lowering.lowered.precedence[t] = Some((level + 1, declared.assoc));
+                }
+            }
+        }
+        Ok(lowered)
+    }
+}
+
+/// What the terminal `name` matches: its spelling if `[tokens]` has one, else the name
+fn spelling<'n>(tokens: &'n HashMap<String, String>, name: &'n str) -> &'n str {
+    tokens
+        .get(name)
+        .map(String::as_str)
+        .filter(|spelling| !spelling.is_empty())
+        .unwrap_or(name)
+}
+
+fn malformed(line: usize, text: &str) -> GrammarError {
+    GrammarError::Syntax {
+        line,
+        text: text.to_string(),
+    }
+}
+
+/// Name and items of `Name = [items];`, the brackets optional unless `bracketed`
+fn split(line: usize, text: &str, bracketed: bool) -> Result<(&str, Vec<Item>), GrammarError> {
+    let (name, body) = text.split_once('=').ok_or_else(|| malformed(line, text))?;
+    let name = name.trim();
+    let body = body.trim();
+    let body = body.strip_suffix(';').unwrap_or(body).trim_end();
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(malformed(line, text));
+    }
+    let items = match body.strip_prefix('[') {
+        Some(inner) => {
+            let mut chars = inner.chars().peekable();
+            let items = items(&mut chars);
+            items.filter(|_| chars.next().is_none())
+        }
+        None if !bracketed => items(&mut body.chars().chain([']']).peekable()),
+        None => None,
+    };
+    Ok((name, items.ok_or_else(|| malformed(line, text))?))
+}
+
+/// `left = [Plus, "-"];` as grammar text writes it
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let assoc = match self.assoc {
+            Assoc::Left => "left",
+            Assoc::Right => "right",
+            Assoc::NonAssoc => "nonassoc",
+        };
+        write!(f, "{} = {};", assoc, Item::Group(self.operators.clone()))
+    }
+}
+
+/// `Name = [items];` as a spec writes it
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = {};", self.name, Item::Group(self.items.clone()))
+    }
+}
+
+/// Items up to the `]` closing the list `chars` is in
+fn items(chars: &mut std::iter::Peekable<impl Iterator<Item = char>>) -> Option<Vec<Item>> {
+    let mut items = Vec::new();
+    loop {
+        match chars.next()? {
+            ']' => return Some(items),
+            c if c == ',' || c.is_whitespace() => {}
+            '[' => items.push(Item::Group(self::items(chars)?)),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => text.push(chars.next()?),
+                        c => text.push(c),
+                    }
+                }
+                items.push(Item::Quoted(text));
+            }
+            c => {
+                let mut name = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                items.push(match name.as_str() {
+                    "_" => Item::Empty,
+                    _ if name.chars().all(|c| c.is_alphanumeric() || c == '_') => Item::Name(name),
+                    _ => return None,
+                });
+            }
+        }
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Item::Name(name) => f.write_str(name),
+            Item::Quoted(text) => write!(f, "{:?}", text),
+            Item::Group(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    item.fmt(f)?;
+                }
+                f.write_str("]")
+            }
+            Item::Empty => f.write_str("_"),
+        }
+    }
+}
+
+/// Terminal 0, what the parser sees once the tokens run out
+pub const END: &str = "$end";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Symbol {
+    Terminal(usize),
+    Nonterminal(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Production {
+    pub lhs: usize,
+    pub rhs: Vec<Symbol>,
+    /// Made up for an optional group, its children go to the parent's node
+    pub inline: bool,
+}
+
+/// A grammar with numbered symbols
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lowered {
+    /// What each terminal matches, compared with `Token::terminal`
+    pub terminals: Vec<String>,
+    /// How the grammar writes each terminal
+    pub terminal_names: Vec<String>,
+    pub nonterminals: Vec<String>,
+    pub productions: Vec<Production>,
+    /// Level, from 1, and associativity of each terminal that has them
+    pub precedence: Vec<Option<(usize, Assoc)>>,
+}
+
+impl Lowered {
+    pub fn name(&self, symbol: Symbol) -> &str {
+        match symbol {
+            Symbol::Terminal(t) => &self.terminal_names[t],
+            Symbol::Nonterminal(n) => &self.nonterminals[n],
+        }
+    }
+
+    /// Precedence of the last terminal of `production` that has one
+    pub fn production_precedence(&self, production: usize) -> Option<(usize, Assoc)> {
+        self.productions[production]
+            .rhs
+            .iter()
+            .rev()
+            .find_map(|symbol| match *symbol {
+                Symbol::Terminal(t) => self.precedence[t],
+                Symbol::Nonterminal(_) => None,
+            })
+    }
+
+    /// The operator of `production` if it is `E = E op ...` and `op` has a precedence
+    pub fn infix(&self, production: usize) -> Option<usize> {
+        let p = &self.productions[production];
+        match p.rhs.as_slice() {
+            [Symbol::Nonterminal(n), Symbol::Terminal(t), ..]
+                if *n == p.lhs && self.precedence[*t].is_some() =>
+            {
+                Some(*t)
+            }
+            _ => None,
+        }
+    }
+
+    /// `Lhs = a "b" . C`, the dot left out if `dot` is `None`
+    pub fn item(&self, production: usize, dot: Option<usize>) -> String {
+        let production = &self.productions[production];
+        let mut out = format!("{} =", self.nonterminals[production.lhs]);
+        for (i, symbol) in production.rhs.iter().enumerate() {
+            if dot == Some(i) {
+                out.push_str(" .");
+            }
+            out.push(' ');
+            out.push_str(self.name(*symbol));
+        }
+        if dot == Some(production.rhs.len()) {
+            out.push_str(" .");
+        }
+        out
+    }
+}
+
+/// What each nonterminal can start with and whether it can match nothing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct First {
+    pub nullable: Vec<bool>,
+    pub first: Vec<BTreeSet<usize>>,
+}
+
+impl First {
+    pub fn new(grammar: &Lowered) -> Self {
+        let mut sets = First {
+            nullable: vec![false; grammar.nonterminals.len()],
+            first: vec![BTreeSet::new(); grammar.nonterminals.len()],
+        };
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for p in &grammar.productions {
+                let (first, nullable) = sets.of(&p.rhs);
+                if nullable && !sets.nullable[p.lhs] {
+                    sets.nullable[p.lhs] = true;
+                    changed = true;
+                }
+                let before = sets.first[p.lhs].len();
+                sets.first[p.lhs].extend(first);
+                changed |= sets.first[p.lhs].len() != before;
+            }
+        }
+        sets
+    }
+
+    /// Terminals `symbols` can start with, and whether they can match nothing
+    pub fn of(&self, symbols: &[Symbol]) -> (BTreeSet<usize>, bool) {
+        let mut first = BTreeSet::new();
+        for symbol in symbols {
+            match *symbol {
+                Symbol::Terminal(t) => {
+                    first.insert(t);
+                    return (first, false);
+                }
+                Symbol::Nonterminal(n) => {
+                    first.extend(&self.first[n]);
+                    if !self.nullable[n] {
+                        return (first, false);
+                    }
+                }
+            }
+        }
+        (first, true)
+    }
+}
+
+struct Lowering<'g> {
+    grammar: &'g Grammar,
+    nonterminals: &'g HashMap<&'g str, usize>,
+    /// Terminal for each thing a token may match
+    terminals: HashMap<String, usize>,
+    /// Optional groups made so far per rule name
+    groups: HashMap<String, usize>,
+    lowered: &'g mut Lowered,
+}
+
+impl Lowering<'_> {
+    fn sequence(&mut self, rule: &str, items: &[Item]) -> Vec<Symbol> {
+        let mut rhs = Vec::new();
+        for item in items {
+            match item {
+                Item::Empty => {}
+                Item::Name(name) => match self.nonterminals.get(name.as_str()) {
+                    Some(&n) => rhs.push(Symbol::Nonterminal(n)),
+                    None => {
+                        let matches = spelling(&self.grammar.tokens, name);
+                        rhs.push(self.terminal(matches, name.clone()));
+                    }
+                },
+                Item::Quoted(text) => rhs.push(self.terminal(text, format!("{:?}", text))),
+                Item::Group(items) => {
+                    let count = self.groups.entry(rule.to_string()).or_default();
+                    *count += 1;
+                    let name = format!("{}#{}", rule, count);
+                    self.lowered.nonterminals.push(name.clone());
+                    let lhs = self.lowered.nonterminals.len() - 1;
+                    let inner = self.sequence(&name, items);
+                    for rhs in [inner, Vec::new()] {
+                        self.lowered.productions.push(Production {
+                            lhs,
+                            rhs,
+                            inline: true,
+                        });
+                    }
+                    rhs.push(Symbol::Nonterminal(lhs));
+                }
+            }
+        }
+        rhs
+    }
+
+    fn terminal(&mut self, matches: &str, name: String) -> Symbol {
+        let lowered = &mut *self.lowered;
+        Symbol::Terminal(
+            *self
+                .terminals
+                .entry(matches.to_string())
+                .or_insert_with(|| {
+                    lowered.terminals.push(matches.to_string());
+                    lowered.terminal_names.push(name);
+                    lowered.precedence.push(None);
+                    lowered.terminals.len() - 1
+                }),
+        )
+    }
+}
@@ -0,0 +1,184 @@
+//! Incremental Re-lexing (engine/incremental.rs)
+//!
+//! A Document keeps the text of an open file together with its tokens and the State checkpoint
+//! the lexer had after each one. An edit re-lexes from the last token that ends before the
+//! changed bytes, and stops as soon as it lexes a token the old run also had at the same place
+//! and left in the same State: from there on both runs see the same text in the same state, so
+//! the old tokens are reused, just moved by the size of the edit. The state is compared after
+//! the token and not before it, lexemes that produce no token (`import`) can change it too.
+//!
+//! Imports are not expanded in a Document, tokens from another file have no place in its spans.
+use crate::engine::errors::LexicalError;
+use crate::engine::lexer::Lexer;
+use crate::engine::states::Checkpoint;
+use crate::tokens::lines::LineIndex;
+use crate::tokens::span::{Span, Spanned};
+use crate::tokens::token_traits::*;
+use crate::tokens::trivia::advance;
+use std::ops::Range;
+
+#[derive(Debug, Clone)]
+pub struct Document<T: Lexable> {
+    text: String,
+    tokens: Vec<Spanned<T::Token<'static>>>,
+    /// State right after each token, `checkpoints[i]` belongs to `tokens[i]`
+    checkpoints: Vec<Checkpoint>,
+    diagnostics: Vec<LexicalError>,
+    /// Kept up to date by every edit, for editors sending positions instead of offsets
+    lines: LineIndex,
+}
+
+impl<T> Document<T>
+where
+    T: Lexable + Resolvable + Delimeted + Clone + Eq + Default,
+{
+    pub fn new(text: impl Into<String>) -> Self {
+        let text = text.into();
+        let mut doc = Document {
+            lines: LineIndex::new(&text),
+            text,
+            tokens: Vec::new(),
+            checkpoints: Vec::new(),
+            diagnostics: Vec::new(),
+        };
+        doc.edit(0..0, "");
+        doc
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn tokens(&self) -> &[Spanned<T::Token<'static>>] {
+        &self.tokens
+    }
+
+    pub fn diagnostics(&self) -> &[LexicalError] {
+        &self.diagnostics
+    }
+
+    pub fn lines(&self) -> &LineIndex {
+        &self.lines
+    }
+
+    /// Replaces the bytes in `range` with `replacement` and brings the tokens up to date.
+    /// Returns the indices of the tokens that were lexed again, every token outside of it was
+    /// reused. Panics if `range` does not fall on char boundaries, like `String::replace_range`.
+    pub fn edit(&mut self, range: Range<usize>, replacement: &str) -> Range<usize> {
+        self.text.replace_range(range.clone(), replacement);
+        self.lines
+            .edit(&self.text, range.clone(), replacement.len());
+        let delta = replacement.len() as isize - range.len() as isize;
+        let edit_end = range.start + replacement.len();
+
+        // restart after the last token that ends strictly before the edit, the byte that
+        // stopped it is still there, unless the state after it looked further ahead
+        let mut first = self.tokens.partition_point(|t| t.span.end < range.start);
+        while first > 0 && self.checkpoints[first - 1].looks_ahead() {
+            first -= 1;
+        }
+        let (at, state) = match first.checked_sub(1) {
+            Some(prev) => (self.end_of(prev), self.checkpoints[prev].clone()),
+            None => (Span::default(), Checkpoint::default()),
+        };
+
+        let mut lexer = Lexer::<T>::from_str(&self.text);
+        lexer.resolve_imports = false;
+        lexer.batch_size = 1;
+        lexer.resume(at, &state);
+
+        let mut fresh = Vec::new();
+        let mut fresh_checkpoints = Vec::new();
+        let mut old = first;
+        let mut resync = None;
+        while let Some(token) = lexer.next() {
+            let span = token.span;
+            let after = lexer.state.checkpoint();
+            fresh.push(token.map(T::into_owned));
+            fresh_checkpoints.push(after);
+            if span.start < edit_end {
+                continue;
+            }
+            let old_start = (span.start as isize - delta) as usize;
+            while old < self.tokens.len() && self.tokens[old].span.start < old_start {
+                old += 1;
+            }
+            if let Some(was) = self.tokens.get(old)
+                && was.span.start == old_start
+                && was.span.len() == span.len()
+                && fresh_checkpoints.last() == Some(&self.checkpoints[old])
+                && fresh.last().is_some_and(|t| t.token == was.token)
+            {
+                resync = Some((old, span));
+                break;
+            }
+        }
+
+        let errors = lexer.take_diagnostics();
+        // old errors before the restart point stay, and so do the ones of an empty token (what
+        // is left of an unterminated template) sitting right at it
+        let empty_before = first > 0 && self.tokens[first - 1].span.is_empty();
+        let kept = |e: &LexicalError| e.offset < at.start || (empty_before && e.offset == at.start);
+        let relexed = first..first + fresh.len();
+        match resync {
+            // the old token at `old` was lexed again, everything after it is reused
+            Some((old, now)) => {
+                let was = self.tokens[old].span;
+                let shift = |x: usize, by: isize| (x as isize + by) as usize;
+                let moved = |s: &mut Span| {
+                    if s.row == was.row {
+                        s.column = shift(s.column, now.column as isize - was.column as isize);
+                    }
+                    s.row = shift(s.row, now.row as isize - was.row as isize);
+                    s.start = shift(s.start, delta);
+                    s.end = shift(s.end, delta);
+                };
+                for t in &mut self.tokens[old + 1..] {
+                    moved(&mut t.span);
+                }
+                self.tokens.splice(first..old + 1, fresh);
+                self.checkpoints.splice(first..old + 1, fresh_checkpoints);
+
+                // the token resynced on was lexed from a state that may differ, its errors come
+                // from the new run (an empty one has them at its start)
+                let old_errors = std::mem::take(&mut self.diagnostics);
+                self.diagnostics = old_errors.iter().filter(|e| kept(e)).cloned().collect();
+                self.diagnostics.extend(
+                    errors
+                        .into_iter()
+                        .filter(|e| e.offset < now.end.max(now.start + 1)),
+                );
+                let reused = was.end.max(was.start + 1);
+                for mut e in old_errors.into_iter().filter(|e| e.offset >= reused) {
+                    let mut s = Span {
+                        start: e.offset,
+                        end: e.offset,
+                        row: e.row,
+                        column: e.column,
+                    };
+                    moved(&mut s);
+                    (e.offset, e.row, e.column) = (s.start, s.row, s.column);
+                    self.diagnostics.push(e);
+                }
+            }
+            None => {
+                self.tokens.truncate(first);
+                self.tokens.extend(fresh);
+                self.checkpoints.truncate(first);
+                self.checkpoints.extend(fresh_checkpoints);
+                self.diagnostics.retain(kept);
+                self.diagnostics.extend(errors);
+            }
+        }
+        relexed
+    }
+
+    /// Position right after token `i`
+    fn end_of(&self, i: usize) -> Span {
+        let span = self.tokens[i].span;
+        let mut at = span;
+        advance(&mut at, &self.text[span.start..span.end]);
+        at.end = at.start;
+        at
+    }
+}
@@ -0,0 +1,260 @@
+//! Identifier interning (engine/interner.rs)
+//!
+//! `normalize` turns a name into a hash that is cheap to compare and says nothing about the name
+//! itself. The interner keeps the names: every distinct one gets a `Symbol`, a `u32` that maps
+//! back to its text. There is a single interner per process, shared by every thread and by the
+//! lexers of imported files, so a name is the same symbol wherever it was seen.
+//!
+//! Names are never freed, so the process interner stops taking new ones at `CAPACITY` bytes.
+//! An identifier first seen after that has its hash but no symbol, and no name to give back.
+use crate::engine::normalize::normalize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fmt;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::sync::{OnceLock, RwLock};
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Shards keep threads lexing different names from queueing on the same lock
+const SHARDS: usize = 16;
+const SHARD_BITS: u32 = SHARDS.trailing_zeros();
+/// Names a shard can number, the rest of a symbol's bits
+const SHARD_NAMES: usize = 1 << (u32::BITS - SHARD_BITS);
+
+/// Bytes of names the process interner takes before it is full
+pub const CAPACITY: usize = 1 << 28;
+
+/// Names a thread remembers having interned before it starts over
+const SEEN_NAMES: usize = 1 << 12;
+
+/// A name in the interner, resolving it is an index into its shard
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    /// Interns `name` in the process interner, `None` once it is full
+    pub fn intern(name: &str) -> Option<Self> {
+        Interner::global().intern(name)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        Interner::global().resolve(self)
+    }
+
+    pub fn id(self) -> u32 {
+        self.0
+    }
+
+    /// `None` when `index` doesn't fit next to the shard
+    fn new(shard: usize, index: usize) -> Option<Self> {
+        (index < SHARD_NAMES).then_some(Symbol(((index as u32) << SHARD_BITS) | shard as u32))
+    }
+
+    fn shard(self) -> usize {
+        self.0 as usize & (SHARDS - 1)
+    }
+
+    fn index(self) -> usize {
+        (self.0 >> SHARD_BITS) as usize
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Symbol({}, {:?})", self.0, self.as_str())
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An identifier as tokens carry it: `hash` is the `normalize` of the name and the brace level
+/// it was found at, `symbol` gets the name back. Equal identifiers agree on both, two names
+/// sharing a hash still differ by their symbol.
+#[derive(Clone, Copy)]
+pub struct Ident {
+    pub hash: u64,
+    /// `None` for a name the process interner had no room for
+    pub symbol: Option<Symbol>,
+}
+
+impl Ident {
+    pub fn new(name: &str, brace_level: usize) -> Self {
+        Ident {
+            hash: normalize(name.as_bytes(), brace_level),
+            symbol: Symbol::intern(name),
+        }
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        self.symbol.map(Symbol::as_str)
+    }
+}
+
+impl PartialEq for Ident {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash && self.symbol == other.symbol
+    }
+}
+
+impl Eq for Ident {}
+
+impl Hash for Ident {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.hash);
+    }
+}
+
+impl fmt::Debug for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Ident({:?}, {:#018x})",
+            self.name().unwrap_or_default(),
+            self.hash
+        )
+    }
+}
+
+/// Keys in a shard are already xxh3 hashes of the name, there is nothing left to mix
+#[derive(Default)]
+struct Prehashed(u64);
+
+impl Hasher for Prehashed {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, _: &[u8]) {
+        unreachable!("shard keys are u64")
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = n;
+    }
+}
+
+type PrehashedMap<V> = HashMap<u64, V, BuildHasherDefault<Prehashed>>;
+
+thread_local! {
+    /// Names this thread has interned lately, a hit never touches the shard locks
+    static SEEN: RefCell<PrehashedMap<(&'static str, Symbol)>> = RefCell::default();
+}
+
+#[derive(Default)]
+struct Shard {
+    /// Name hash to index, the name at the index is checked since two names can share a hash
+    ids: PrehashedMap<u32>,
+    /// Names whose hash was already taken by another name
+    collided: HashMap<&'static str, u32>,
+    names: Vec<&'static str>,
+    /// Bytes over `names`
+    bytes: usize,
+}
+
+impl Shard {
+    fn get(&self, hash: u64, name: &str) -> Option<u32> {
+        match self.ids.get(&hash) {
+            Some(&i) if self.names[i as usize] == name => Some(i),
+            Some(_) => self.collided.get(name).copied(),
+            None => None,
+        }
+    }
+}
+
+pub struct Interner {
+    shards: [RwLock<Shard>; SHARDS],
+    /// Bytes of names each shard may hold
+    shard_bytes: usize,
+}
+
+impl Interner {
+    /// The interner of this process, names are never freed
+    pub fn global() -> &'static Interner {
+        static GLOBAL: OnceLock<Interner> = OnceLock::new();
+        GLOBAL.get_or_init(|| Interner::with_capacity(CAPACITY))
+    }
+
+    /// An interner that takes names until they fill `bytes`, about. Its symbols resolve only
+    /// through `resolve` on it.
+    pub fn with_capacity(bytes: usize) -> Self {
+        Interner {
+            shards: Default::default(),
+            shard_bytes: bytes.div_ceil(SHARDS),
+        }
+    }
+
+    /// `None` for a new name that doesn't fit any more
+    pub fn intern(&self, name: &str) -> Option<Symbol> {
+        let hash = xxh3_64(name.as_bytes());
+        let global = std::ptr::eq(self, Interner::global());
+        if global
+            && let Some(symbol) = SEEN.with_borrow(|seen| match seen.get(&hash) {
+                Some(&(known, symbol)) if known == name => Some(symbol),
+                _ => None,
+            })
+        {
+            return Some(symbol);
+        }
+        let (name, symbol) = self.intern_shared(hash, name)?;
+        if global {
+            SEEN.with_borrow_mut(|seen| {
+                if seen.len() >= SEEN_NAMES {
+                    seen.clear();
+                }
+                seen.insert(hash, (name, symbol))
+            });
+        }
+        Some(symbol)
+    }
+
+    fn intern_shared(&self, hash: u64, name: &str) -> Option<(&'static str, Symbol)> {
+        let shard = (hash >> 32) as usize & (SHARDS - 1);
+        {
+            let table = self.shards[shard].read().unwrap();
+            if let Some(i) = table.get(hash, name) {
+                return Some((table.names[i as usize], Symbol::new(shard, i as usize)?));
+            }
+        }
+        let mut guard = self.shards[shard].write().unwrap();
+        // another thread may have added it between the two locks
+        if let Some(i) = guard.get(hash, name) {
+            return Some((guard.names[i as usize], Symbol::new(shard, i as usize)?));
+        }
+        if guard.bytes + name.len() > self.shard_bytes {
+            return None;
+        }
+        let index = guard.names.len();
+        let symbol = Symbol::new(shard, index)?;
+        let name: &'static str = Box::leak(name.into());
+        guard.names.push(name);
+        guard.bytes += name.len();
+        let table = &mut *guard;
+        if let Entry::Vacant(slot) = table.ids.entry(hash) {
+            slot.insert(index as u32);
+        } else {
+            table.collided.insert(name, index as u32);
+        }
+        Some((name, symbol))
+    }
+
+    pub fn resolve(&self, symbol: Symbol) -> &'static str {
+        self.shards[symbol.shard()].read().unwrap().names[symbol.index()]
+    }
+
+    /// Distinct names interned so far
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|s| s.read().unwrap().names.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -1,503 +1,1107 @@
 //! Lexer Engine (engine/lexer.rs)
 //!
-//! This module defines the core lexer engine behind syntx, responsible for character-wise
-//! tokenization of source input. It is designed to be highly flexible by relying on
-//! user-defined Lexable and Delimeted traits, allowing support for a wide variety of languages.
+//! This module defines the core lexer engine behind syntx, responsible for tokenization of source
+//! input. It is designed to be highly flexible by relying on user-defined Lexable and Delimeted
+//! traits, allowing support for a wide variety of languages.
+//!
+//! The engine walks the source as a byte slice with an explicit cursor (`State::offset`). ASCII
+//! is handled byte by byte, multibyte chars are decoded only where they show up, and are never
+//! handed to the byte based Delimeted queries, so they can't be mistaken for delimiters.
+//!
+//! Lexing is pull based: the Lexer is an Iterator over spanned tokens and `next_batch` hands
+//! them out in chunks. The push mode (`Lexer::new` + `tokenize`) is a thin adapter that feeds
+//! those batches to a TokenSink (see engine/sink.rs), a crossbeam Sender being the usual one.
+use crate::engine::errors::*;
+use crate::engine::limits::{CHECK_EVERY, CancelToken, Limits};
+use crate::engine::normalize::{HashTable, Verifying};
 use crate::engine::semantic_traits::*;
+use crate::engine::sink::{BATCH_SIZE, TokenSink};
 use crate::engine::states::*;
+use crate::tokens::lines::*;
+use crate::tokens::span::{Span, Spanned};
 use crate::tokens::token_traits::*;
-use crossbeam::channel::Sender;
-
-use std::iter::Peekable;
-use std::str::Chars;
-
-const BATCH_SIZE: usize = 2048;
+use crate::tokens::trivia::*;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 #[derive(Debug)]
 pub struct Lexer<'a, T: Lexable + Resolvable + Delimeted + Eq + Clone> {
-    pub tokens: Vec<T::Token>,
-    pub contents: Peekable<Chars<'a>>,
+    pub tokens: Vec<Spanned<T::Token<'a>>>,
     pub state: State<T>,
-    pub sender: Sender<Vec<T::Token>>,
+    /// Where `tokenize` delivers its batches, `None` for a pull based lexer
+    pub sink: Option<Box<dyn TokenSink<T::Token<'a>> + 'a>>,
+    /// Tokens per batch, taken from the sink
+    pub batch_size: usize,
+    /// No lexeme starts at or past this offset, the last one may run over it (chunked lexing)
+    pub stop_at: usize,
+    pub source: &'a str,
+    bytes: &'a [u8],
+    /// Keep comments, whitespace and skipped text as trivia on the tokens (see tokens/trivia.rs)
+    pub lossless: bool,
+    /// Splice the tokens of imported files in place of the import, off in lossless mode
+    pub resolve_imports: bool,
+    /// Where the trivia in front of the next token starts
+    trivia_at: Span,
+    /// Starts of the lines walked so far
+    lines: LineIndex,
+    /// Comments seen since the last token, waiting to be attached
+    comments: Vec<Trivia>,
+    /// First bytes of every comment opener of the lang
+    comment_starts: [bool; 256],
+    /// First bytes of every string opener of the lang
+    string_starts: [bool; 256],
+    /// `T::is_ident_start` and `T::is_ident_continue` for ASCII, looked up instead of asked
+    ident_start: [bool; 128],
+    ident_continue: [bool; 128],
+    /// Tokens already lexed but not yet handed out by `next`
+    pending: std::vec::IntoIter<Spanned<T::Token<'a>>>,
+    /// Set once the input is exhausted (and the lossless EOF emitted)
+    done: bool,
+    /// What the run may use up, unlimited unless set before lexing starts
+    pub limits: Limits,
+    /// Checks identifier hashes against this table while lexing (see engine/normalize.rs)
+    pub hashes: Option<Arc<Mutex<HashTable>>>,
+    /// The limit that stopped the run, nothing is lexed once it is set
+    stopped: Option<LimitError>,
+    /// Tokens already handed out in batches
+    emitted: usize,
+    steps: usize,
+    /// Set from `limits.timeout` when the first batch starts
+    pub(crate) deadline: Option<Instant>,
+    /// Imported files the input was reached through, outermost first
+    import_chain: Vec<PathBuf>,
+}
+
+/// What the lexer of an imported file takes over from the one that met the import: what is left
+/// of its limits, its deadline, its hash table, and the files imported on the way to it.
+#[derive(Debug, Clone, Default)]
+pub struct ImportScope {
+    pub limits: Limits,
+    pub deadline: Option<Instant>,
+    pub hashes: Option<Arc<Mutex<HashTable>>>,
+    /// Imported files from the outermost in, importing one of them again is a cycle
+    pub chain: Vec<PathBuf>,
 }
 
 /// Implementation of lexer constructor from a &str, to avoid duplication and save memory
 /// Traits needed:
-///        - Lexable:   Must be declared for token inference.
+/// - Lexable:   Must be declared for token inference.
 ///
-///        - Delimeted: Must explicitly specify delimeters (both operators and others).
-///                     See syntx/src/langs/java.rs for examples, this is so the engine
-///                     knows when to stop eating and try to infer a type.
-
-impl<'a, T: Lexable + Resolvable + Delimeted + Eq + Clone + Default> Lexer<'a, T>
-where
-    T::Token: Token,
-{
-    pub fn new(value: &'a str, s: Sender<Vec<T::Token>>) -> Self {
+/// - Delimeted: Must explicitly specify delimeters (both operators and others).
+///   See syntx/src/langs/java.rs for examples, this is so the engine
+///   knows when to stop eating and try to infer a type.
+impl<'a, T: Lexable + Resolvable + Delimeted + Eq + Clone + Default> Lexer<'a, T> {
+    /// Pull based lexer, tokens are produced as the Lexer is iterated (or all at once into
+    /// `tokens` by `tokenize`). No thread or channel involved.
+    // a borrowing lexer can't be built through FromStr
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(value: &'a str) -> Self {
         Lexer {
-            sender: s,
+            sink: None,
+            batch_size: BATCH_SIZE,
+            stop_at: value.len(),
             tokens: Vec::with_capacity(BATCH_SIZE),
-            contents: value.chars().peekable(),
             state: State::<T>::default(),
+            source: value,
+            bytes: value.as_bytes(),
+            lossless: false,
+            resolve_imports: true,
+            trivia_at: Span::default(),
+            lines: LineIndex::default(),
+            comments: Vec::new(),
+            comment_starts: comment_starts::<T>(),
+            string_starts: string_starts::<T>(),
+            ident_start: ascii_table(T::is_ident_start),
+            ident_continue: ascii_table(T::is_ident_continue),
+            pending: Vec::new().into_iter(),
+            done: false,
+            limits: Limits::default(),
+            hashes: None,
+            stopped: None,
+            emitted: 0,
+            steps: 0,
+            deadline: None,
+            import_chain: Vec::new(),
         }
     }
+
+    /// Pull based lexer for the file at `path` imported under `scope`, it goes on with the
+    /// limits, deadline and hash table of the importer. `None` when `path` is already being imported further
+    /// up, lexing it again would never end.
+    pub fn for_import(value: &'a str, path: &Path, scope: &ImportScope) -> Option<Self> {
+        if scope.chain.iter().any(|p| p == path) {
+            return None;
+        }
+        let mut lexer = Self::from_str(value);
+        lexer.limits = scope.limits.clone();
+        lexer.deadline = scope.deadline;
+        lexer.hashes = scope.hashes.clone();
+        lexer.import_chain = scope.chain.clone();
+        lexer.import_chain.push(path.to_path_buf());
+        Some(lexer)
+    }
+
+    /// Push mode: `tokenize` lexes the whole input and hands it to `s` in batches of
+    /// `s.batch_size()` tokens
+    pub fn new(value: &'a str, s: impl TokenSink<T::Token<'a>> + 'a) -> Self {
+        let mut lexer = Self::from_str(value);
+        lexer.batch_size = s.batch_size().max(1);
+        lexer.tokens = Vec::with_capacity(lexer.batch_size);
+        lexer.sink = Some(Box::new(s));
+        lexer
+    }
+
+    /// Same as `new`, but every token carries its leading trivia and source text so the stream
+    /// can be concatenated back into the input byte for byte. Imports are not expanded in this
+    /// mode, the import path stays in the stream as skipped trivia.
+    pub fn new_lossless(value: &'a str, s: impl TokenSink<T::Token<'a>> + 'a) -> Self {
+        let mut lexer = Self::new(value, s);
+        lexer.lossless = true;
+        lexer.resolve_imports = false;
+        lexer
+    }
+
+    /// Puts the cursor at `at.start` in the state a previous run had there, so lexing picks up
+    /// from the middle of the input. `at` has to be a place where that run was between tokens.
+    pub fn resume(&mut self, at: Span, checkpoint: &Checkpoint) {
+        self.state.offset = at.start;
+        self.state.row = at.row;
+        self.state.column = at.column;
+        self.state.restore(checkpoint);
+        let line = self.bytes[..at.start]
+            .iter()
+            .rposition(|&b| is_line_end(b))
+            .map_or(0, |i| i + 1);
+        self.lines = LineIndex::starting_at(at.row, line);
+        self.trivia_at = Span {
+            end: at.start,
+            ..at
+        };
+    }
+
+    /// Pull based counterpart of `new_lossless`
+    pub fn from_str_lossless(value: &'a str) -> Self {
+        let mut lexer = Self::from_str(value);
+        lexer.lossless = true;
+        lexer.resolve_imports = false;
+        lexer
+    }
 }
 
-/// Implements the core tokenizer engine using the Walker trait.
-/// This module consumes characters via lookahead (Peekable) and emits structured tokens.
-/// The engine behavior is fully customizable through Lexable and Delimeted user-defined trait bounds.
-impl<'a, T> Walker<T> for Lexer<'a, T>
+/// Span bookkeeping, every eater takes a mark before consuming and emits through `emit` so the
+/// token carries the byte range and the row/column it started at.
+impl<'a, T> Lexer<'a, T>
 where
     T: Lexable + Resolvable + Delimeted + Clone + Eq,
-    T::Token: Token,
 {
-    /// Main logic for advancing char position in the iterator, updating state coordinates on newlines
+    /// Where the cursor is, as an empty span
+    #[inline(always)]
+    pub fn mark(&self) -> Span {
+        Span {
+            start: self.state.offset,
+            end: self.state.offset,
+            row: self.state.row,
+            column: self.state.column,
+        }
+    }
 
-    fn bump(&mut self, ch: char) {
-        if !self.state.in_str && !self.state.in_char && ch == '\n' {
-            self.state.row += 1;
-            self.state.column = 0;
-        } else {
-            self.state.column += 1;
+    /// Byte under the cursor
+    #[inline(always)]
+    pub fn peek_byte(&self) -> Option<u8> {
+        self.bytes.get(self.state.offset).copied()
+    }
+
+    /// Byte `n` positions ahead of the cursor, lookahead is free on a slice
+    #[inline(always)]
+    pub fn peek_at(&self, n: usize) -> Option<u8> {
+        self.bytes.get(self.state.offset + n).copied()
+    }
+
+    /// Char under the cursor, decoded only when it is not ASCII
+    #[inline(always)]
+    pub fn peek(&self) -> Option<char> {
+        match self.peek_byte()? {
+            b if b.is_ascii() => Some(b as char),
+            _ => self.source[self.state.offset..].chars().next(),
         }
-        self.contents.next();
     }
 
-    /// Main loop for the tokenizer, eats chars until there are no more in the iterator to consume
+    /// Everything from the cursor to the end of the input
+    #[inline(always)]
+    pub fn rest(&self) -> &'a str {
+        &self.source[self.state.offset..]
+    }
+
+    /// Moves the cursor to `end` in one go, updating row and column from the skipped bytes.
+    /// `end` must sit on a char boundary.
+    #[inline(always)]
+    fn advance_to(&mut self, end: usize) {
+        let start = self.state.offset;
+        let skipped = &self.bytes[start..end];
+        let chars = |b: &[u8]| b.iter().filter(|&&b| (b & 0xC0) != 0x80).count();
+        let mut line = None;
+        if skipped.iter().any(|&b| is_line_end(b)) {
+            for s in breaks(self.bytes, start..end) {
+                self.state.row += 1;
+                self.lines.push(s);
+                line = Some(s);
+            }
+        }
+        match line {
+            Some(s) => self.state.column = chars(&self.bytes[s..end]),
+            None => self.state.column += chars(skipped),
+        }
+        self.state.offset = end;
+    }
 
-    fn tokenize(&mut self) {
-        while let Some(&ch) = self.contents.peek() {
-            // Comment detection based on greedy search and the maximal-munch principle
-            // Uses fixed 4-char lookahead (max known length for comment tokens across languages).
-            // Can be extended if needed for languages with longer comment markers.
+    /// Emits a single char token that has not been bumped yet
+    #[inline(always)]
+    fn emit_char(&mut self, ch: char) {
+        let mut span = self.mark();
+        span.end += ch.len_utf8();
+        let text = Cow::Borrowed(&self.source[span.start..span.end]);
+        if let Some(x) = T::infer_token(text, &mut self.state) {
+            self.push(x, span);
+        }
+    }
 
-            if let Some(n) = T::may_trigger_line_comment(ch) {
-                let mut buf = ['\0'; 4];
-                let mut len = 0;
+    #[inline(always)]
+    fn emit(&mut self, s: Cow<'a, str>, mut span: Span) {
+        if let Some(x) = T::infer_token(s, &mut self.state) {
+            span.end = self.state.offset;
+            self.push(x, span);
+        }
+    }
 
-                let mut cpy = self.contents.clone();
-                for i in 0..n {
-                    if let Some(&ch) = cpy.peek() {
-                        buf[i] = ch;
-                        cpy.next();
-                        len += 1;
-                    } else {
-                        break;
-                    }
+    #[inline(always)]
+    fn is_ident_start(&self, ch: char) -> bool {
+        match ch {
+            _ if ch.is_ascii() => self.ident_start[ch as usize],
+            _ => T::is_ident_start(ch),
+        }
+    }
+
+    /// Emits the embed marker that ends at the cursor, through `Lexable::embed_marker`
+    fn emit_marker(&mut self, mut span: Span) {
+        span.end = self.state.offset;
+        let text = Cow::Borrowed(&self.source[span.start..span.end]);
+        if let Some(x) = T::embed_marker(text, &mut self.state) {
+            self.push(x, span);
+        }
+    }
+
+    /// Parks the string at the opener of an embedded expression, the next step takes the
+    /// opener. What is left of the string waits on the mode stack, with no opener of its own.
+    fn enter_embed(&mut self, open: OpenString) {
+        let Some((embed, close)) = open.embed else {
+            return;
+        };
+        self.state
+            .push_mode(Mode::Str(OpenString { len: 0, ..open }));
+        self.state.push_mode(Mode::Embed {
+            pending: Some(embed),
+            close,
+            brace_level: self.state.brace_level,
+        });
+    }
+
+    /// Lexes from the top of the mode stack when the engine owns it: the opener or closer of an
+    /// embedded expression, or the rest of a string. False when it's plain code.
+    fn step_mode(&mut self) -> bool {
+        match self.state.mode() {
+            Some(&Mode::Embed {
+                pending: Some(embed),
+                ..
+            }) => {
+                let at = self.mark();
+                if let Some(Mode::Embed { pending, .. }) = self.state.modes.last_mut() {
+                    *pending = None;
                 }
-                if T::trigger_comment_line(&buf[..len]) {
-                    self.eat_comment_line();
-                    continue;
+                self.advance_to(at.start + embed.len());
+                self.emit_marker(at);
+            }
+            Some(&Mode::Embed {
+                pending: None,
+                close,
+                brace_level,
+            }) if brace_level == self.state.brace_level && self.rest().starts_with(close) => {
+                let at = self.mark();
+                self.state.pop_mode();
+                self.advance_to(at.start + close.len());
+                self.emit_marker(at);
+            }
+            Some(Mode::Str(_)) => {
+                if let Some(Mode::Str(open)) = self.state.pop_mode() {
+                    self.eat_str(open, self.limits.max_literal);
                 }
             }
+            _ => return false,
+        }
+        true
+    }
 
-            if let Some((end_seq, n)) = T::may_trigger_block_comment(ch) {
-                let mut buf = ['\0'; 4];
-                let mut len = 0;
-
-                let mut cpy = self.contents.clone();
-                for i in 0..n {
-                    if let Some(&ch) = cpy.peek() {
-                        buf[i] = ch;
-                        cpy.next();
-                        len += 1;
-                    } else {
-                        break;
-                    }
-                }
+    #[inline(always)]
+    fn push(&mut self, x: T::Token<'a>, span: Span) {
+        let mut token = Spanned::new(x, span);
+        if self.lossless {
+            token.lossless = Some(Box::new(self.take_trivia(span)));
+        }
+        self.tokens.push(token);
+    }
 
-                if T::trigger_comment_block(&buf[..len]) {
-                    self.eat_comment_block(&end_seq);
-                    continue;
-                }
-            }
+    #[inline(always)]
+    fn report(
+        &mut self,
+        kind: LexicalErrorKind,
+        at: Span,
+        expected: Option<&str>,
+        found: Option<String>,
+    ) {
+        self.state.report(LexicalError {
+            row: at.row,
+            column: at.column,
+            offset: at.start,
+            kind,
+            expected: expected.map(str::to_string),
+            found,
+        });
+    }
 
-            // State-machine updater logic and dispatcher for eaters to consume items based on a
-            // condition.
-            // NOTE: eat_str and eat_char handle state toggles (in_str/in_char) internally.
-            // No need to mutate those flags here.
+    /// Where the lines walked so far start, every line of the input once `tokenize` returns
+    pub fn lines(&self) -> &LineIndex {
+        &self.lines
+    }
 
-            match ch {
-                '\n' => self.bump(ch),
-                '"' => {
-                    if !self.state.in_char {
-                        self.eat_str();
-                    }
-                }
-                '\'' => {
-                    if !self.state.in_str {
-                        self.eat_char();
-                    }
-                }
-                '{' => {
-                    self.state.brace_level += 1;
-                    if let Some(x) = T::infer_token(ch.to_string(), &mut self.state) {
-                        self.tokens.push(x);
-                    }
-                    self.bump(ch);
-                }
-                '}' => {
-                    self.state.brace_level -= 1;
-                    if let Some(x) = T::infer_token(ch.to_string(), &mut self.state) {
-                        self.tokens.push(x);
-                    }
-                    self.bump(ch);
-                }
-                '(' => {
-                    self.state.in_paren = true;
-                    self.state.paren_level += 1;
-                    if let Some(x) = T::infer_token(ch.to_string(), &mut self.state) {
-                        self.tokens.push(x);
-                    }
-                    self.bump(ch);
-                }
-                ')' => {
-                    self.state.paren_level -= 1;
-                    if self.state.paren_level == 0 {
-                        self.state.in_paren = false;
+    pub fn take_lines(&mut self) -> LineIndex {
+        std::mem::take(&mut self.lines)
+    }
+
+    /// Errors collected so far, complete once `tokenize` returns
+    pub fn diagnostics(&self) -> &[LexicalError] {
+        self.state.stacktrace.as_deref().unwrap_or_default()
+    }
+
+    pub fn take_diagnostics(&mut self) -> Vec<LexicalError> {
+        self.state.stacktrace.take().unwrap_or_default()
+    }
+
+    /// Eats an escape sequence starting at the backslash and pushes the char it stands for.
+    /// Unicode escapes come either braced (`\u{1F600}`) or with the fixed length the language
+    /// gives in `allowed_unicode_char` (`\u0041`).
+    fn eat_escape(&mut self, s: &mut String) {
+        let at = self.mark();
+        self.bump('\\');
+        let Some(escaped) = self.peek() else {
+            return;
+        };
+        if escaped == 'u' || escaped == 'U' {
+            self.bump(escaped);
+            let mut s2 = String::new();
+            if self.peek_byte() == Some(b'{') {
+                self.bump('{');
+                // eat until closing '}', never past the end of the literal
+                while let Some(x) = self.peek() {
+                    if x == '}' {
+                        self.bump(x);
+                        break;
                     }
-                    if let Some(x) = T::infer_token(ch.to_string(), &mut self.state) {
-                        self.tokens.push(x);
+                    if matches!(x, '"' | '\'' | '\n' | '\r') {
+                        break;
                     }
-                    self.bump(ch);
+                    s2.push(x);
+                    self.bump(x);
                 }
-                x if T::is_operator(&vec![x as u8]) => self.eat_delimeter(x),
-                x if T::is_delimeter(&(x as u8)) => {
-                    if !x.is_whitespace() {
-                        if let Some(t) = T::infer_token(x.to_string(), &mut self.state) {
-                            self.tokens.push(t);
-                        }
+            } else if let Some(mut t) = T::allowed_unicode_char(&escaped) {
+                // legacy form: eat t chars or until a whitespace is hit
+                while let Some(x) = self.peek() {
+                    if x.is_whitespace() || t == 0 {
+                        break;
                     }
-                    self.goto_next_sequence(x);
+                    t -= 1;
+                    s2.push(x);
+                    self.bump(x);
                 }
-                x if self.state.read_include && x.is_alphabetic() => {
-                    let name = self.eat_to_newl();
-                    self.state.read_include = false;
-                    self.sender.send(std::mem::take(&mut self.tokens)).unwrap();
-                    T::resolve_import(name, self.sender.clone());
-                }
-                x if x.is_alphabetic() => self.eat_literal(),
-                x if x.is_numeric() => self.eat_number(x),
-                _ => {
-                    self.bump(ch);
+            }
+            // if you can convert it -> push char, otherwise keep the raw digits
+            match u32::from_str_radix(&s2, 16).ok().and_then(char::from_u32) {
+                Some(c) => s.push(c),
+                None => {
+                    s.push_str(&s2);
+                    let found = format!("\\{}{}", escaped, s2);
+                    self.report(
+                        LexicalErrorKind::InvalidEscape,
+                        at,
+                        Some("hexadecimal code point"),
+                        Some(found),
+                    );
                 }
             }
+        } else if let Some(x) = T::is_scape(&escaped) {
+            s.push(x);
+            self.bump(escaped);
+        } else {
+            // unknown escape: push raw
+            self.bump(escaped);
+            s.push(escaped);
+            self.report(
+                LexicalErrorKind::InvalidEscape,
+                at,
+                None,
+                Some(format!("\\{}", escaped)),
+            );
         }
-        if self.tokens.len() >= BATCH_SIZE {
-            self.sender.send(std::mem::take(&mut self.tokens)).unwrap();
+    }
+
+    /// Records a comment that was just eaten, starting at `start`
+    fn record_comment(&mut self, kind: TriviaKind, mut span: Span) {
+        span.end = self.state.offset;
+        // line comments leave their terminator to be a Newline trivia of its own
+        if kind == TriviaKind::LineComment {
+            span.end = span
+                .start
+                .max(line_end(&self.bytes[..span.end], span.start));
         }
-        self.sender.send(std::mem::take(&mut self.tokens)).unwrap();
+        self.comments.push(Trivia {
+            kind,
+            span,
+            text: self.source[span.start..span.end].to_string(),
+        });
+    }
+
+    /// Collects everything between the previous token and `span` as trivia, and moves the
+    /// trivia cursor past the token.
+    fn take_trivia(&mut self, span: Span) -> Lossless {
+        let mut leading = Vec::new();
+        let mut at = self.trivia_at;
+        for comment in std::mem::take(&mut self.comments) {
+            split_gap(
+                &self.source[at.start..comment.span.start],
+                &mut at,
+                &mut leading,
+            );
+            at = comment.span;
+            advance(&mut at, &comment.text);
+            leading.push(comment);
+        }
+        split_gap(&self.source[at.start..span.start], &mut at, &mut leading);
+
+        let text = self.source[span.start..span.end].to_string();
+        at = span;
+        advance(&mut at, &text);
+        self.trivia_at = at;
+        Lossless { leading, text }
+    }
+}
+
+/// Implements the core tokenizer engine using the Walker trait.
+/// This module consumes bytes through the cursor in State and emits structured tokens.
+/// The engine behavior is fully customizable through Lexable and Delimeted user-defined trait bounds.
+impl<'a, T> Walker<T> for Lexer<'a, T>
+where
+    T: Lexable + Resolvable + Delimeted + Clone + Eq,
+{
+    /// Main logic for advancing the cursor past `ch`, updating state coordinates on newlines.
+    /// A `\r\n` ends its line at the `\n`.
+    #[inline(always)]
+    fn bump(&mut self, ch: char) {
+        self.state.offset += ch.len_utf8();
+        if ch == '\n' || (ch == '\r' && self.peek_byte() != Some(b'\n')) {
+            self.state.row += 1;
+            self.state.column = 0;
+            self.lines.push(self.state.offset);
+        } else {
+            self.state.column += 1;
+        }
+    }
+
+    /// Main loop for the tokenizer. With a sink the input is lexed batch by batch into it and
+    /// the first batch it refuses stops the run, otherwise everything ends up in `tokens`.
+    /// An exceeded limit stops it too, after the tokens lexed so far were delivered.
+    fn tokenize(&mut self) -> Result<(), LexError> {
+        let Some(mut sink) = self.sink.take() else {
+            let _verifying = Verifying::install(self.hashes.clone());
+            self.check_clock();
+            while self.advance() {}
+            return self.stopped.clone().map_or(Ok(()), |e| Err(e.into()));
+        };
+        let accepted = self.batches().try_for_each(|batch| sink.accept(batch));
+        let result = match accepted {
+            Err(e) => Err(e.into()),
+            Ok(()) => match self.stopped.clone() {
+                Some(e) => Err(e.into()),
+                None => sink.finish().map_err(LexError::from),
+            },
+        };
+        self.sink = Some(sink);
+        result
     }
 
     /// Logic to eat a generic number, uses the traits in 'Delimeted' to stop eating,
     /// allowing for differnt chars apart from the classics 0-9, this can take floats, ints, etc...
     fn eat_number(&mut self, ch: char) {
-        let mut s = String::new();
-        s.push(ch);
+        let span = self.mark();
         self.bump(ch);
 
-        while let Some(&c) = self.contents.peek() {
-            if T::allowed_number_chars(&c) {
-                s.push(c);
-                self.bump(c);
-            } else {
+        while let Some(c) = self.peek() {
+            if !T::allowed_number_chars(&c) {
                 break;
             }
+            self.bump(c);
         }
-        if let Some(x) = T::infer_token(s, &mut self.state) {
-            self.tokens.push(x);
-        }
+        let s = &self.source[span.start..self.state.offset];
+        self.emit(Cow::Borrowed(s), span);
     }
 
     /// Eats until it finds the terminator secuence defined by the user, ignoring any kind of
-    /// special chars
-
-    fn eat_comment_block(&mut self, until: &[char]) {
-        let len = until.len();
-        let mut buffer = vec!['\0'; len];
-
-        while let Some(_) = self.contents.peek() {
-            let mut matched = true;
-            let mut temp_iter = self.contents.clone();
-
-            for i in 0..len {
-                if let Some(ch) = temp_iter.next() {
-                    buffer[i] = ch;
-                    if ch != until[i] {
-                        matched = false;
-                        break;
-                    }
-                } else {
-                    matched = false;
-                    break;
-                }
-            }
-
-            if matched {
-                for _ in 0..len {
-                    self.contents.next();
-                }
-                break;
+    /// special chars. Nested styles count the openers on the way and need as many terminators.
+    fn eat_comment_block(&mut self, style: &CommentStyle, budget: usize) {
+        let start = self.mark();
+        match style.measure_within(self.rest(), budget) {
+            Ok(len) | Err(len) if len > budget => self.too_long(start),
+            Ok(len) => self.advance_to(start.start + len),
+            Err(len) => {
+                self.advance_to(start.start + len);
+                let CommentStyle::Block { close, .. } = style else {
+                    return;
+                };
+                self.report(
+                    LexicalErrorKind::UnterminatedBlockComment,
+                    start,
+                    Some(close),
+                    None,
+                );
             }
-            self.contents.next();
         }
     }
 
-    /// Just skips to the next line
-
-    fn eat_comment_line(&mut self) {
-        self.skip_line();
+    /// Just skips to the next line, as long as it ends within the budget
+    fn eat_comment_line(&mut self, budget: usize) {
+        let start = self.mark();
+        match CommentStyle::Line("").measure_within(self.rest(), budget) {
+            Ok(len) | Err(len) if len > budget => self.too_long(start),
+            Ok(len) | Err(len) => self.advance_to(start.start + len),
+        }
     }
 
     /// Consumes chars until it reaches a valid character that can be interpreted as a literal,
     /// delimiter, operator, .... Skipping all whitespaces.
-
     fn goto_next_sequence(&mut self, ch: char) {
         self.bump(ch);
-        while let Some(&ch) = self.contents.peek() {
-            if !ch.is_whitespace() {
+        while let Some(ch) = self.peek() {
+            if !ch.is_whitespace() || self.state.offset >= self.stop_at {
                 return;
             }
             self.bump(ch);
         }
     }
 
-    /// Consumes everything until it sees a newline
-
+    /// Consumes everything until it sees a line terminator (the terminator included)
     fn skip_line(&mut self) {
-        while let Some(&ch) = self.contents.peek() {
-            if ch == '\n' {
-                self.bump(ch);
-                return;
-            }
-            self.bump(ch);
-        }
+        let end = line_end(self.bytes, self.state.offset);
+        self.advance_to(end + terminator_len(self.bytes, end));
     }
 
-    /// Eats until a delimeter (whitespaces, operators, delimiters, ...)
-
+    /// Eats an identifier or keyword: the char it starts with, then every char the lang lets an
+    /// identifier go on with (Unicode XID_Continue plus its extras)
     fn eat_literal(&mut self) {
-        let mut literal: String = String::new();
-        while let Some(&ch) = self.contents.peek() {
-            if T::is_delimeter(&(ch as u8)) {
-                break;
+        let span = self.mark();
+        let mut end = self.state.offset + self.peek().map_or(1, char::len_utf8);
+        while let Some(&b) = self.bytes.get(end) {
+            if b.is_ascii() {
+                if !self.ident_continue[b as usize] {
+                    break;
+                }
+                end += 1;
+            } else {
+                let ch = self.source[end..].chars().next().unwrap_or_default();
+                if !T::is_ident_continue(ch) {
+                    break;
+                }
+                end += ch.len_utf8();
             }
-            literal.push(ch);
-            self.bump(ch);
-        }
-        if let Some(x) = T::infer_token(literal, &mut self.state) {
-            self.tokens.push(x);
         }
+        self.advance_to(end);
+        let literal = &self.source[span.start..end];
+        self.emit(Cow::Borrowed(literal), span);
     }
 
     fn eat_to_newl(&mut self) -> String {
-        let mut literal: String = String::new();
-        while let Some(&ch) = self.contents.peek() {
-            if ch == '\n' {
-                break;
-            }
-            literal.push(ch);
-            self.bump(ch);
-        }
-        match literal.strip_suffix(';') {
-            Some(suffix_stripped) => suffix_stripped.to_string(),
-            None => literal,
-        }
+        let start = self.state.offset;
+        let end = line_end(self.bytes, start);
+        self.advance_to(end);
+        let literal = &self.source[start..end];
+        literal.strip_suffix(';').unwrap_or(literal).to_string()
     }
 
-    /// Eats until it sees double quotes, is up to the user to define token inference correctly for
-    /// literal interpretation on strigs that contain single quotes,
-
-    fn eat_str(&mut self) {
+    /// Eats until the terminator the opener asked for, is up to the user to define token inference
+    /// correctly for literal interpretation on strigs that contain single quotes. Escapes are only
+    /// read in styles that have them. The end of input, or a newline in a single line style,
+    /// before the terminator is reported as unterminated. In a template the text up to an
+    /// embedded expression is a token of its own, and lexing goes on in `Mode::Embed`.
+    fn eat_str(&mut self, open: OpenString, budget: usize) {
+        let span = self.mark();
+        let cap = self
+            .bytes
+            .len()
+            .min(span.start.saturating_add(budget).saturating_add(1));
         self.state.in_str = true;
-        self.bump('"');
-        let mut s = String::new();
-        while let Some(&ch) = self.contents.peek() {
-            if ch == '"' {
-                break;
+        self.advance_to(span.start + open.len);
+        let content = self.state.offset;
+        let close = open.close.as_bytes();
+        let embed = open.embed.map(|(embed, _)| embed.as_bytes());
+        // bytes that may end a run of plain content
+        let stop = |b: u8| {
+            b == close[0]
+                || (open.escapes && b == b'\\')
+                || (!open.multiline && is_line_end(b))
+                || embed.is_some_and(|e| e[0] == b)
+        };
+        // stays None (borrowed text) until an escape forces a rewrite
+        let mut owned: Option<String> = None;
+        let mut closed = false;
+        let mut embedded = false;
+        let mut end = content;
+        while let Some(b) = self.peek_byte() {
+            if self.state.offset - span.start > budget {
+                self.state.in_str = false;
+                self.too_long(span);
+                return;
             }
-            if ch == '\\' {
-                self.bump(ch);
-                if let Some(&escaped) = self.contents.peek() {
-                    // basically check if the char is \u, \U or other type of unicode
-                    // t is the length of the unicode to read next
-                    if escaped == 'u' || escaped == 'U' {
-                        self.bump(escaped);
-                        if let Some(&next) = self.contents.peek() {
-                            // if the next char is '{', switch to brace-style
-                            if next == '{' {
-                                self.bump(next);
-                                let mut s2: String = String::new();
-                                // eat until closing '}'
-                                while let Some(&x) = self.contents.peek() {
-                                    if x == '}' {
-                                        self.bump(x);
-                                        break;
-                                    }
-                                    s2.push(x);
-                                    self.bump(x);
-                                }
-                                // if you can convert it -> push char
-                                if let Ok(n) = u32::from_str_radix(&s2, 16) {
-                                    if let Some(c) = char::from_u32(n) {
-                                        s.push(c);
-                                    } else {
-                                        s.push_str(&s2);
-                                    }
-                                } else {
-                                    s.push_str(&s2);
-                                }
-                            } else if let Some(mut t) = T::allowed_unicode_char(&escaped) {
-                                // legacy form: eat t chars or until a whitespace is hit
-                                let mut s2: String = String::new();
-                                while let Some(&x) = self.contents.peek() {
-                                    if x.is_whitespace() || t <= 0 {
-                                        break;
-                                    }
-                                    t -= 1;
-                                    s2.push(x);
-                                    self.bump(x);
-                                }
-                                // if you can convert it -> push char
-                                if let Ok(n) = u32::from_str_radix(&s2, 16) {
-                                    if let Some(c) = char::from_u32(n) {
-                                        s.push(c);
-                                    } else {
-                                        s.push_str(&s2);
-                                    }
-                                } else {
-                                    s.push_str(&s2);
-                                }
-                            }
+            match b {
+                _ if self.bytes[self.state.offset..].starts_with(close) => {
+                    end = self.state.offset;
+                    self.advance_to(end + close.len());
+                    closed = true;
+                    break;
+                }
+                _ if embed.is_some_and(|e| self.bytes[self.state.offset..].starts_with(e)) => {
+                    end = self.state.offset;
+                    embedded = true;
+                    break;
+                }
+                b'\n' | b'\r' if !open.multiline => break,
+                b'\\' if open.escapes => {
+                    let mut buf = owned
+                        .take()
+                        .unwrap_or_else(|| self.source[content..self.state.offset].to_string());
+                    self.eat_escape(&mut buf);
+                    owned = Some(buf);
+                }
+                _ => {
+                    // take the whole run up to the next byte that may end it at once, a run
+                    // that goes on past the budget is cut short there
+                    let start = self.state.offset;
+                    let len = self.bytes[start + 1..cap]
+                        .iter()
+                        .position(|&b| stop(b))
+                        .map(|n| n + 1);
+                    let len = match len {
+                        Some(len) => len,
+                        None if cap == self.bytes.len() => cap - start,
+                        None => {
+                            self.state.in_str = false;
+                            self.too_long(span);
+                            return;
                         }
-                    // if it's a supported escape char, just push it
-                    } else if let Some(x) = T::is_scape(&escaped) {
-                        s.push(x);
-                        self.bump(escaped);
-                    } else {
-                        // unknown escape: push raw
-                        self.bump(escaped);
-                        s.push(escaped);
+                    };
+                    self.advance_to(start + len);
+                    if let Some(s) = owned.as_mut() {
+                        s.push_str(&self.source[start..start + len]);
                     }
                 }
-            } else {
-                s.push(ch);
-                self.bump(ch);
             }
         }
-        self.bump('"');
-        if let Some(x) = T::infer_token(s, &mut self.state) {
-            self.tokens.push(x);
+        if !closed && !embedded {
+            end = self.state.offset;
+            let found = self.peek().map(|c| c.to_string());
+            self.report(
+                LexicalErrorKind::UnterminatedString,
+                span,
+                Some(&open.close),
+                found,
+            );
         }
+        let s = match owned {
+            Some(s) => Cow::Owned(s),
+            None => Cow::Borrowed(&self.source[content..end]),
+        };
+        self.emit(s, span);
         self.state.in_str = false;
+        if embedded {
+            self.enter_embed(open);
+        }
     }
 
     /// Eats until it sees a single quote, is up to the user to define token inference correctly for
     /// literal interpretation on strigs that contain souble quotes,
-
     fn eat_char(&mut self) {
+        let span = self.mark();
         self.state.in_char = true;
         self.bump('\'');
-        let mut s = String::new();
-        if let Some(&ch) = self.contents.peek() {
-            if ch == '\\' {
-                self.bump(ch);
-                if let Some(&escaped) = self.contents.peek() {
-                    // basically check if the char is \u, \U or other type of unicode
-                    // t is the length of the unicode to read next
-                    if escaped == 'u' || escaped == 'U' {
-                        self.bump(escaped);
-
-                        if let Some(&next) = self.contents.peek() {
-                            if next == '{' {
-                                // handle brace-style \u{...}
-                                self.bump(next); // bump '{'
-                                let mut s2 = String::new();
-                                while let Some(&x) = self.contents.peek() {
-                                    if x == '}' {
-                                        self.bump(x); // bump '}'
-                                        break;
-                                    }
-                                    s2.push(x);
-                                    self.bump(x);
-                                }
-                                // if you can convert it -> push char
-                                if let Ok(n) = u32::from_str_radix(&s2, 16) {
-                                    if let Some(c) = char::from_u32(n) {
-                                        s.push(c);
-                                    } else {
-                                        s.push_str(&s2);
-                                    }
-                                } else {
-                                    s.push_str(&s2);
-                                }
-                            } else if let Some(mut t) = T::allowed_unicode_char(&escaped) {
-                                // legacy \uXXXX form
-                                let mut s2 = String::new();
-                                while let Some(&x) = self.contents.peek() {
-                                    if x.is_whitespace() || t <= 0 {
-                                        break;
-                                    }
-                                    t -= 1;
-                                    s2.push(x);
-                                    self.bump(x);
-                                }
-                                // if you can convert it -> push char
-                                if let Ok(n) = u32::from_str_radix(&s2, 16) {
-                                    if let Some(c) = char::from_u32(n) {
-                                        s.push(c);
-                                    } else {
-                                        s.push_str(&s2);
-                                    }
-                                } else {
-                                    s.push_str(&s2);
-                                }
-                            }
-                        }
-                    // if its a supported scape char, just push it
-                    } else if let Some(x) = T::is_scape(&escaped) {
-                        s.push(x);
-                        self.bump(escaped);
-                    } else {
-                        // fallback: push raw escaped char
-                        self.bump(escaped);
-                        s.push(escaped);
-                    }
-                }
-            } else {
-                s.push(ch);
+        let content = self.state.offset;
+        let mut s = Cow::Borrowed("");
+        match self.peek() {
+            Some('\'') => {
+                self.report(LexicalErrorKind::EmptyChar, span, None, Some("'".into()));
+            }
+            Some('\\') => {
+                let mut buf = String::new();
+                self.eat_escape(&mut buf);
+                s = Cow::Owned(buf);
+            }
+            Some('\n' | '\r') | None => {}
+            Some(ch) => {
                 self.bump(ch);
+                s = Cow::Borrowed(&self.source[content..self.state.offset]);
             }
         }
-        if let Some(x) = T::infer_token(s, &mut self.state) {
-            self.tokens.push(x);
+        match self.peek() {
+            Some('\'') => self.bump('\''),
+            found => {
+                let found = found.map(|c| c.to_string());
+                self.report(LexicalErrorKind::UnterminatedChar, span, Some("'"), found);
+            }
         }
+        self.emit(s, span);
         self.state.in_char = false;
     }
 
     /// Handles multi-character operators like >>=, !=, ->, etc.
-    /// Greedily eats characters until a delimeter non-operator or alphanumeric delimiter is found.
-
+    /// Greedily eats chars while the run is still a known operator (maximal munch).
     fn eat_delimeter(&mut self, ch: char) {
-        let mut s = Vec::<u8>::new();
-        s.push(ch as u8);
-        self.bump(ch);
-        while let Some(&next) = self.contents.peek() {
-            s.push(next as u8);
-
-            if !T::is_operator(&s) {
-                s.pop(); // undo the last push
-                let token_str = unsafe { String::from_utf8_unchecked(s) };
-                if let Some(x) = T::infer_token(token_str, &mut self.state) {
-                    self.tokens.push(x);
+        let span = self.mark();
+        let start = self.state.offset;
+        let mut end = start + ch.len_utf8();
+        while let Some(next) = self.source[end..].chars().next()
+            && T::is_operator(&self.source[start..end + next.len_utf8()])
+        {
+            end += next.len_utf8();
+        }
+        let len = T::operator_len(&self.source[start..end], &self.state);
+        end = start + len.clamp(ch.len_utf8(), end - start);
+        self.advance_to(end);
+        let token_str = &self.source[start..end];
+        self.emit(Cow::Borrowed(token_str), span);
+    }
+}
+
+/// Batching, and the eaters that are not part of the Walker interface
+impl<'a, T> Lexer<'a, T>
+where
+    T: Lexable + Resolvable + Delimeted + Clone + Eq,
+{
+    /// Lexes up to a batch worth of tokens and hands them out, tokens already buffered for
+    /// `next` come first. `None` once the input is exhausted.
+    pub fn next_batch(&mut self) -> Option<Vec<Spanned<T::Token<'a>>>> {
+        if self.pending.len() > 0 {
+            return Some(self.pending.by_ref().collect());
+        }
+        let _verifying = Verifying::install(self.hashes.clone());
+        self.check_clock();
+        while self.tokens.len() < self.batch_size && self.advance() {}
+        if self.tokens.is_empty() {
+            return None;
+        }
+        self.emitted += self.tokens.len();
+        Some(std::mem::replace(
+            &mut self.tokens,
+            Vec::with_capacity(self.batch_size),
+        ))
+    }
+
+    /// Iterator over the remaining batches
+    pub fn batches(&mut self) -> impl Iterator<Item = Vec<Spanned<T::Token<'a>>>> + '_ {
+        std::iter::from_fn(move || self.next_batch())
+    }
+
+    /// The limit that stopped the run, `None` while it is within them or once it ran to the end
+    pub fn stopped(&self) -> Option<&LimitError> {
+        self.stopped.as_ref()
+    }
+
+    /// A step, then the limits it may have run into. False once the input is exhausted or a
+    /// limit stopped the run, the tokens of the step that hit it are kept.
+    #[inline(always)]
+    fn advance(&mut self) -> bool {
+        if self.stopped.is_some() {
+            return false;
+        }
+        let at = self.mark();
+        if !self.step() {
+            return false;
+        }
+        self.steps += 1;
+        if self.stopped.is_some() {
+            // an eater ran out of budget, or an import into a limit
+            return true;
+        }
+        let limits = &self.limits;
+        let depth = (self.state.brace_level)
+            .max(self.state.paren_level)
+            .max(self.state.modes.len());
+        if self.state.offset - at.start > limits.max_literal {
+            self.too_long(at);
+        } else if depth > limits.max_depth {
+            self.stopped = Some(LimitError::TooDeep {
+                limit: limits.max_depth,
+                row: at.row,
+                column: at.column,
+            });
+        } else if self.emitted + self.tokens.len() > limits.max_tokens {
+            self.tokens.truncate(limits.max_tokens - self.emitted);
+            self.stopped = Some(LimitError::TooManyTokens(limits.max_tokens));
+        } else if self.steps.is_multiple_of(CHECK_EVERY) {
+            self.check_clock();
+        }
+        true
+    }
+
+    /// Stops the run on a lexeme starting at `at` that takes more than `limits.max_literal` bytes
+    fn too_long(&mut self, at: Span) {
+        self.stopped = Some(LimitError::LiteralTooLong {
+            limit: self.limits.max_literal,
+            row: at.row,
+            column: at.column,
+        });
+    }
+
+    /// Stops the run if it was cancelled or is out of time, the clock starts on the first call
+    fn check_clock(&mut self) {
+        if self.stopped.is_some() {
+            return;
+        }
+        if self
+            .limits
+            .cancel
+            .as_ref()
+            .is_some_and(CancelToken::is_cancelled)
+        {
+            self.stopped = Some(LimitError::Cancelled);
+        } else if let Some(timeout) = self.limits.timeout {
+            let now = Instant::now();
+            match self.deadline {
+                None => self.deadline = Some(now + timeout),
+                Some(deadline) if now >= deadline => {
+                    self.stopped = Some(LimitError::Timeout(timeout));
                 }
-                return;
+                Some(_) => {}
+            }
+        }
+    }
+
+    /// Lexes the next lexeme into `tokens`, returns false once there are no more bytes to consume
+    #[inline(always)]
+    fn step(&mut self) -> bool {
+        let Some(b) = self
+            .peek_byte()
+            .filter(|_| self.state.offset < self.stop_at)
+        else {
+            if !self.done
+                && self.state.offset >= self.bytes.len()
+                && let Some(close) = self.state.modes.iter().rev().find_map(|m| match m {
+                    Mode::Str(open) => Some(open.close.clone()),
+                    _ => None,
+                })
+            {
+                // the input ended inside an embedded expression
+                let at = self.mark();
+                self.report(LexicalErrorKind::UnterminatedString, at, Some(&close), None);
+            }
+            if self.lossless && !self.done {
+                let eof = self.mark();
+                self.push(<T::Token<'a> as Token>::eof(), eof);
+            }
+            self.done = true;
+            return false;
+        };
+        if !self.state.modes.is_empty() && self.step_mode() {
+            return true;
+        }
+        // Comment detection based on maximal munch: the longest opener of any style starting
+        // here wins. Only bytes some opener starts with get to look at the styles at all.
+        if self.comment_starts[b as usize]
+            && let Some(style) = T::comment_at(self.rest())
+        {
+            let start = self.mark();
+            let budget = self.limits.max_literal;
+            let kind = match style {
+                CommentStyle::Line(_) => {
+                    self.eat_comment_line(budget);
+                    TriviaKind::LineComment
+                }
+                CommentStyle::Block { .. } => {
+                    self.eat_comment_block(style, budget);
+                    TriviaKind::BlockComment
+                }
+            };
+            if self.lossless && self.stopped.is_none() {
+                self.record_comment(kind, start);
             }
-            self.bump(next);
+            return true;
         }
-        let token_str = unsafe { String::from_utf8_unchecked(s) };
-        if let Some(x) = T::infer_token(token_str, &mut self.state) {
-            self.tokens.push(x);
+        if self.string_starts[b as usize]
+            && let Some(open) = T::string_at(self.rest())
+        {
+            self.eat_str(open, self.limits.max_literal);
+            return true;
         }
+
+        // Multibyte chars go through the same rules, only the byte arms below can't match them
+        let ch = if b.is_ascii() {
+            b as char
+        } else {
+            self.peek().unwrap_or(char::REPLACEMENT_CHARACTER)
+        };
+
+        // State-machine updater logic and dispatcher for eaters to consume items based on a
+        // condition.
+        // NOTE: eat_str and eat_char handle state toggles (in_str/in_char) internally.
+        // No need to mutate those flags here.
+
+        match b {
+            b'\n' => self.bump(ch),
+            b'\'' => {
+                if !self.state.in_str {
+                    self.eat_char();
+                }
+            }
+            b'{' => {
+                self.state.brace_level += 1;
+                self.emit_char(ch);
+                self.bump(ch);
+            }
+            b'}' => {
+                self.close_level(ch, |s| &mut s.brace_level);
+                self.emit_char(ch);
+                self.bump(ch);
+            }
+            b'(' => {
+                self.state.in_paren = true;
+                self.state.paren_level += 1;
+                self.emit_char(ch);
+                self.bump(ch);
+            }
+            b')' => {
+                self.close_level(ch, |s| &mut s.paren_level);
+                if self.state.paren_level == 0 {
+                    self.state.in_paren = false;
+                }
+                self.emit_char(ch);
+                self.bump(ch);
+            }
+            _ if T::is_operator(&self.rest()[..ch.len_utf8()]) => self.eat_delimeter(ch),
+            _ if T::is_delimeter(&ch) => {
+                if !ch.is_whitespace() {
+                    self.emit_char(ch);
+                }
+                self.goto_next_sequence(ch);
+            }
+            _ if self.state.read_include && self.is_ident_start(ch) => self.eat_import(),
+            _ if self.is_ident_start(ch) => self.eat_literal(),
+            _ if ch.is_numeric() => self.eat_number(ch),
+            _ if ch.is_whitespace() => self.bump(ch),
+            _ => self.eat_stray(ch),
+        }
+        true
+    }
+
+    /// Reads the import path after an import keyword and lets the tokenset resolve it, the
+    /// imported tokens are queued right where the import was. A limit the import runs into
+    /// stops this run too, with positions in the imported file.
+    fn eat_import(&mut self) {
+        let name = self.eat_to_newl();
+        self.state.read_include = false;
+        if !self.resolve_imports {
+            return;
+        }
+        let produced = self.emitted + self.tokens.len();
+        let scope = ImportScope {
+            limits: Limits {
+                max_tokens: self.limits.max_tokens.saturating_sub(produced),
+                ..self.limits.clone()
+            },
+            deadline: self.deadline,
+            hashes: self.hashes.clone(),
+            chain: self.import_chain.clone(),
+        };
+        // the buffer is a Vec sink, which never refuses a batch
+        match T::resolve_import(name, &scope, &mut self.tokens) {
+            Err(LexError::Limit(LimitError::TooManyTokens(_))) => {
+                self.stopped = Some(LimitError::TooManyTokens(self.limits.max_tokens));
+            }
+            Err(LexError::Limit(e)) => self.stopped = Some(e),
+            _ => {}
+        }
+    }
+
+    /// Leaves a nesting level on a closer, a closer with nothing open is reported and leaves
+    /// the level at 0
+    fn close_level(&mut self, ch: char, level: fn(&mut State<T>) -> &mut usize) {
+        match level(&mut self.state).checked_sub(1) {
+            Some(l) => *level(&mut self.state) = l,
+            None => {
+                let at = self.mark();
+                self.report(
+                    LexicalErrorKind::UnmatchedDelimiter,
+                    at,
+                    None,
+                    Some(ch.to_string()),
+                );
+            }
+        }
+    }
+
+    /// A char no rule claims: report it and move on
+    fn eat_stray(&mut self, ch: char) {
+        let at = self.mark();
+        self.report(
+            LexicalErrorKind::UnexpectedChar,
+            at,
+            None,
+            Some(ch.to_string()),
+        );
+        self.bump(ch);
+    }
+}
+
+/// Pull based lexing, one spanned token at a time
+impl<'a, T> Iterator for Lexer<'a, T>
+where
+    T: Lexable + Resolvable + Delimeted + Clone + Eq,
+{
+    type Item = Spanned<T::Token<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(token) = self.pending.next() {
+                return Some(token);
+            }
+            self.pending = self.next_batch()?.into_iter();
+        }
+    }
+}
+
+/// `f` for every ASCII char
+fn ascii_table(f: impl Fn(char) -> bool) -> [bool; 128] {
+    std::array::from_fn(|b| f(b as u8 as char))
+}
+
+/// Lookup table for the bytes a comment of `T` can start with
+pub fn comment_starts<T: Delimeted>() -> [bool; 256] {
+    first_bytes(T::comments().iter().map(CommentStyle::open))
+}
+
+/// Lookup table for the bytes a string literal of `T` can start with
+pub fn string_starts<T: Delimeted>() -> [bool; 256] {
+    first_bytes(T::strings().iter().map(StringStyle::prefix))
+}
+
+fn first_bytes<'s>(openers: impl Iterator<Item = &'s str>) -> [bool; 256] {
+    let mut starts = [false; 256];
+    for b in openers.filter_map(|o| o.as_bytes().first()) {
+        starts[*b as usize] = true;
     }
+    starts
 }
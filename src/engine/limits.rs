@@ -0,0 +1,55 @@
+//! Resource Limits (engine/limits.rs)
+//!
+//! Bounds for lexing input nobody vouches for. A Lexer checks its `Limits` as it goes and stops
+//! with a `LimitError` (see engine/errors.rs) at the first one exceeded, `tokenize` returns it
+//! and a pull based lexer keeps it in `Lexer::stopped`. Tokens lexed until then are still
+//! handed out. Everything is unlimited by default.
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Wall time and cancellation are looked at every this many steps, and before every batch
+pub const CHECK_EVERY: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// Tokens the run may produce, imported ones included
+    pub max_tokens: usize,
+    /// Deepest nesting of braces, parens or lexer modes
+    pub max_depth: usize,
+    /// Most bytes a single lexeme may take: a literal, a comment, or a run of whitespace
+    pub max_literal: usize,
+    /// Time from the first batch on
+    pub timeout: Option<Duration>,
+    pub cancel: Option<CancelToken>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_tokens: usize::MAX,
+            max_depth: usize::MAX,
+            max_literal: usize::MAX,
+            timeout: None,
+            cancel: None,
+        }
+    }
+}
+
+/// Stops a run from another thread. Clones share the flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
@@ -1,12 +1,225 @@
-///! Normalization module (engine/normalize.rs)
-///!
-use xxhash_rust::xxh3::xxh3_128;
+//! Normalization module (engine/normalize.rs)
+//!
+//! `normalize` is the identity of an identifier: its name and brace level folded into 64 bits.
+//! Two different identifiers can fold to the same value. A lexer given a `HashTable` in
+//! `Lexer::hashes` checks every call made while it lexes against what each hash was made from,
+//! a colliding identifier gets the next free hash instead and the collision is kept for
+//! `HashTable::report`. Any tokenset calling `normalize` is covered. Lexers share a table by
+//! sharing the Arc, it holds every identifier they saw.
+//!
+//! Unless some lexer is verifying right now, `normalize` only hashes: no thread local, no
+//! allocation for names that fit on the stack.
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use xxhash_rust::xxh3::{xxh3_128, xxh3_128_with_seed};
+
+/// Names up to this long are hashed from a buffer on the stack
+const STACK_NAME: usize = 56;
+
+/// Lexers verifying at the moment, on any thread. While there are none `normalize` doesn't look
+/// for a table.
+static VERIFYING: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Table of the lexer running on this thread, if it verifies
+    static TABLE: RefCell<Option<Arc<Mutex<HashTable>>>> = const { RefCell::new(None) };
+}
 
 pub fn normalize(name: &[u8], brace_level: usize) -> u64 {
+    if VERIFYING.load(Ordering::Relaxed) > 0
+        && let Some(hash) = TABLE.with_borrow(|table| {
+            let table = table.as_ref()?;
+            let buffer = key(name, brace_level);
+            let hash = fold(xxh3_128(&buffer));
+            Some(table.lock().unwrap().check(hash, buffer))
+        })
+    {
+        return hash;
+    }
+    if name.len() > STACK_NAME {
+        return fold(xxh3_128(&key(name, brace_level)));
+    }
+    let mut buffer = [0u8; STACK_NAME + 8];
+    let len = name.len() + 8;
+    buffer[..name.len()].copy_from_slice(name);
+    buffer[name.len()..len].copy_from_slice(&(brace_level as u64).to_le_bytes());
+    fold(xxh3_128(&buffer[..len]))
+}
+
+/// Points `normalize` on this thread at a lexer's table while it lexes, what was there before
+/// is put back on drop (the lexer of an import runs inside the one of its importer)
+pub(crate) struct Verifying(Option<Arc<Mutex<HashTable>>>);
+
+impl Verifying {
+    /// Nothing to install for a lexer without a table
+    pub(crate) fn install(table: Option<Arc<Mutex<HashTable>>>) -> Option<Self> {
+        let table = table?;
+        VERIFYING.fetch_add(1, Ordering::Relaxed);
+        Some(Verifying(TABLE.replace(Some(table))))
+    }
+}
+
+impl Drop for Verifying {
+    fn drop(&mut self) {
+        TABLE.set(self.0.take());
+        VERIFYING.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// What gets hashed, the name followed by the brace level
+pub(crate) fn key(name: &[u8], brace_level: usize) -> Vec<u8> {
     let mut buffer = Vec::with_capacity(name.len() + 8);
     buffer.extend_from_slice(name);
     buffer.extend_from_slice(&(brace_level as u64).to_le_bytes());
+    buffer
+}
 
-    let hash128 = xxh3_128(&buffer);
+fn fold(hash128: u128) -> u64 {
     (hash128 >> 64) as u64 ^ (hash128 as u64)
 }
+
+/// What each hash was made from, keys are `key` buffers
+#[derive(Debug, Default)]
+pub struct HashTable {
+    keys: HashMap<u64, Box<[u8]>>,
+    /// Identifiers that lost their hash to another one, and the hash they got instead
+    moved: HashMap<Box<[u8]>, u64>,
+    names: HashSet<Box<[u8]>>,
+    lookups: u64,
+    collisions: Vec<Collision>,
+}
+
+impl HashTable {
+    pub(crate) fn check(&mut self, hash: u64, key: Vec<u8>) -> u64 {
+        self.lookups += 1;
+        let name = &key[..key.len() - 8];
+        if !self.names.contains(name) {
+            self.names.insert(name.into());
+        }
+        match self.keys.get(&hash) {
+            Some(known) if **known == *key => hash,
+            Some(known) => {
+                if let Some(&moved) = self.moved.get(&*key) {
+                    return moved;
+                }
+                let first = Collision::split(known);
+                let resolved = (1..)
+                    .map(|seed| fold(xxh3_128_with_seed(&key, seed)))
+                    .find(|h| !self.keys.contains_key(h))
+                    .unwrap();
+                self.collisions.push(Collision {
+                    hash,
+                    first,
+                    second: Collision::split(&key),
+                    resolved,
+                });
+                let key: Box<[u8]> = key.into();
+                self.moved.insert(key.clone(), resolved);
+                self.keys.insert(resolved, key);
+                resolved
+            }
+            None => {
+                self.keys.insert(hash, key.into());
+                hash
+            }
+        }
+    }
+
+    /// What verification has seen so far
+    pub fn report(&self) -> HashReport {
+        let lengths = self.names.iter().map(|n| n.len());
+        HashReport {
+            lookups: self.lookups,
+            identifiers: self.keys.len(),
+            names: self.names.len(),
+            longest_name: lengths.clone().max().unwrap_or(0),
+            name_bytes: lengths.sum(),
+            collisions: self.collisions.clone(),
+        }
+    }
+}
+
+/// Two identifiers that `normalize` folded to the same hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collision {
+    pub hash: u64,
+    /// Name and brace level of the identifier that had the hash first, it keeps it
+    pub first: (String, usize),
+    pub second: (String, usize),
+    /// What `normalize` returns for the second one from then on
+    pub resolved: u64,
+}
+
+impl Collision {
+    fn split(key: &[u8]) -> (String, usize) {
+        let (name, level) = key.split_at(key.len() - 8);
+        let level = u64::from_le_bytes(level.try_into().unwrap()) as usize;
+        (String::from_utf8_lossy(name).into_owned(), level)
+    }
+}
+
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` (level {}) and `{}` (level {}) share {:#018x}, `{}` moved to {:#018x}",
+            self.first.0,
+            self.first.1,
+            self.second.0,
+            self.second.1,
+            self.hash,
+            self.second.0,
+            self.resolved
+        )
+    }
+}
+
+/// Statistics about the identifier space seen while verifying
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashReport {
+    /// Calls to `normalize`
+    pub lookups: u64,
+    /// Distinct name and brace level pairs, each one a hash
+    pub identifiers: usize,
+    /// Distinct names, whatever the level
+    pub names: usize,
+    pub longest_name: usize,
+    /// Bytes over all distinct names
+    pub name_bytes: usize,
+    pub collisions: Vec<Collision>,
+}
+
+impl HashReport {
+    /// Collisions a perfect 64 bit hash is expected to give for this many identifiers
+    pub fn expected_collisions(&self) -> f64 {
+        let n = self.identifiers as f64;
+        n * (n - 1.0) / 2f64.powi(65)
+    }
+}
+
+impl fmt::Display for HashReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Lookups      : {}", self.lookups)?;
+        writeln!(f, "Identifiers  : {}", self.identifiers)?;
+        writeln!(f, "Names        : {}", self.names)?;
+        writeln!(f, "Longest name : {}", self.longest_name)?;
+        writeln!(
+            f,
+            "Mean name    : {:.2}",
+            self.name_bytes as f64 / self.names.max(1) as f64
+        )?;
+        writeln!(
+            f,
+            "Collisions   : {} ({:.2e} expected)",
+            self.collisions.len(),
+            self.expected_collisions()
+        )?;
+        for c in &self.collisions {
+            writeln!(f, "  {}", c)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+//! Parallel Chunked Lexing (engine/parallel.rs)
+//!
+//! Splits one big input into chunks at newlines, lexes the chunks on a pool of threads and hands
+//! the tokens to a sink in source order.
+//!
+//! Chunk starts come from a pre-scan that only looks at strings, chars, comments and brackets,
+//! so it runs far ahead of the real lexer and guesses the row and the brace/paren levels at each
+//! start. The guess is checked while stitching: a chunk is taken as is only if the chunk before
+//! it really stopped at its start, on the same row and in the same State. Otherwise (a lexeme
+//! running across the boundary, a language rule the pre-scan doesn't know about) the chunk is
+//! lexed again from where the previous one ended, so the output is always the sequential one.
+use crate::engine::errors::{LexicalError, SinkError};
+use crate::engine::lexer::{Lexer, comment_starts, string_starts};
+use crate::engine::normalize::HashTable;
+use crate::engine::sink::TokenSink;
+use crate::engine::states::Checkpoint;
+use crate::tokens::lines::{breaks, line_end};
+use crate::tokens::span::{Span, Spanned};
+use crate::tokens::token_traits::*;
+use crossbeam::channel::unbounded;
+use std::sync::{Arc, Mutex};
+
+/// Chunks per thread, more of them keeps the pool busy when chunks lex at different speeds
+const CHUNKS_PER_THREAD: usize = 4;
+
+/// A place where a chunk starts, with the state the pre-scan expects the lexer to be in there
+#[derive(Debug, Clone, PartialEq)]
+struct Boundary {
+    at: Span,
+    state: Checkpoint,
+}
+
+struct Chunk<'a, T: Lexable> {
+    start: Boundary,
+    end: Boundary,
+    batches: Vec<Vec<Spanned<T::Token<'a>>>>,
+    diagnostics: Vec<LexicalError>,
+}
+
+/// Lexes `source` with `threads` threads and feeds the tokens to `sink` in order, the same
+/// stream a single Lexer would produce. Returns the diagnostics of the whole input. Every
+/// thread checks identifier hashes against `hashes`, when given (see engine/normalize.rs).
+pub fn tokenize_parallel<'a, T>(
+    source: &'a str,
+    threads: usize,
+    hashes: Option<&Arc<Mutex<HashTable>>>,
+    sink: &mut dyn TokenSink<T::Token<'a>>,
+) -> Result<Vec<LexicalError>, SinkError>
+where
+    T: Lexable + Resolvable + Delimeted + Clone + Eq + Default,
+    T::Token<'a>: Send,
+{
+    let threads = threads.max(1);
+    let count = threads * CHUNKS_PER_THREAD;
+    let targets: Vec<usize> = (1..count).map(|k| source.len() / count * k).collect();
+    let starts = prescan::<T>(source, &targets);
+    let stop = |k: usize| starts.get(k + 1).map_or(source.len(), |b| b.at.start);
+    let batch_size = sink.batch_size();
+
+    let (work, queue) = unbounded();
+    for k in 0..starts.len() {
+        work.send(k).unwrap();
+    }
+    drop(work);
+    let (done, results) = unbounded();
+
+    let mut diagnostics = Vec::new();
+    let mut result = Ok(());
+    std::thread::scope(|s| {
+        for _ in 0..threads {
+            let (queue, done) = (queue.clone(), done.clone());
+            let (starts, stop) = (&starts, &stop);
+            s.spawn(move || {
+                for k in queue.iter() {
+                    let chunk = lex_chunk::<T>(source, &starts[k], stop(k), batch_size, hashes);
+                    if done.send((k, chunk)).is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(done);
+
+        let mut ready: Vec<Option<Chunk<T>>> = (0..starts.len()).map(|_| None).collect();
+        let mut next = 0;
+        let mut at = Boundary {
+            at: Span::default(),
+            state: Checkpoint::default(),
+        };
+        for (k, chunk) in results.iter() {
+            ready[k] = Some(chunk);
+            while let Some(chunk) = ready.get_mut(next).and_then(Option::take) {
+                let k = next;
+                next += 1;
+                if at.at.start >= source.len() {
+                    // the chunk before ran over this one to the end of the input, lexing
+                    // again from there would only report the end of input a second time
+                    continue;
+                }
+                let chunk = if chunk.start == at {
+                    chunk
+                } else {
+                    lex_chunk::<T>(source, &at, stop(k), batch_size, hashes)
+                };
+                at = chunk.end;
+                diagnostics.extend(chunk.diagnostics);
+                if result.is_ok() {
+                    result = chunk.batches.into_iter().try_for_each(|b| sink.accept(b));
+                    if result.is_err() {
+                        // nobody is going to read the rest
+                        while queue.try_recv().is_ok() {}
+                    }
+                }
+            }
+        }
+    });
+    result?;
+    sink.finish()?;
+    Ok(diagnostics)
+}
+
+/// Lexes from `start` until no lexeme starts before `stop_at`
+fn lex_chunk<'a, T>(
+    source: &'a str,
+    start: &Boundary,
+    stop_at: usize,
+    batch_size: usize,
+    hashes: Option<&Arc<Mutex<HashTable>>>,
+) -> Chunk<'a, T>
+where
+    T: Lexable + Resolvable + Delimeted + Clone + Eq + Default,
+{
+    let mut lexer = Lexer::<T>::from_str(source);
+    lexer.batch_size = batch_size;
+    lexer.hashes = hashes.cloned();
+    lexer.stop_at = stop_at;
+    lexer.resume(start.at, &start.state);
+    let batches = lexer.batches().collect();
+    Chunk {
+        start: start.clone(),
+        end: Boundary {
+            at: lexer.mark(),
+            state: lexer.state.checkpoint(),
+        },
+        batches,
+        diagnostics: lexer.take_diagnostics(),
+    }
+}
+
+/// Finds a line start at or after each target offset and guesses the lexer state there. Only
+/// brackets outside of strings, chars and comments are counted, which is all the engine keeps
+/// across lines.
+fn prescan<T: Delimeted>(source: &str, targets: &[usize]) -> Vec<Boundary> {
+    let bytes = source.as_bytes();
+    let mut starts = vec![Boundary {
+        at: Span::default(),
+        state: Checkpoint::default(),
+    }];
+    let mut targets = targets.iter().peekable();
+    let (mut row, mut brace, mut paren) = (0usize, 0usize, 0usize);
+    let mut i = 0;
+
+    // bytes the scan has to stop at, everything else is skipped without looking closer
+    let comment_starts = comment_starts::<T>();
+    let string_starts = string_starts::<T>();
+    let mut stops = comment_starts;
+    for (stop, string) in stops.iter_mut().zip(string_starts) {
+        *stop |= string;
+    }
+    for b in b"'{}()\n\r" {
+        stops[*b as usize] = true;
+    }
+
+    // skips a char literal, which ends at the closing quote or the end of the line
+    let skip_quoted = |i: usize, quote: u8, row: &mut usize| {
+        let mut j = i + 1;
+        while let Some(&b) = bytes.get(j) {
+            match b {
+                b'\n' | b'\r' => break,
+                b'\\' => {
+                    if bytes.get(j + 1) == Some(&b'\n') {
+                        *row += 1;
+                    }
+                    j += 2;
+                }
+                _ if b == quote => return j + 1,
+                _ => j += 1,
+            }
+        }
+        j.min(bytes.len())
+    };
+
+    while i < bytes.len() {
+        let b = bytes[i];
+        if !stops[b as usize] {
+            i += 1;
+            continue;
+        }
+        if comment_starts[b as usize]
+            && let Some(style) = T::comment_at(&source[i..])
+        {
+            let len = style.measure(&source[i..]).unwrap_or_else(|len| len);
+            // a line comment leaves its terminator to the loop, it may be a chunk start
+            let len = match style {
+                CommentStyle::Line(_) => line_end(&bytes[..i + len], i) - i,
+                _ => len,
+            };
+            row += breaks(bytes, i..i + len).count();
+            i += len;
+            continue;
+        }
+        if string_starts[b as usize]
+            && let Some(open) = T::string_at(&source[i..])
+        {
+            // a single line literal stops before its newline, the loop takes it from there
+            let len = open.measure(&source[i..]).unwrap_or_else(|len| len);
+            row += breaks(bytes, i..i + len).count();
+            i += len;
+            continue;
+        }
+        match b {
+            b'\'' => {
+                i = skip_quoted(i, b, &mut row);
+                continue;
+            }
+            b'{' => brace += 1,
+            b'}' => brace = brace.saturating_sub(1),
+            b'(' => paren += 1,
+            b')' => paren = paren.saturating_sub(1),
+            // a lone `\r` ends a line too, chunks only start after a `\n`
+            b'\r' if bytes.get(i + 1) != Some(&b'\n') => row += 1,
+            b'\n' => {
+                row += 1;
+                if targets.next_if(|&&t| i + 1 >= t).is_some() {
+                    // a newline can pass several targets on short inputs
+                    while targets.next_if(|&&t| i + 1 >= t).is_some() {}
+                    starts.push(Boundary {
+                        at: Span {
+                            start: i + 1,
+                            end: i + 1,
+                            row,
+                            column: 0,
+                        },
+                        state: Checkpoint {
+                            in_paren: paren > 0,
+                            brace_level: brace,
+                            paren_level: paren,
+                            ..Checkpoint::default()
+                        },
+                    });
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    starts
+}
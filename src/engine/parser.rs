@@ -1,15 +1,433 @@
-use crate::engine::states::State;
+//! LALR(1) Parser (engine/parser.rs)
+//!
+//! Tables are built from a lowered grammar (see engine/grammar.rs) the textbook way: the LR(0)
+//! automaton first, then the lookaheads of its kernel items, found spontaneously or propagated
+//! from the item they came from until nothing changes. A state and lookahead that would need two
+//! actions is a conflict, reported with the items involved instead of resolved behind the
+//! grammar writer's back. The one exception is a shift/reduce conflict the grammar declared
+//! precedence for, on both the lookahead and the production: the tighter one wins, and on a tie
+//! left associativity reduces, right shifts and nonassoc makes the lookahead an error.
+//!
+//! The driver takes tokens of any tokenset and matches them to terminals by `Token::terminal`.
+//! Whitespace tokens are skipped. An `EOF` token only counts when the grammar asks for one, and
+//! the end of input stands in for it when the tokens come without. The result is a parse tree
+//! holding every token, with a node per production reduced.
+use crate::engine::errors::{Conflict, ConflictKind, GrammarError, ParseError};
+use crate::engine::grammar::{Assoc, First, Grammar, Lowered, Symbol};
+use crate::tokens::span::{Span, Spanned};
 use crate::tokens::token_traits::*;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
-pub enum Action<T: Lexable + Delimeted> {
-    Shift(State<T>),
-    Reduce(State<T>),
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// Push the token and go to the state
+    Shift(usize),
+    /// Pop the right hand side of the production
+    Reduce(usize),
     Accept,
     Error,
 }
 
-pub struct Parser<T: Lexable + Delimeted> {
-    action_table: HashMap<(State<T>, T::Token), Action<T>>,
-    goto_table: HashMap<(State<T>, T::Token), State<T>>,
+/// Lookahead of the items LALR lookaheads propagate from
+const PROPAGATE: usize = usize::MAX;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseTree<K> {
+    Token(Spanned<K>),
+    Node {
+        /// Nonterminal of the production, named in `Lowered::nonterminals`
+        symbol: usize,
+        production: usize,
+        children: Vec<ParseTree<K>>,
+    },
+}
+
+impl<K> ParseTree<K> {
+    /// First and last token of the tree, `None` for a node that matched nothing
+    pub fn tokens(&self) -> Option<(&Spanned<K>, &Spanned<K>)> {
+        match self {
+            ParseTree::Token(t) => Some((t, t)),
+            ParseTree::Node { children, .. } => {
+                let first = children.iter().find_map(|c| c.tokens())?.0;
+                let last = children.iter().rev().find_map(|c| c.tokens())?.1;
+                Some((first, last))
+            }
+        }
+    }
+}
+
+impl<K: Token> ParseTree<K> {
+    /// The tree as nested lists, `(Name children...)` with nodes named from `nonterminals` and
+    /// tokens spelled out
+    pub fn render(&self, nonterminals: &[impl AsRef<str>]) -> String {
+        match self {
+            ParseTree::Token(t) => t.token.spelling().into_owned(),
+            ParseTree::Node {
+                symbol, children, ..
+            } => {
+                let mut out = format!("({}", nonterminals[*symbol].as_ref());
+                for child in children {
+                    out.push(' ');
+                    out.push_str(&child.render(nonterminals));
+                }
+                out.push(')');
+                out
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Parser {
+    pub grammar: Lowered,
+    action_table: HashMap<(usize, usize), Action>,
+    goto_table: HashMap<(usize, usize), usize>,
+    /// Terminal for what `Token::terminal` gives
+    terminal_ids: HashMap<String, usize>,
+    states: usize,
+}
+
+impl Parser {
+    pub fn new(grammar: &Grammar) -> Result<Self, GrammarError> {
+        let grammar = grammar.lower()?;
+        let tables = Tables::new(&grammar);
+        let (kernels, goto) = tables.lr0();
+        let lookaheads = tables.lookaheads(&kernels, &goto);
+
+        let mut action_table: HashMap<(usize, usize), Action> = HashMap::new();
+        let mut goto_table = HashMap::new();
+        let mut conflicts: BTreeMap<(usize, usize, usize), Conflict> = BTreeMap::new();
+        // entries precedence settled, later shifts on them change nothing
+        let mut settled = HashSet::new();
+        for (state, kernel) in kernels.iter().enumerate() {
+            let items = tables.closure(
+                kernel
+                    .iter()
+                    .zip(&lookaheads[state])
+                    .flat_map(|(&(p, d), las)| las.iter().map(move |&a| (p, d, a))),
+            );
+            // reductions first so a shift on the same lookahead finds them
+            let mut items: Vec<_> = items.into_iter().collect();
+            items.sort_by_key(|&(p, d, a)| (d < grammar.productions[p].rhs.len(), p, d, a));
+            for (p, d, a) in items {
+                let (terminal, action) = match grammar.productions[p].rhs.get(d) {
+                    Some(&Symbol::Terminal(t)) => {
+                        (t, Action::Shift(goto[&(state, Symbol::Terminal(t))]))
+                    }
+                    Some(Symbol::Nonterminal(_)) => continue,
+                    None if p == 0 => (a, Action::Accept),
+                    None => (a, Action::Reduce(p)),
+                };
+                match action_table.get(&(state, terminal)) {
+                    None => {
+                        action_table.insert((state, terminal), action);
+                    }
+                    Some(&old) if old == action || settled.contains(&(state, terminal)) => {}
+                    Some(&old) => {
+                        if let (Action::Reduce(q), Action::Shift(_)) = (old, action)
+                            && let Some((reduced, _)) = grammar.production_precedence(q)
+                            && let Some((shifted, assoc)) = grammar.precedence[terminal]
+                        {
+                            let action = match (reduced.cmp(&shifted), assoc) {
+                                (Ordering::Greater, _) | (Ordering::Equal, Assoc::Left) => old,
+                                (Ordering::Less, _) | (Ordering::Equal, Assoc::Right) => action,
+                                (Ordering::Equal, Assoc::NonAssoc) => Action::Error,
+                            };
+                            action_table.insert((state, terminal), action);
+                            settled.insert((state, terminal));
+                            continue;
+                        }
+                        let kind = match (old, action) {
+                            (Action::Reduce(_), Action::Reduce(_)) => ConflictKind::ReduceReduce,
+                            _ => ConflictKind::ShiftReduce,
+                        };
+                        let conflict = conflicts
+                            .entry((state, terminal, kind as usize))
+                            .or_insert_with(|| Conflict {
+                                kind,
+                                state,
+                                lookahead: grammar.terminal_names[terminal].clone(),
+                                items: match old {
+                                    Action::Reduce(q) => vec![
+                                        grammar.item(q, Some(grammar.productions[q].rhs.len())),
+                                    ],
+                                    _ => Vec::new(),
+                                },
+                            });
+                        let item = grammar.item(p, Some(d));
+                        if !conflict.items.contains(&item) {
+                            conflict.items.push(item);
+                        }
+                    }
+                }
+            }
+        }
+        if !conflicts.is_empty() {
+            return Err(GrammarError::Conflicts(conflicts.into_values().collect()));
+        }
+        for (&(state, symbol), &to) in &goto {
+            if let Symbol::Nonterminal(n) = symbol {
+                goto_table.insert((state, n), to);
+            }
+        }
+        let terminal_ids = grammar
+            .terminals
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(i, t)| (t.clone(), i))
+            .collect();
+        Ok(Parser {
+            grammar,
+            action_table,
+            goto_table,
+            terminal_ids,
+            states: kernels.len(),
+        })
+    }
+
+    /// States of the automaton
+    pub fn states(&self) -> usize {
+        self.states
+    }
+
+    pub fn action(&self, state: usize, terminal: usize) -> Action {
+        self.action_table
+            .get(&(state, terminal))
+            .copied()
+            .unwrap_or(Action::Error)
+    }
+
+    pub fn goto(&self, state: usize, nonterminal: usize) -> Option<usize> {
+        self.goto_table.get(&(state, nonterminal)).copied()
+    }
+
+    /// Parses the whole stream into a tree rooted at the start symbol
+    pub fn parse<K: Token>(
+        &self,
+        tokens: impl IntoIterator<Item = Spanned<K>>,
+    ) -> Result<ParseTree<K>, ParseError> {
+        let eof = self.terminal_ids.get("EOF").copied();
+        let mut tokens = tokens
+            .into_iter()
+            .filter(|t| t.token.kind() != TokenKind::Whitespace);
+        let mut states = vec![0];
+        let mut values: Vec<Vec<ParseTree<K>>> = Vec::new();
+        let mut end = Span::default();
+        let mut seen_eof = false;
+        let mut lookahead = None;
+        loop {
+            if lookahead.is_none() {
+                lookahead = Some(loop {
+                    match tokens.next() {
+                        Some(t) if t.token == K::eof() => {
+                            end = t.span;
+                            if eof.is_some() && !seen_eof {
+                                seen_eof = true;
+                                break (eof, Some(t));
+                            }
+                        }
+                        Some(t) => {
+                            end = after(&t);
+                            break (
+                                self.terminal_ids.get(t.token.terminal().as_ref()).copied(),
+                                Some(t),
+                            );
+                        }
+                        None if eof.is_some() && !seen_eof => {
+                            seen_eof = true;
+                            break (eof, Some(Spanned::new(K::eof(), end)));
+                        }
+                        None => break (Some(0), None),
+                    }
+                });
+            }
+            let terminal = lookahead.as_ref().and_then(|l| l.0);
+            let state = *states.last().unwrap();
+            match terminal.map_or(Action::Error, |t| self.action(state, t)) {
+                Action::Shift(to) => {
+                    let (_, token) = lookahead.take().unwrap();
+                    states.push(to);
+                    values.push(token.map(ParseTree::Token).into_iter().collect());
+                }
+                Action::Reduce(p) => {
+                    let production = &self.grammar.productions[p];
+                    let at = values.len() - production.rhs.len();
+                    states.truncate(states.len() - production.rhs.len());
+                    let children: Vec<_> = values.drain(at..).flatten().collect();
+                    values.push(if production.inline {
+                        children
+                    } else {
+                        vec![ParseTree::Node {
+                            symbol: production.lhs,
+                            production: p,
+                            children,
+                        }]
+                    });
+                    let from = *states.last().unwrap();
+                    states.push(self.goto(from, production.lhs).expect("goto after reduce"));
+                }
+                Action::Accept => {
+                    return Ok(values
+                        .pop()
+                        .and_then(|mut v| v.pop())
+                        .expect("accepted tree"));
+                }
+                Action::Error => {
+                    let token = lookahead.and_then(|l| l.1);
+                    let span = token.as_ref().map_or(end, |t| t.span);
+                    return Err(ParseError {
+                        row: span.row,
+                        column: span.column,
+                        offset: span.start,
+                        found: token.as_ref().map(|t| t.token.spelling().into_owned()),
+                        expected: self.expected(state),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Terminals `state` has an action for
+    pub fn expected(&self, state: usize) -> Vec<String> {
+        (0..self.grammar.terminals.len())
+            .filter(|&t| self.action(state, t) != Action::Error)
+            .map(|t| self.grammar.terminal_names[t].clone())
+            .collect()
+    }
+
+    /// The tree as nested lists, `(Name children...)` with tokens spelled out
+    pub fn render<K: Token>(&self, tree: &ParseTree<K>) -> String {
+        tree.render(&self.grammar.nonterminals)
+    }
+}
+
+/// Where the input ends if `token` is the last of it. The column counts the chars of its
+/// spelling, which is the source text unless the token had escapes.
+pub(crate) fn after<K: Token>(token: &Spanned<K>) -> Span {
+    Span {
+        start: token.span.end,
+        end: token.span.end,
+        row: token.span.row,
+        column: token.span.column + token.token.spelling().chars().count(),
+    }
+}
+
+/// An item `(production, dot)` with a lookahead terminal
+type Item1 = (usize, usize, usize);
+/// Items `(production, dot)` a state starts from, sorted
+type Kernel = Vec<(usize, usize)>;
+/// State reached from a state over a symbol
+type Transitions = HashMap<(usize, Symbol), usize>;
+
+struct Tables<'g> {
+    grammar: &'g Lowered,
+    /// Productions of each nonterminal
+    by_lhs: Vec<Vec<usize>>,
+    first: First,
+}
+
+impl<'g> Tables<'g> {
+    fn new(grammar: &'g Lowered) -> Self {
+        let mut by_lhs = vec![Vec::new(); grammar.nonterminals.len()];
+        for (i, p) in grammar.productions.iter().enumerate() {
+            by_lhs[p.lhs].push(i);
+        }
+        Tables {
+            grammar,
+            by_lhs,
+            first: First::new(grammar),
+        }
+    }
+
+    /// Kernels of the LR(0) states and the transitions between them
+    fn lr0(&self) -> (Vec<Kernel>, Transitions) {
+        let mut kernels = vec![vec![(0, 0)]];
+        let mut ids = HashMap::from([(vec![(0, 0)], 0)]);
+        let mut goto = HashMap::new();
+        let mut state = 0;
+        while state < kernels.len() {
+            let items = self.closure(kernels[state].iter().map(|&(p, d)| (p, d, PROPAGATE)));
+            let mut next: BTreeMap<Symbol, BTreeSet<(usize, usize)>> = BTreeMap::new();
+            for (p, d, _) in items {
+                if let Some(&symbol) = self.grammar.productions[p].rhs.get(d) {
+                    next.entry(symbol).or_default().insert((p, d + 1));
+                }
+            }
+            for (symbol, kernel) in next {
+                let kernel: Vec<_> = kernel.into_iter().collect();
+                let to = *ids.entry(kernel.clone()).or_insert_with(|| {
+                    kernels.push(kernel);
+                    kernels.len() - 1
+                });
+                goto.insert((state, symbol), to);
+            }
+            state += 1;
+        }
+        (kernels, goto)
+    }
+
+    /// LR(1) closure of `items`
+    fn closure(&self, items: impl Iterator<Item = Item1>) -> HashSet<Item1> {
+        let mut seen: HashSet<Item1> = HashSet::new();
+        let mut work: Vec<Item1> = items.filter(|&i| seen.insert(i)).collect();
+        while let Some((p, d, a)) = work.pop() {
+            let rhs = &self.grammar.productions[p].rhs;
+            let Some(&Symbol::Nonterminal(n)) = rhs.get(d) else {
+                continue;
+            };
+            let (mut lookaheads, nullable) = self.first.of(&rhs[d + 1..]);
+            if nullable {
+                lookaheads.insert(a);
+            }
+            for &q in &self.by_lhs[n] {
+                for &b in &lookaheads {
+                    if seen.insert((q, 0, b)) {
+                        work.push((q, 0, b));
+                    }
+                }
+            }
+        }
+        seen
+    }
+
+    /// Lookaheads of every kernel item, in the order of `kernels`
+    fn lookaheads(&self, kernels: &[Kernel], goto: &Transitions) -> Vec<Vec<BTreeSet<usize>>> {
+        let mut lookaheads: Vec<Vec<BTreeSet<usize>>> = kernels
+            .iter()
+            .map(|k| vec![BTreeSet::new(); k.len()])
+            .collect();
+        lookaheads[0][0].insert(0);
+        let mut propagates = Vec::new();
+        for (state, kernel) in kernels.iter().enumerate() {
+            for (i, &(p, d)) in kernel.iter().enumerate() {
+                for (q, e, a) in self.closure(std::iter::once((p, d, PROPAGATE))) {
+                    let Some(&symbol) = self.grammar.productions[q].rhs.get(e) else {
+                        continue;
+                    };
+                    let to = goto[&(state, symbol)];
+                    let j = kernels[to]
+                        .binary_search(&(q, e + 1))
+                        .expect("advanced item in kernel");
+                    if a == PROPAGATE {
+                        propagates.push(((state, i), (to, j)));
+                    } else {
+                        lookaheads[to][j].insert(a);
+                    }
+                }
+            }
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &((s, i), (t, j)) in &propagates {
+                let from = lookaheads[s][i].clone();
+                let before = lookaheads[t][j].len();
+                lookaheads[t][j].extend(from);
+                changed |= lookaheads[t][j].len() != before;
+            }
+        }
+        lookaheads
+    }
 }
@@ -1,7 +1,8 @@
-///! Semantic Traits Interface (engine/semantic_traits.rs)
-///!
-///! Traits needed for the lexer engine to work, also future interfaces for the LookAhead (grammar
-///! prediction)
+//! Semantic Traits Interface (engine/semantic_traits.rs)
+//!
+//! Traits needed for the lexer engine to work, also future interfaces for the LookAhead (grammar
+//! prediction)
+use crate::engine::errors::LexError;
 use crate::tokens::token_traits::*;
 
 //trait LookAhead<T: Lexable + Delimeted> {
@@ -13,18 +14,20 @@ use crate::tokens::token_traits::*;
 //    fn try_match(&self, input: &str) -> Option<T::Token>;
 //}
 
-//// This
+// This
 pub trait Walker<T: Lexable + Delimeted> {
     fn bump(&mut self, ch: char);
-    fn tokenize(&mut self);
+    fn tokenize(&mut self) -> Result<(), LexError>;
     fn goto_next_sequence(&mut self, ch: char);
     fn skip_line(&mut self);
     fn eat_literal(&mut self);
     fn eat_to_newl(&mut self) -> String;
-    fn eat_str(&mut self);
+    /// `budget` is the most bytes the lexeme may take (`Limits::max_literal`), an eater stops
+    /// scanning once it is past it
+    fn eat_str(&mut self, open: OpenString, budget: usize);
     fn eat_char(&mut self);
-    fn eat_comment_block(&mut self, until: &[char]);
-    fn eat_comment_line(&mut self);
+    fn eat_comment_block(&mut self, style: &CommentStyle, budget: usize);
+    fn eat_comment_line(&mut self, budget: usize);
     fn eat_delimeter(&mut self, ch: char);
     fn eat_number(&mut self, ch: char);
 }
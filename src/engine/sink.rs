@@ -0,0 +1,144 @@
+//! Token Sinks (engine/sink.rs)
+//!
+//! Where the lexer delivers its batches when it is not iterated directly. Anything that can take
+//! a `Vec` of spanned tokens can be a sink: a Vec, a callback, a crossbeam channel (bounded or
+//! not), a writer, or the next stage of a pipeline. Sinks report failures instead of panicking,
+//! and decide how big the batches they get are.
+use crate::engine::errors::SinkError;
+use crate::tokens::span::Spanned;
+use crossbeam::channel::Sender;
+use std::fmt;
+use std::io::Write;
+
+/// Tokens per batch unless the sink asks for something else
+pub const BATCH_SIZE: usize = 2048;
+
+pub trait TokenSink<T> {
+    /// Takes one batch, in source order
+    fn accept(&mut self, batch: Vec<Spanned<T>>) -> Result<(), SinkError>;
+
+    /// Called once after the last batch
+    fn finish(&mut self) -> Result<(), SinkError> {
+        Ok(())
+    }
+
+    /// How many tokens the lexer should gather before calling `accept`
+    fn batch_size(&self) -> usize {
+        BATCH_SIZE
+    }
+
+    /// Same sink, fed batches of `size` tokens
+    fn batched(self, size: usize) -> Batched<Self>
+    where
+        Self: Sized,
+    {
+        Batched {
+            sink: self,
+            size: size.max(1),
+        }
+    }
+}
+
+impl<T> fmt::Debug for dyn TokenSink<T> + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenSink")
+            .field("batch_size", &self.batch_size())
+            .finish()
+    }
+}
+
+/// Collects everything, never fails
+impl<T> TokenSink<T> for Vec<Spanned<T>> {
+    fn accept(&mut self, batch: Vec<Spanned<T>>) -> Result<(), SinkError> {
+        if self.is_empty() {
+            *self = batch;
+        } else {
+            self.extend(batch);
+        }
+        Ok(())
+    }
+}
+
+/// Channel mode, the receiving side hanging up is reported as `Disconnected`. A bounded channel
+/// blocks the lexer until the consumer catches up.
+impl<T> TokenSink<T> for Sender<Vec<Spanned<T>>> {
+    fn accept(&mut self, batch: Vec<Spanned<T>>) -> Result<(), SinkError> {
+        self.send(batch).map_err(|_| SinkError::Disconnected)
+    }
+}
+
+impl<T, S: TokenSink<T> + ?Sized> TokenSink<T> for &mut S {
+    fn accept(&mut self, batch: Vec<Spanned<T>>) -> Result<(), SinkError> {
+        (**self).accept(batch)
+    }
+
+    fn finish(&mut self) -> Result<(), SinkError> {
+        (**self).finish()
+    }
+
+    fn batch_size(&self) -> usize {
+        (**self).batch_size()
+    }
+}
+
+impl<T, S: TokenSink<T> + ?Sized> TokenSink<T> for Box<S> {
+    fn accept(&mut self, batch: Vec<Spanned<T>>) -> Result<(), SinkError> {
+        (**self).accept(batch)
+    }
+
+    fn finish(&mut self) -> Result<(), SinkError> {
+        (**self).finish()
+    }
+
+    fn batch_size(&self) -> usize {
+        (**self).batch_size()
+    }
+}
+
+/// Wraps a closure, returning an error from it stops the lexer
+pub struct Callback<F>(pub F);
+
+impl<T, F> TokenSink<T> for Callback<F>
+where
+    F: FnMut(Vec<Spanned<T>>) -> Result<(), SinkError>,
+{
+    fn accept(&mut self, batch: Vec<Spanned<T>>) -> Result<(), SinkError> {
+        (self.0)(batch)
+    }
+}
+
+/// Writes one `span token` line per token, the format `--tokens` prints
+pub struct WriterSink<W: Write>(pub W);
+
+impl<T: fmt::Debug, W: Write> TokenSink<T> for WriterSink<W> {
+    fn accept(&mut self, batch: Vec<Spanned<T>>) -> Result<(), SinkError> {
+        for t in batch {
+            writeln!(self.0, "{} {:?}", t.span, t.token)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), SinkError> {
+        Ok(self.0.flush()?)
+    }
+}
+
+/// Overrides the batch size of the sink it wraps, see `TokenSink::batched`
+pub struct Batched<S> {
+    pub sink: S,
+    pub size: usize,
+}
+
+impl<T, S: TokenSink<T>> TokenSink<T> for Batched<S> {
+    fn accept(&mut self, batch: Vec<Spanned<T>>) -> Result<(), SinkError> {
+        self.sink.accept(batch)
+    }
+
+    fn finish(&mut self) -> Result<(), SinkError> {
+        self.sink.finish()
+    }
+
+    fn batch_size(&self) -> usize {
+        self.size
+    }
+}
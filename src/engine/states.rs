@@ -1,35 +1,45 @@
-///! State Machine Fields (engine/states.rs)
-///!
-///! This file contains the struct definition for the state machine, as well as the defaults for
-///! when we havent started to lex yet.
+//! State Machine Fields (engine/states.rs)
+//!
+//! This file contains the struct definition for the state machine, as well as the defaults for
+//! when we havent started to lex yet.
 use crate::{
     engine::errors::LexicalError,
-    tokens::token_traits::{Delimeted, Lexable},
+    tokens::token_traits::{Delimeted, Lexable, OpenString},
 };
+use std::marker::PhantomData;
 
 #[derive(Debug)]
 pub struct State<T: Delimeted + Lexable> {
     pub valid: bool,
     pub row: usize,
     pub column: usize,
+    /// Byte offset of the cursor in the source, used to build token spans
+    pub offset: usize,
     pub in_str: bool,
     pub in_char: bool,
     pub in_brace: bool,
     pub in_paren: bool,
     pub scape_next: bool,
     pub brace_level: usize,
+    /// Open `<` of type arguments, tokensets that have generics keep it
     pub generic_level: usize,
+    /// The last token could name a type, so a `<` right after it opens type arguments
+    pub after_type: bool,
     pub paren_level: usize,
     pub read_include: bool,
-    pub stacktrace: Option<Vec<LexicalError<T>>>,
+    /// Sub-languages entered and not left yet, innermost last. Empty is plain code
+    pub modes: Vec<Mode>,
+    pub stacktrace: Option<Vec<LexicalError>>,
+    pub lang: PhantomData<T>,
 }
 
-impl<'a, T: Delimeted + Lexable> Default for State<T> {
+impl<T: Delimeted + Lexable> Default for State<T> {
     fn default() -> Self {
         Self {
             valid: true,
             row: 0,
             column: 0,
+            offset: 0,
             in_str: false,
             in_char: false,
             in_brace: false,
@@ -38,8 +48,111 @@ impl<'a, T: Delimeted + Lexable> Default for State<T> {
             brace_level: 0,
             paren_level: 0,
             generic_level: 0,
+            after_type: false,
             read_include: false,
+            modes: Vec::new(),
             stacktrace: Some(Vec::new()),
+            lang: PhantomData,
         }
     }
 }
+
+impl<T: Delimeted + Lexable> State<T> {
+    /// Records a lexical error and marks the run as invalid, lexing keeps going
+    pub fn report(&mut self, err: LexicalError) {
+        self.valid = false;
+        self.stacktrace.get_or_insert_with(Vec::new).push(err);
+    }
+
+    /// The innermost mode, `None` in plain code
+    pub fn mode(&self) -> Option<&Mode> {
+        self.modes.last()
+    }
+
+    pub fn push_mode(&mut self, mode: Mode) {
+        self.modes.push(mode);
+    }
+
+    pub fn pop_mode(&mut self) -> Option<Mode> {
+        self.modes.pop()
+    }
+}
+
+/// A sub-language the lexer is in. The engine drives `Str` and `Embed` for template strings:
+/// reaching an embedded expression it pushes the rest of the string and then the expression,
+/// lexes the expression as code, and at its closer pops back into the string. Every step of
+/// that emits one token at most, so a checkpoint after any token is a place to resume from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// The rest of a string, lexed as soon as it is on top again
+    Str(OpenString),
+    /// An embedded expression, it ends at `close` once the braces opened inside are closed.
+    /// `pending` is its opener while that is still ahead of the cursor.
+    Embed {
+        pending: Option<&'static str>,
+        close: &'static str,
+        brace_level: usize,
+    },
+    /// Modes a tokenset pushes for itself from `infer_token` or `embed_marker`, the engine
+    /// carries them along and never looks inside
+    Custom(&'static str),
+}
+
+/// The part of the State that decides how the text after a token is lexed, position aside.
+/// Two runs that agree on it at the same place of the same text produce the same tokens from
+/// there on, which is what incremental re-lexing resyncs on.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Checkpoint {
+    pub in_str: bool,
+    pub in_char: bool,
+    pub in_paren: bool,
+    pub brace_level: usize,
+    pub generic_level: usize,
+    pub after_type: bool,
+    pub paren_level: usize,
+    pub read_include: bool,
+    pub modes: Vec<Mode>,
+}
+
+impl Checkpoint {
+    /// Whether the state already depends on bytes past the cursor: a string parked at the
+    /// opener of an embedded expression has seen the opener. Resuming from it is only right if
+    /// those bytes are still there.
+    pub fn looks_ahead(&self) -> bool {
+        matches!(
+            self.modes.last(),
+            Some(Mode::Embed {
+                pending: Some(_),
+                ..
+            })
+        )
+    }
+}
+
+impl<T: Delimeted + Lexable> State<T> {
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            in_str: self.in_str,
+            in_char: self.in_char,
+            in_paren: self.in_paren,
+            brace_level: self.brace_level,
+            generic_level: self.generic_level,
+            after_type: self.after_type,
+            paren_level: self.paren_level,
+            read_include: self.read_include,
+            modes: self.modes.clone(),
+        }
+    }
+
+    pub fn restore(&mut self, c: &Checkpoint) {
+        self.in_str = c.in_str;
+        self.in_char = c.in_char;
+        self.in_paren = c.in_paren;
+        self.brace_level = c.brace_level;
+        self.generic_level = c.generic_level;
+        self.after_type = c.after_type;
+        self.paren_level = c.paren_level;
+        self.read_include = c.read_include;
+        self.modes.clone_from(&c.modes);
+    }
+}
@@ -0,0 +1,180 @@
+//! Streaming Input (engine/stream.rs)
+//!
+//! Lexes from any `std::io::Read` without holding the whole input: pipes, sockets, decompressing
+//! readers, files bigger than memory. The input is read in windows of about `window` bytes and
+//! each window is lexed up to its last complete line, like a chunk of parallel lexing. What is
+//! left of it moves to the front of the next window and lexing resumes there from the State
+//! checkpoint the window ended in. Tokens reach the sink as soon as their window is done.
+//!
+//! A window without a line break is lexed to its end. When the last lexeme is still open at
+//! the end of a window (a long comment or text block), the tokens before it go out and the
+//! next window starts where it does, from the checkpoint the lexer had there. A lexeme that
+//! fills a whole window makes it grow, by its size but never by more than `max_literal` at a
+//! time, so one lexeme longer than `window` is held whole and Limits bound it (see
+//! engine/limits.rs). Spans and diagnostics are positions in the whole input.
+//!
+//! Imports are not expanded, the tokens of another file have no place in these spans. Input
+//! is UTF-8, anything else goes through a `Transcode` (see engine/encoding.rs) first.
+use crate::engine::errors::{LexError, LexicalError};
+use crate::engine::lexer::Lexer;
+use crate::engine::limits::Limits;
+use crate::engine::normalize::HashTable;
+use crate::engine::sink::TokenSink;
+use crate::engine::states::Checkpoint;
+use crate::tokens::lines::breaks;
+use crate::tokens::span::Span;
+use crate::tokens::token_traits::*;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Bytes read ahead of the lexer unless asked otherwise
+pub const WINDOW: usize = 1 << 16;
+
+/// Lexes everything `input` yields into `sink`, reading `window` bytes at a time, and returns
+/// the diagnostics. `limits` apply to the whole run, identifier hashes are checked against
+/// `hashes` when given (see engine/normalize.rs).
+pub fn tokenize_reader<T>(
+    mut input: impl Read,
+    window: usize,
+    limits: &Limits,
+    hashes: Option<&Arc<Mutex<HashTable>>>,
+    sink: &mut dyn TokenSink<T::Token<'static>>,
+) -> Result<Vec<LexicalError>, LexError>
+where
+    T: Lexable + Resolvable + Delimeted + Clone + Eq + Default,
+{
+    let window = window.max(1);
+    let mut text = String::new();
+    // bytes of a char the last read cut in two
+    let mut partial = Vec::new();
+    // offset of `text` in the input
+    let mut base = 0;
+    let mut at = Span::default();
+    let mut state = Checkpoint::default();
+    let mut limits = limits.clone();
+    let mut diagnostics = Vec::new();
+    let mut eof = false;
+    let mut want = window;
+    let deadline = limits.timeout.map(|t| Instant::now() + t);
+    loop {
+        while !eof && text.len() < want {
+            let n = want - text.len();
+            eof = fill(&mut input, &mut text, &mut partial, n).map_err(LexError::Input)?;
+        }
+        // lexemes may start up to the last line that has bytes after it, the lexer has seen
+        // what ends each of them once it stops before the end of the window
+        let cut = if eof {
+            text.len()
+        } else {
+            let bytes = text.as_bytes();
+            breaks(bytes, 0..bytes.len() - 1)
+                .last()
+                .unwrap_or(bytes.len())
+        };
+        let mut lexer = Lexer::<T>::from_str(&text);
+        lexer.resolve_imports = false;
+        lexer.batch_size = 1;
+        lexer.limits = limits.clone();
+        lexer.deadline = deadline;
+        lexer.hashes = hashes.cloned();
+        lexer.stop_at = cut;
+        lexer.resume(at, &state);
+        // tokens lexed so far, and where the last one that ends inside the window ends, with
+        // the diagnostics reported up to there
+        let mut tokens = Vec::new();
+        let mut complete = (0, at, state.clone(), 0);
+        while let Some(mut t) = lexer.next() {
+            t.span.start += base;
+            t.span.end += base;
+            tokens.push(t.map(T::into_owned));
+            let end = lexer.mark();
+            if end.start < text.len() {
+                let reported = lexer.diagnostics().len();
+                complete = (tokens.len(), end, lexer.state.checkpoint(), reported);
+            }
+        }
+        let stopped = lexer.stopped().cloned();
+        let end = lexer.mark();
+        // the last lexeme may go on in bytes not read yet, it is lexed again with them
+        let open = !eof && end.start >= text.len() && stopped.is_none();
+        let mut errors = lexer.take_diagnostics();
+        let (kept, resume, after) = if open {
+            // what the lexemes lexed again report comes back with them
+            let (kept, resume, after, reported) = complete;
+            errors.truncate(reported);
+            (kept, resume, after)
+        } else {
+            (tokens.len(), end, lexer.state.checkpoint())
+        };
+        diagnostics.extend(errors.into_iter().map(|mut e| {
+            e.offset += base;
+            e
+        }));
+        drop(lexer);
+        tokens.truncate(kept);
+        let batch_size = sink.batch_size().max(1);
+        let mut tokens = tokens.into_iter().peekable();
+        while tokens.peek().is_some() {
+            sink.accept(tokens.by_ref().take(batch_size).collect())?;
+        }
+        if let Some(e) = stopped {
+            return Err(e.into());
+        }
+        if eof {
+            break;
+        }
+        limits.max_tokens = limits.max_tokens.saturating_sub(kept);
+
+        text.drain(..resume.start);
+        base += resume.start;
+        at = Span {
+            start: 0,
+            end: 0,
+            ..resume
+        };
+        state = after;
+        want = match resume.start {
+            // open since the start of the window
+            0 => text.len() + text.len().clamp(window, limits.max_literal.max(window)),
+            _ => text.len() + window,
+        };
+    }
+    sink.finish()?;
+    Ok(diagnostics)
+}
+
+/// Reads up to `n` more bytes into `text`. A char split by the read waits in `partial` for the
+/// rest of its bytes. True once the input is exhausted.
+fn fill(
+    input: &mut impl Read,
+    text: &mut String,
+    partial: &mut Vec<u8>,
+    n: usize,
+) -> io::Result<bool> {
+    let start = partial.len();
+    partial.resize(start + n, 0);
+    let read = loop {
+        match input.read(&mut partial[start..]) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                partial.truncate(start);
+                return Err(e);
+            }
+            Ok(read) => break read,
+        }
+    };
+    partial.truncate(start + read);
+    let valid = match std::str::from_utf8(partial) {
+        Ok(s) => s,
+        // only the last char may be incomplete, and only while more bytes can come
+        Err(e) if e.error_len().is_none() && read > 0 => {
+            std::str::from_utf8(&partial[..e.valid_up_to()]).unwrap_or_default()
+        }
+        Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+    };
+    text.push_str(valid);
+    let valid = valid.len();
+    partial.drain(..valid);
+    Ok(read == 0)
+}
@@ -1,6 +1,6 @@
 use crate::langs::java::tokenset::*;
-use crate::tokens::token_traits::Delimeted;
-impl Delimeted for JavaTokenSet {
+use crate::tokens::token_traits::{CommentStyle, Delimeted, StringStyle};
+impl<const TEMPLATES: bool> Delimeted for JavaTokenSet<TEMPLATES> {
     #[inline(always)]
     fn allowed_unicode_char(c: &char) -> Option<usize> {
         match c {
@@ -21,27 +21,63 @@ impl Delimeted for JavaTokenSet {
             '\'' => Some('\''),
             '\"' => Some('\"'),
             '\\' => Some('\\'),
+            's' => Some(' '),
+            '0' => Some('\0'),
             _ => None,
         }
     }
 
     #[inline(always)]
-    fn may_trigger_line_comment(c: char) -> Option<usize> {
-        if c == '/' { Some(2) } else { None }
+    fn comments() -> &'static [CommentStyle] {
+        &[
+            CommentStyle::Line("//"),
+            CommentStyle::Block {
+                open: "/*",
+                close: "*/",
+                nested: false,
+            },
+        ]
     }
 
     #[inline(always)]
-    fn trigger_comment_line(input: &[char]) -> bool {
-        input == ['/', '/']
-    }
-
-    #[inline(always)]
-    fn may_trigger_block_comment(c: char) -> Option<(Vec<char>, usize)> {
-        if c == '/' {
-            Some((vec!['*', '/'], 2))
-        } else {
-            None
+    fn strings() -> &'static [StringStyle] {
+        if !TEMPLATES {
+            return &[
+                StringStyle::Quoted {
+                    open: "\"",
+                    close: "\"",
+                    escapes: true,
+                    multiline: false,
+                },
+                // text blocks
+                StringStyle::Quoted {
+                    open: "\"\"\"",
+                    close: "\"\"\"",
+                    escapes: true,
+                    multiline: true,
+                },
+            ];
         }
+        &[
+            // `\{` was never a valid escape, so every string can be a template
+            StringStyle::Template {
+                open: "\"",
+                close: "\"",
+                embed: "\\{",
+                embed_close: "}",
+                escapes: true,
+                multiline: false,
+            },
+            // text blocks
+            StringStyle::Template {
+                open: "\"\"\"",
+                close: "\"\"\"",
+                embed: "\\{",
+                embed_close: "}",
+                escapes: true,
+                multiline: true,
+            },
+        ]
     }
 
     #[inline(always)]
@@ -50,85 +86,85 @@ impl Delimeted for JavaTokenSet {
     }
 
     #[inline(always)]
-    fn is_operator(s: &[u8]) -> bool {
+    fn is_operator(s: &str) -> bool {
         matches!(
             s,
-            b"!" | b"."
-                | b"@"
-                | b">"
-                | b">="
-                | b"<"
-                | b"<="
-                | b">>"
-                | b"<<"
-                | b">>>"
-                | b">>="
-                | b"<<="
-                | b">>>="
-                | b"+"
-                | b"-"
-                | b"*"
-                | b"="
-                | b"=="
-                | b"/"
-                | b"+="
-                | b"-="
-                | b"*="
-                | b"/="
-                | b"%"
-                | b"%="
-                | b"++"
-                | b"--"
-                | b"&"
-                | b"&="
-                | b"^"
-                | b"^="
-                | b"~"
-                | b"|"
-                | b"|="
-                | b"!="
-                | b"&&"
-                | b"||"
-                | b"?"
-                | b"instanceof"
+            "!" | "."
+                | "@"
+                | ">"
+                | ">="
+                | "<"
+                | "<="
+                | ">>"
+                | "<<"
+                | ">>>"
+                | ">>="
+                | "<<="
+                | ">>>="
+                | "+"
+                | "-"
+                | "*"
+                | "="
+                | "=="
+                | "/"
+                | "+="
+                | "-="
+                | "*="
+                | "/="
+                | "%"
+                | "%="
+                | "++"
+                | "--"
+                | "&"
+                | "&="
+                | "^"
+                | "^="
+                | "~"
+                | "|"
+                | "|="
+                | "!="
+                | "&&"
+                | "||"
+                | "?"
+                | "instanceof"
         )
     }
 
     #[inline(always)]
-    fn trigger_comment_block(input: &[char]) -> bool {
-        input == ['/', '*']
-    }
-
-    #[inline(always)]
-    fn is_delimeter(c: &u8) -> bool {
+    fn is_delimeter(c: &char) -> bool {
         matches!(
             c,
-            b'+' | b'-'
-                | b'<'
-                | b'>'
-                | b'='
-                | b'!'
-                | b'|'
-                | b'*'
-                | b'~'
-                | b'^'
-                | b')'
-                | b'('
-                | b'}'
-                | b'{'
-                | b'['
-                | b']'
-                | b','
-                | b';'
-                | b'/'
-                | b':'
-                | b'?'
-                | b'&'
-                | b'.'
-                | b'%'
-                | b' '
-                | b'\t'
-                | b'\n'
+            '+' | '-'
+                | '<'
+                | '>'
+                | '='
+                | '!'
+                | '|'
+                | '*'
+                | '~'
+                | '^'
+                | ')'
+                | '('
+                | '}'
+                | '{'
+                | '['
+                | ']'
+                | ','
+                | ';'
+                | '/'
+                | ':'
+                | '?'
+                | '&'
+                | '.'
+                | '%'
+                | ' '
+                | '\t'
+                | '\n'
         )
     }
+
+    #[inline(always)]
+    fn ident_extras() -> &'static [char] {
+        &['$', '_']
+    }
 }
@@ -1,18 +1,48 @@
-use crate::engine::lexer::Lexer;
-use crate::engine::semantic_traits::Walker;
+use crate::engine::encoding::decode;
+use crate::engine::errors::LexError;
+use crate::engine::lexer::{ImportScope, Lexer};
+use crate::engine::sink::TokenSink;
 use crate::langs::java::tokenset::JavaTokenSet;
-use crate::tokens::token_traits::Resolvable;
+use crate::tokens::token_traits::{Lexable, Resolvable};
 use memmap2::Mmap;
 use std::env;
-use std::fs::{File, read_dir};
+use std::fs::{File, canonicalize, read_dir};
 use std::path::{Path, PathBuf};
 
 use super::tokenset::JavaToken;
 
-impl Resolvable for JavaTokenSet {
-    fn resolve_import(name: String, sender: crossbeam::channel::Sender<Vec<JavaToken>>) {
+/// Lexes an imported file and forwards its tokens detached from the file buffer, which is
+/// unmapped as soon as this returns. A file already being imported further up is skipped.
+fn lex_detached<'a, const TEMPLATES: bool>(
+    path: &Path,
+    source_code: &str,
+    scope: &ImportScope,
+    sink: &mut dyn TokenSink<JavaToken<'a>>,
+) -> Result<(), LexError> {
+    let path = canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let Some(mut lexer) = Lexer::<JavaTokenSet<TEMPLATES>>::for_import(source_code, &path, scope)
+    else {
+        return Ok(());
+    };
+    lexer.batch_size = sink.batch_size();
+    for batch in lexer.batches() {
+        let batch = batch
+            .into_iter()
+            .map(|t| t.map(JavaToken::into_owned))
+            .collect();
+        sink.accept(batch)?;
+    }
+    lexer.stopped().cloned().map_or(Ok(()), |e| Err(e.into()))
+}
+
+impl<const TEMPLATES: bool> Resolvable for JavaTokenSet<TEMPLATES> {
+    fn resolve_import<'a>(
+        name: String,
+        scope: &ImportScope,
+        sink: &mut dyn TokenSink<<Self as Lexable>::Token<'a>>,
+    ) -> Result<(), LexError> {
         if name.starts_with("java") || name.starts_with("javax") {
-            return;
+            return Ok(());
         }
         let name = name.replace('.', "/") + ".java";
         #[cfg(windows)]
@@ -39,18 +69,14 @@ impl Resolvable for JavaTokenSet {
             if let Ok(entries) = read_dir(dir_path) {
                 for file in entries.flatten() {
                     let path = file.path();
-                    if let Some(ext) = path.extension() {
-                        if ext == "java" {
-                            if let Ok(file) = File::open(&path) {
-                                if let Ok(mmap) = unsafe { Mmap::map(&file) } {
-                                    if let Ok(source_code) = std::str::from_utf8(&mmap) {
-                                        let mut lexer =
-                                            Lexer::<JavaTokenSet>::new(source_code, sender.clone());
-                                        lexer.tokenize();
-                                    }
-                                }
-                            }
-                        }
+                    if path.extension().is_none_or(|ext| ext != "java") {
+                        continue;
+                    }
+                    if let Ok(file) = File::open(&path)
+                        && let Ok(mmap) = unsafe { Mmap::map(&file) }
+                        && let Ok(decoded) = decode(&mmap, None, true)
+                    {
+                        lex_detached::<TEMPLATES>(&path, &decoded.text, scope, sink)?;
                     }
                 }
             } else {
@@ -60,15 +86,16 @@ impl Resolvable for JavaTokenSet {
         for path in &classpath {
             let full_path = PathBuf::from(path).join(&name);
 
-            if let Ok(file) = File::open(&full_path) {
-                if let Ok(mmap) = unsafe { Mmap::map(&file) } {
-                    let source_code = std::str::from_utf8(&mmap).unwrap_or_default();
-
-                    let mut lexer = Lexer::<JavaTokenSet>::new(source_code, sender.clone());
-                    lexer.tokenize();
-                    break;
+            if let Ok(file) = File::open(&full_path)
+                && let Ok(mmap) = unsafe { Mmap::map(&file) }
+            {
+                // legacy sources are often Latin-1, whatever does not decode is replaced
+                if let Ok(decoded) = decode(&mmap, None, true) {
+                    lex_detached::<TEMPLATES>(&full_path, &decoded.text, scope, sink)?;
                 }
+                break;
             }
         }
+        Ok(())
     }
 }
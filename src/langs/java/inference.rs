@@ -1,13 +1,35 @@
-use crate::engine::normalize::normalize;
-use crate::engine::states::State;
+use crate::engine::interner::Ident;
+use crate::engine::states::{Mode, State};
 use crate::langs::java::tokenset::*;
 use crate::tokens::token_traits::{Delimeted, Lexable};
 use std::borrow::Cow;
 
-impl Lexable for JavaTokenSet {
-    type Token = JavaToken;
-    fn infer_token(s: String, state: &mut State<Self>) -> Option<Self::Token> {
-        let raw = s.as_str();
+impl<const TEMPLATES: bool> Lexable for JavaTokenSet<TEMPLATES> {
+    type Token<'a> = JavaToken<'a>;
+
+    fn into_owned<'a, 'b>(token: JavaToken<'a>) -> JavaToken<'b> {
+        token.into_owned()
+    }
+
+    /// Inside type arguments a `>` run closes one list per `>`, `>>` is never a shift there
+    fn operator_len(op: &str, state: &State<Self>) -> usize {
+        if state.generic_level > 0 && op.starts_with('>') {
+            1
+        } else {
+            op.len()
+        }
+    }
+
+    fn embed_marker<'a>(_: Cow<'a, str>, state: &mut State<Self>) -> Option<Self::Token<'a>> {
+        Some(JavaToken::Delimeter(match state.mode() {
+            Some(Mode::Embed { .. }) => JavaDelimeters::EmbedStart,
+            _ => JavaDelimeters::EmbedEnd,
+        }))
+    }
+
+    fn infer_token<'a>(s: Cow<'a, str>, state: &mut State<Self>) -> Option<Self::Token<'a>> {
+        let raw: &str = &s;
+        let after_type = std::mem::take(&mut state.after_type);
         if state.in_char {
             return Some(JavaToken::Identifier(JavaIdentifier::CharLiteral(s)));
         }
@@ -15,7 +37,20 @@ impl Lexable for JavaTokenSet {
             return Some(JavaToken::Identifier(JavaIdentifier::StringLiteral(s)));
         }
 
-        if JavaTokenSet::is_operator(s.as_bytes()) {
+        if Self::is_operator(&s) {
+            match raw {
+                "<" if after_type => {
+                    state.generic_level += 1;
+                    return Some(JavaToken::Delimeter(JavaDelimeters::LAngle));
+                }
+                ">" if state.generic_level > 0 => {
+                    state.generic_level -= 1;
+                    return Some(JavaToken::Delimeter(JavaDelimeters::RAngle));
+                }
+                // wildcards, qualified names, bounds and annotations can sit in type arguments
+                "?" | "." | "&" | "@" => {}
+                _ => state.generic_level = 0,
+            }
             return Some(match s.as_bytes() {
                 b"!" => JavaToken::Operator(JavaOperator::Not),
                 b"." => JavaToken::Operator(JavaOperator::Dot),
@@ -56,35 +91,41 @@ impl Lexable for JavaTokenSet {
                 b"||" => JavaToken::Operator(JavaOperator::Or),
                 b"?" => JavaToken::Operator(JavaOperator::Qmark),
                 b"instanceof" => JavaToken::Operator(JavaOperator::Instanceof),
-                _ => JavaToken::Identifier(JavaIdentifier::Unknown(normalize(
-                    &s.as_bytes(),
+                _ => JavaToken::Identifier(JavaIdentifier::Unknown(Ident::new(
+                    raw,
                     state.brace_level,
                 ))),
             });
         }
 
-        if let Some(&first) = s.as_bytes().first() {
-            if JavaTokenSet::is_delimeter(&first) {
-                return Some(match s.as_bytes() {
-                    b":" => JavaToken::Delimeter(JavaDelimeters::Colon),
-                    b"," => JavaToken::Delimeter(JavaDelimeters::Comma),
-                    b";" => JavaToken::Delimeter(JavaDelimeters::Semicolon),
-                    b")" => JavaToken::Delimeter(JavaDelimeters::Rparen),
-                    b"(" => JavaToken::Delimeter(JavaDelimeters::LParen),
-                    b"{" => JavaToken::Delimeter(JavaDelimeters::LBrace),
-                    b"}" => JavaToken::Delimeter(JavaDelimeters::RBrace),
-                    b"[" => JavaToken::Delimeter(JavaDelimeters::LBracket),
-                    b"]" => JavaToken::Delimeter(JavaDelimeters::RBracket),
-                    b" " => JavaToken::Delimeter(JavaDelimeters::Whitespace),
-                    b"\t" => JavaToken::Delimeter(JavaDelimeters::Tab),
-                    b"\n" => JavaToken::Delimeter(JavaDelimeters::NewLine),
-                    _ => JavaToken::Identifier(JavaIdentifier::Unknown(normalize(
-                        &s.as_bytes(),
-                        state.brace_level,
-                    ))),
-                });
+        if let Some(first) = s.chars().next()
+            && Self::is_delimeter(&first)
+        {
+            match raw {
+                ";" | "(" | ")" | "{" | "}" => state.generic_level = 0,
+                " " | "\t" => state.after_type = after_type,
+                _ => {}
             }
+            return Some(match s.as_bytes() {
+                b":" => JavaToken::Delimeter(JavaDelimeters::Colon),
+                b"," => JavaToken::Delimeter(JavaDelimeters::Comma),
+                b";" => JavaToken::Delimeter(JavaDelimeters::Semicolon),
+                b")" => JavaToken::Delimeter(JavaDelimeters::Rparen),
+                b"(" => JavaToken::Delimeter(JavaDelimeters::LParen),
+                b"{" => JavaToken::Delimeter(JavaDelimeters::LBrace),
+                b"}" => JavaToken::Delimeter(JavaDelimeters::RBrace),
+                b"[" => JavaToken::Delimeter(JavaDelimeters::LBracket),
+                b"]" => JavaToken::Delimeter(JavaDelimeters::RBracket),
+                b" " => JavaToken::Delimeter(JavaDelimeters::Whitespace),
+                b"\t" => JavaToken::Delimeter(JavaDelimeters::Tab),
+                b"\n" => JavaToken::Delimeter(JavaDelimeters::NewLine),
+                _ => JavaToken::Identifier(JavaIdentifier::Unknown(Ident::new(
+                    raw,
+                    state.brace_level,
+                ))),
+            });
         }
+        state.after_type = state.generic_level > 0 || names_type(raw);
         match s.as_bytes() {
             b"boolean" => {
                 return Some(JavaToken::Identifier(JavaIdentifier::JavaKeyword(
@@ -342,7 +383,9 @@ impl Lexable for JavaTokenSet {
             }
             _ => {} // fall through
         }
-        if !state.in_str && !state.in_char {
+        // `_` starts identifiers as well, only a digit starts a number
+        if !state.in_str && !state.in_char && raw.as_bytes().first().is_some_and(u8::is_ascii_digit)
+        {
             let mut is_float = false;
             let mut all_digits = true;
 
@@ -366,7 +409,7 @@ impl Lexable for JavaTokenSet {
                 };
 
                 if let Ok(f) = cleaned.parse::<f64>() {
-                    return Some(JavaToken::Identifier(JavaIdentifier::Float(f)));
+                    return Some(JavaToken::Identifier(JavaIdentifier::Float(s, f)));
                 }
             } else if all_digits {
                 return Some(JavaToken::Identifier(JavaIdentifier::Integer(
@@ -392,9 +435,21 @@ impl Lexable for JavaTokenSet {
                 )));
             }
         }
-        Some(JavaToken::Identifier(JavaIdentifier::Unknown(normalize(
-            &s.as_bytes(),
+        Some(JavaToken::Identifier(JavaIdentifier::Unknown(Ident::new(
+            raw,
             state.brace_level,
         ))))
     }
 }
+
+/// Java names types in UpperCamelCase, an upper case first letter is taken for a type unless
+/// the whole name is upper case, which is how constants are named (`MAX < 3`).
+fn names_type(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) if first.is_uppercase() => {
+            name.len() == first.len_utf8() || chars.any(|c| c.is_lowercase())
+        }
+        _ => false,
+    }
+}
@@ -1,17 +1,29 @@
+use crate::engine::interner::Ident;
+use crate::tokens::printer::{Spacing, quote};
+use crate::tokens::span::Spanned;
 use crate::tokens::token_traits::{Token, TokenKind};
+use std::borrow::Cow;
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum JavaToken {
-    Identifier(JavaIdentifier),
+pub enum JavaToken<'a> {
+    Identifier(JavaIdentifier<'a>),
     Operator(JavaOperator),
     Delimeter(JavaDelimeters),
     EOF,
 }
 
+/// What the lexer actually emits: a JavaToken plus its location in the source
+pub type JavaSpannedToken<'a> = Spanned<JavaToken<'a>>;
+
+/// Java as the JDK ships it. `TEMPLATES` turns on the string templates of the JDK 21 and 22
+/// previews (`"a \{b} c"`), removed again in JDK 23; without them `\{` is a bad escape.
 #[derive(Debug, Clone, PartialEq, Eq, Default, Hash)]
-pub struct JavaTokenSet;
+pub struct JavaTokenSet<const TEMPLATES: bool = false>;
+
+/// Java with the string template preview
+pub type JavaTemplateTokenSet = JavaTokenSet<true>;
 
-impl Token for JavaToken {
+impl Token for JavaToken<'_> {
     fn kind(&self) -> TokenKind {
         match self {
             JavaToken::EOF => TokenKind::Delimeter,
@@ -27,6 +39,109 @@ impl Token for JavaToken {
             JavaToken::Delimeter(_) => TokenKind::Delimeter,
         }
     }
+
+    fn eof() -> Self {
+        JavaToken::EOF
+    }
+
+    /// `String` lexes as the `class` keyword and prints as one. `Var` and `ObjVar` are never
+    /// emitted and print as nothing.
+    fn spelling(&self) -> Cow<'_, str> {
+        self.spelling_between(None, None)
+    }
+
+    fn spelling_between(&self, prev: Option<&Self>, next: Option<&Self>) -> Cow<'_, str> {
+        let embed = |t: Option<&Self>, d| t == Some(&JavaToken::Delimeter(d));
+        match self {
+            JavaToken::EOF => Cow::Borrowed(""),
+            JavaToken::Operator(op) => Cow::Borrowed(op.as_str()),
+            JavaToken::Delimeter(d) => Cow::Borrowed(d.as_str()),
+            JavaToken::Identifier(id) => match id {
+                JavaIdentifier::Var(_, _) | JavaIdentifier::ObjVar(_, _) => Cow::Borrowed(""),
+                // a template fragment has no quote on the side of an embedded expression
+                JavaIdentifier::StringLiteral(s) => Cow::Owned(quote::<JavaTokenSet>(
+                    s,
+                    if embed(prev, JavaDelimeters::EmbedEnd) {
+                        ""
+                    } else {
+                        "\""
+                    },
+                    if embed(next, JavaDelimeters::EmbedStart) {
+                        ""
+                    } else {
+                        "\""
+                    },
+                )),
+                JavaIdentifier::CharLiteral(s) => Cow::Owned(quote::<JavaTokenSet>(s, "'", "'")),
+                JavaIdentifier::JavaKeyword(k) => Cow::Borrowed(k.as_str()),
+                JavaIdentifier::Integer(s, _) => Cow::Borrowed(s),
+                JavaIdentifier::Float(s, _) => Cow::Borrowed(s),
+                // a name the interner had no room for can't be spelled
+                JavaIdentifier::Unknown(ident) => Cow::Borrowed(ident.name().unwrap_or_default()),
+            },
+        }
+    }
+
+    /// Literals by their kind, `NumberLiteral`, `StringLiteral` or `CharLiteral`
+    fn terminal(&self) -> Cow<'_, str> {
+        match self {
+            JavaToken::EOF => Cow::Borrowed("EOF"),
+            JavaToken::Identifier(id) => match id {
+                JavaIdentifier::Integer(_, _) | JavaIdentifier::Float(_, _) => {
+                    Cow::Borrowed("NumberLiteral")
+                }
+                JavaIdentifier::StringLiteral(_) => Cow::Borrowed("StringLiteral"),
+                JavaIdentifier::CharLiteral(_) => Cow::Borrowed("CharLiteral"),
+                JavaIdentifier::JavaKeyword(k) => Cow::Borrowed(k.as_str()),
+                _ => Cow::Borrowed("Identifier"),
+            },
+            _ => self.spelling(),
+        }
+    }
+
+    /// Statements and blocks go on lines of their own, calls, member access and type arguments
+    /// sit tight
+    fn spacing(&self) -> Spacing {
+        let spaced = |before, after| Spacing {
+            before,
+            after,
+            ..Spacing::SPACED
+        };
+        match self {
+            JavaToken::Operator(op) => match op {
+                JavaOperator::Dot | JavaOperator::Increment | JavaOperator::Decrement => {
+                    Spacing::TIGHT
+                }
+                JavaOperator::At | JavaOperator::Not | JavaOperator::BitCompl => {
+                    spaced(true, false)
+                }
+                _ => Spacing::SPACED,
+            },
+            JavaToken::Delimeter(d) => match d {
+                JavaDelimeters::Comma
+                | JavaDelimeters::Rparen
+                | JavaDelimeters::RBracket
+                | JavaDelimeters::RAngle => spaced(false, true),
+                JavaDelimeters::Colon => Spacing::SPACED,
+                JavaDelimeters::Semicolon => Spacing {
+                    line_break: true,
+                    ..spaced(false, true)
+                },
+                JavaDelimeters::LBrace => Spacing {
+                    line_break: true,
+                    indent: 1,
+                    ..Spacing::SPACED
+                },
+                JavaDelimeters::RBrace => Spacing {
+                    line_break: true,
+                    indent: -1,
+                    ..Spacing::SPACED
+                },
+                _ => Spacing::TIGHT,
+            },
+            _ => Spacing::of(self.kind()),
+        }
+    }
 }
 
 #[repr(u8)]
@@ -44,6 +159,37 @@ pub enum JavaDelimeters {
     Whitespace,
     Tab,
     NewLine,
+    /// `\{` opening an expression embedded in a string template
+    EmbedStart,
+    /// `}` closing it
+    EmbedEnd,
+    /// `<` opening type arguments, `List<String>`
+    LAngle,
+    /// `>` closing them, `>>` and `>>>` split into one per list
+    RAngle,
+}
+impl JavaDelimeters {
+    /// Source text of the delimiter
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JavaDelimeters::LParen => "(",
+            JavaDelimeters::Rparen => ")",
+            JavaDelimeters::LBracket => "[",
+            JavaDelimeters::RBracket => "]",
+            JavaDelimeters::LBrace => "{",
+            JavaDelimeters::RBrace => "}",
+            JavaDelimeters::Comma => ",",
+            JavaDelimeters::Colon => ":",
+            JavaDelimeters::Semicolon => ";",
+            JavaDelimeters::Whitespace => " ",
+            JavaDelimeters::Tab => "\t",
+            JavaDelimeters::NewLine => "\n",
+            JavaDelimeters::EmbedStart => "\\{",
+            JavaDelimeters::EmbedEnd => "}",
+            JavaDelimeters::LAngle => "<",
+            JavaDelimeters::RAngle => ">",
+        }
+    }
 }
 #[repr(u8)]
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -53,16 +199,45 @@ pub enum JavaBase {
     Octal,
     Hexadecimal,
 }
+/// Literal text borrows from the source buffer, only escaped literals own their data
 #[derive(Debug, Clone, PartialEq)]
-pub enum JavaIdentifier {
+pub enum JavaIdentifier<'a> {
     Var(u64, JavaKeyword),
     ObjVar(u64, u64),
-    StringLiteral(String),
-    CharLiteral(String),
+    StringLiteral(Cow<'a, str>),
+    CharLiteral(Cow<'a, str>),
     JavaKeyword(JavaKeyword),
-    Integer(String, JavaBase),
-    Float(f64),
-    Unknown(u64),
+    Integer(Cow<'a, str>, JavaBase),
+    /// Source text and value
+    Float(Cow<'a, str>, f64),
+    Unknown(Ident),
+}
+
+impl JavaToken<'_> {
+    /// Detaches the token from the source buffer
+    pub fn into_owned(self) -> JavaToken<'static> {
+        match self {
+            JavaToken::Identifier(id) => JavaToken::Identifier(match id {
+                JavaIdentifier::Var(h, k) => JavaIdentifier::Var(h, k),
+                JavaIdentifier::ObjVar(h, o) => JavaIdentifier::ObjVar(h, o),
+                JavaIdentifier::StringLiteral(s) => {
+                    JavaIdentifier::StringLiteral(Cow::Owned(s.into_owned()))
+                }
+                JavaIdentifier::CharLiteral(s) => {
+                    JavaIdentifier::CharLiteral(Cow::Owned(s.into_owned()))
+                }
+                JavaIdentifier::JavaKeyword(k) => JavaIdentifier::JavaKeyword(k),
+                JavaIdentifier::Integer(s, b) => {
+                    JavaIdentifier::Integer(Cow::Owned(s.into_owned()), b)
+                }
+                JavaIdentifier::Float(s, f) => JavaIdentifier::Float(Cow::Owned(s.into_owned()), f),
+                JavaIdentifier::Unknown(h) => JavaIdentifier::Unknown(h),
+            }),
+            JavaToken::Operator(op) => JavaToken::Operator(op),
+            JavaToken::Delimeter(d) => JavaToken::Delimeter(d),
+            JavaToken::EOF => JavaToken::EOF,
+        }
+    }
 }
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -164,3 +339,111 @@ pub enum JavaKeyword {
     Super,
     While,
 }
+
+impl JavaOperator {
+    /// Source text of the operator
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JavaOperator::Dot => ".",
+            JavaOperator::At => "@",
+            JavaOperator::Qmark => "?",
+            JavaOperator::Assign => "=",
+            JavaOperator::Eq => "==",
+            JavaOperator::Not => "!",
+            JavaOperator::Neq => "!=",
+            JavaOperator::Geq => ">=",
+            JavaOperator::Leq => "<=",
+            JavaOperator::Gt => ">",
+            JavaOperator::Lt => "<",
+            JavaOperator::Plus => "+",
+            JavaOperator::Minus => "-",
+            JavaOperator::PlusEq => "+=",
+            JavaOperator::MinusEq => "-=",
+            JavaOperator::Div => "/",
+            JavaOperator::Mod => "%",
+            JavaOperator::Mul => "*",
+            JavaOperator::DivEq => "/=",
+            JavaOperator::ModEq => "%=",
+            JavaOperator::MulEq => "*=",
+            JavaOperator::BitShiftLeft => "<<",
+            JavaOperator::BitShiftRight => ">>",
+            JavaOperator::UBitShiftRight => ">>>",
+            JavaOperator::BitShiftLeftEq => "<<=",
+            JavaOperator::BitShiftRightEq => ">>=",
+            JavaOperator::UBitShiftRightEq => ">>>=",
+            JavaOperator::Increment => "++",
+            JavaOperator::Decrement => "--",
+            JavaOperator::And => "&&",
+            JavaOperator::Or => "||",
+            JavaOperator::AndEq => "&=",
+            JavaOperator::OrEq => "|=",
+            JavaOperator::XorEq => "^=",
+            JavaOperator::BitAnd => "&",
+            JavaOperator::BitOr => "|",
+            JavaOperator::BitXor => "^",
+            JavaOperator::BitAndEq => "&=",
+            JavaOperator::BitOrEq => "|=",
+            JavaOperator::BitXorEq => "^=",
+            JavaOperator::BitCompl => "~",
+            JavaOperator::Instanceof => "instanceof",
+        }
+    }
+}
+
+impl JavaKeyword {
+    /// Source text of the keyword
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JavaKeyword::Abstract => "abstract",
+            JavaKeyword::Continue => "continue",
+            JavaKeyword::For => "for",
+            JavaKeyword::New => "new",
+            JavaKeyword::Switch => "switch",
+            JavaKeyword::Assert => "assert",
+            JavaKeyword::Default => "default",
+            JavaKeyword::Goto => "goto",
+            JavaKeyword::Package => "package",
+            JavaKeyword::Synchronized => "synchronized",
+            JavaKeyword::Boolean => "boolean",
+            JavaKeyword::Do => "do",
+            JavaKeyword::If => "if",
+            JavaKeyword::Private => "private",
+            JavaKeyword::This => "this",
+            JavaKeyword::Break => "break",
+            JavaKeyword::Double => "double",
+            JavaKeyword::Implements => "implements",
+            JavaKeyword::Protected => "protected",
+            JavaKeyword::Throw => "throw",
+            JavaKeyword::Byte => "byte",
+            JavaKeyword::Else => "else",
+            JavaKeyword::Import => "import",
+            JavaKeyword::Public => "public",
+            JavaKeyword::Throws => "throws",
+            JavaKeyword::Case => "case",
+            JavaKeyword::Enum => "enum",
+            JavaKeyword::Instanceof => "instanceof",
+            JavaKeyword::Return => "return",
+            JavaKeyword::Transient => "transient",
+            JavaKeyword::Catch => "catch",
+            JavaKeyword::Extends => "extends",
+            JavaKeyword::Int => "int",
+            JavaKeyword::Short => "short",
+            JavaKeyword::Try => "try",
+            JavaKeyword::Char => "char",
+            JavaKeyword::Final => "final",
+            JavaKeyword::Interface => "interface",
+            JavaKeyword::Static => "static",
+            JavaKeyword::Void => "void",
+            JavaKeyword::Class => "class",
+            JavaKeyword::Finally => "finally",
+            JavaKeyword::Long => "long",
+            JavaKeyword::Strictfp => "strictfp",
+            JavaKeyword::Volatile => "volatile",
+            JavaKeyword::Const => "const",
+            JavaKeyword::Float => "float",
+            JavaKeyword::Native => "native",
+            JavaKeyword::Super => "super",
+            JavaKeyword::While => "while",
+        }
+    }
+}
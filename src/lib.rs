@@ -1,13 +1,27 @@
 pub mod engine {
+    pub mod descent;
+    pub mod encoding;
     pub mod errors;
+    pub mod grammar;
+    pub mod incremental;
+    pub mod interner;
     pub mod lexer;
+    pub mod limits;
     pub mod normalize;
+    pub mod parallel;
     pub mod parser;
     pub mod semantic_traits;
+    pub mod sink;
     pub mod states;
+    pub mod stream;
 }
 pub mod tokens {
+    pub mod cst;
+    pub mod lines;
+    pub mod printer;
+    pub mod span;
     pub mod token_traits;
+    pub mod trivia;
 }
 
 //pub mod structures {
@@ -16,12 +30,20 @@ pub mod tokens {
 
 pub mod tests {
     //pub mod c;
+    /// What `codegen::grammar::descent_source` writes for the grammar of java.rs' precedence test
+    #[cfg(test)]
+    pub mod calc_parser;
+    /// What `codegen::grammar::descent_source` writes for the grammar of java.rs' descent test
+    #[cfg(test)]
+    pub mod expr_parser;
     pub mod java;
 }
 
 pub mod codegen {
+    #[allow(clippy::module_inception)]
     pub mod codegen;
     pub mod delimeted;
+    pub mod grammar;
     pub mod lexable;
     pub mod syntx;
     pub mod tokenset;
@@ -1,20 +1,27 @@
-use crossbeam::channel::unbounded;
 use memmap2::Mmap;
 use procfs::process::Process;
+use std::borrow::Cow;
 use std::env;
 use std::fs::{File, create_dir_all};
-use std::io::Read;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use syntx::codegen::codegen::*;
+use syntx::engine::encoding::{Encoding, Transcode, decode};
+use syntx::engine::errors::LexicalError;
 use syntx::engine::lexer::*;
+use syntx::engine::limits::Limits;
+use syntx::engine::normalize::HashTable;
+use syntx::engine::parallel::tokenize_parallel;
 use syntx::engine::semantic_traits::Walker;
+use syntx::engine::sink::{Callback, WriterSink};
+use syntx::engine::stream::{WINDOW, tokenize_reader};
 use syntx::langs::java::tokenset::*;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() == 1 {
-        run_stdin();
+    if args.len() == 1 || matches!(args[1].as_str(), "--encoding" | "--lossy") {
+        run_stdin(decoding_args(&args[1..]));
         return;
     }
 
@@ -22,8 +29,13 @@ fn main() {
     let filename = &args[2];
 
     match mode.as_str() {
-        "--bench" => run_benchmark(filename),
-        "--tokens" => print_tokens(filename),
+        "--bench" => {
+            let flag = |f: &str| args[3..].iter().any(|a| a == f);
+            let (verify, stream) = (flag("--verify-hashes"), flag("--stream"));
+            let decoding = decoding_args(&args[3..]);
+            run_benchmark(filename, threads_arg(&args[3..]), verify, stream, decoding)
+        }
+        "--tokens" => print_tokens(filename, decoding_args(&args[3..])),
         "--build" => generate_code(filename),
         _ => {
             eprintln!("Unknown mode: {}", mode);
@@ -35,34 +47,122 @@ fn main() {
 fn generate_code(filename: &str) {
     let f = File::open(filename).expect("Failed to open file");
     let mmap = unsafe { Mmap::map(&f).expect("Failed to mmap file") };
-    let source_code = std::str::from_utf8(&mmap).expect("File is not valid UTF-8");
+    let source_code = read_source(filename, &mmap, Decoding::default());
     create_dir_all(PathBuf::from("langs").join(PathBuf::from(filename.trim_end_matches(".stx"))))
         .unwrap();
-    codegen(source_code);
+    codegen(&source_code);
+}
+
+/// How file bytes become text: `--encoding NAME` instead of detecting it, and `--lossy` to
+/// replace what does not decode instead of stopping
+#[derive(Debug, Clone, Copy, Default)]
+struct Decoding {
+    encoding: Option<Encoding>,
+    lossy: bool,
+}
+
+fn decoding_args(rest: &[String]) -> Decoding {
+    let encoding = rest.iter().position(|a| a == "--encoding").map(|i| {
+        let name = rest.get(i + 1).map(String::as_str).unwrap_or_default();
+        name.parse().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        })
+    });
+    Decoding {
+        encoding,
+        lossy: rest.iter().any(|a| a == "--lossy"),
+    }
+}
+
+/// Decodes a mapped file, printing what lossy decoding replaced. Exits on undecodable input.
+fn read_source<'a>(filename: &str, bytes: &'a [u8], decoding: Decoding) -> Cow<'a, str> {
+    match decode(bytes, decoding.encoding, decoding.lossy) {
+        Ok(decoded) => {
+            for err in &decoded.diagnostics {
+                eprintln!("{}:{}", filename, err);
+            }
+            decoded.text
+        }
+        Err(e) => {
+            eprintln!("{}: {}", filename, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `--threads N` after the file name, 1 when missing
+fn threads_arg(rest: &[String]) -> usize {
+    match rest.iter().position(|a| a == "--threads") {
+        Some(i) => {
+            let n = rest.get(i + 1).map(String::as_str).unwrap_or_default();
+            n.parse().unwrap_or_else(|_| {
+                eprintln!("Invalid thread count: {}", n);
+                std::process::exit(1);
+            })
+        }
+        None => 1,
+    }
 }
 
-fn run_benchmark(filename: &str) {
+/// `--verify-hashes` checks every identifier hash for collisions and prints what it saw, it
+/// slows the run down. `--stream` reads the file in windows instead of mapping it.
+fn run_benchmark(filename: &str, threads: usize, verify: bool, stream: bool, decoding: Decoding) {
     let f = File::open(filename).expect("Failed to open file");
     let mmap = unsafe { Mmap::map(&f).expect("Failed to mmap file") };
-    let source_code = std::str::from_utf8(&mmap).expect("File is not valid UTF-8");
+    let source_code = read_source(filename, &mmap, decoding);
+    let source_code: &str = &source_code;
 
-    let source_for_lexer = source_code.to_owned();
     let pid = std::process::id() as i32;
     let start_mem = Process::new(pid).unwrap().statm().unwrap().resident;
     let page_size = procfs::page_size();
 
-    let (sender, receiver) = crossbeam::channel::unbounded::<Vec<JavaToken>>();
+    let (sender, receiver) = crossbeam::channel::unbounded::<Vec<JavaSpannedToken>>();
 
+    let hashes = verify.then(|| Arc::new(Mutex::new(HashTable::default())));
     let start = Instant::now();
 
-    std::thread::spawn(move || {
-        let mut lexer = Lexer::<JavaTokenSet>::new(&source_for_lexer, sender);
-        lexer.tokenize();
-    });
-
     let mut token_count = 0usize;
-    for batch in receiver.iter() {
-        token_count += batch.len();
+    if stream {
+        let mut count = Callback(|batch: Vec<JavaSpannedToken>| {
+            token_count += batch.len();
+            Ok(())
+        });
+        let input = Transcode::new(f, decoding.encoding, decoding.lossy);
+        if let Err(e) = tokenize_reader::<JavaTokenSet>(
+            input,
+            WINDOW,
+            &Limits::default(),
+            hashes.as_ref(),
+            &mut count,
+        ) {
+            eprintln!("{}: {}", filename, e);
+        }
+    } else if threads > 1 {
+        let mut count = Callback(|batch: Vec<JavaSpannedToken>| {
+            token_count += batch.len();
+            Ok(())
+        });
+        if let Err(e) =
+            tokenize_parallel::<JavaTokenSet>(source_code, threads, hashes.as_ref(), &mut count)
+        {
+            eprintln!("{}: {}", filename, e);
+        }
+    } else {
+        std::thread::scope(|s| {
+            let hashes = hashes.clone();
+            s.spawn(move || {
+                let mut lexer = Lexer::<JavaTokenSet>::new(source_code, sender);
+                lexer.hashes = hashes;
+                if let Err(e) = lexer.tokenize() {
+                    eprintln!("{}: {}", filename, e);
+                }
+            });
+
+            for batch in receiver.iter() {
+                token_count += batch.len();
+            }
+        });
     }
 
     let elapsed = start.elapsed();
@@ -74,6 +174,7 @@ fn run_benchmark(filename: &str) {
     println!("========== Benchmark Results for {} ==========", filename);
     println!("Lines        : {}", line_count);
     println!("Tokens       : {}", token_count);
+    println!("Threads      : {}", threads);
     println!("Time         : {:.4} s", elapsed.as_secs_f64());
     println!("Memory       : {:.2} MB", used_mb);
     println!(
@@ -84,38 +185,50 @@ fn run_benchmark(filename: &str) {
         "Tokens/sec   : {}",
         (token_count as f64 / elapsed.as_secs_f64()) as usize
     );
+    if let Some(hashes) = hashes {
+        println!("========== Identifier hashes ==========");
+        print!("{}", hashes.lock().unwrap().report());
+    }
 }
 
-fn print_tokens(filename: &str) {
+fn print_tokens(filename: &str, decoding: Decoding) {
     let f = File::open(filename).expect("Failed to open file");
     let mmap = unsafe { Mmap::map(&f).expect("Failed to mmap file") };
-    let source_code = std::str::from_utf8(&mmap).expect("File is not valid UTF-8");
-    let (sender, receiver) = unbounded::<Vec<JavaToken>>();
-    let source_code = source_code.to_owned();
-
-    std::thread::spawn(move || {
-        let mut lexer = Lexer::<JavaTokenSet>::new(&source_code, sender);
-        lexer.tokenize();
-    });
+    let source_code = read_source(filename, &mmap, decoding);
+    dump_tokens(filename, &source_code);
+}
 
-    for batch in receiver.iter() {
-        for token in batch {
-            eprintln!("{:?}", token);
+/// Tokens are written out as the input comes in, nothing waits for the end of it
+fn run_stdin(decoding: Decoding) {
+    let mut sink = WriterSink(std::io::stderr().lock());
+    let mut stdin = Transcode::new(std::io::stdin().lock(), decoding.encoding, decoding.lossy);
+    match tokenize_reader::<JavaTokenSet>(&mut stdin, WINDOW, &Limits::default(), None, &mut sink) {
+        Ok(mut errors) => {
+            errors.extend_from_slice(stdin.decoder().diagnostics());
+            errors.sort_by_key(|e| e.offset);
+            report_diagnostics("<stdin>", errors)
+        }
+        Err(e) => {
+            eprintln!("<stdin>: {}", e);
+            std::process::exit(1);
         }
     }
 }
 
-fn run_stdin() {
-    let mut src: String = String::new();
-    std::io::stdin().read_to_string(&mut src).unwrap();
-    let (sender, receiver) = unbounded::<Vec<JavaToken>>();
-    std::thread::spawn(move || {
-        let mut lexer = Lexer::<JavaTokenSet>::new(&src, sender);
-        lexer.tokenize();
-    });
-    for batch in receiver.iter() {
-        for token in batch {
-            eprintln!("{:?}", token);
-        }
+fn dump_tokens(filename: &str, source_code: &str) {
+    let mut lexer = Lexer::<JavaTokenSet>::new(source_code, WriterSink(std::io::stderr().lock()));
+    if let Err(e) = lexer.tokenize() {
+        eprintln!("{}: {}", filename, e);
+        std::process::exit(1);
+    }
+    report_diagnostics(filename, lexer.take_diagnostics());
+}
+
+fn report_diagnostics(filename: &str, errors: Vec<LexicalError>) {
+    for err in &errors {
+        eprintln!("{}:{}", filename, err);
+    }
+    if !errors.is_empty() {
+        std::process::exit(1);
     }
 }
@@ -0,0 +1,162 @@
+use crate::engine::descent::Descent;
+use crate::engine::errors::ParseError;
+use crate::engine::parser::ParseTree;
+use crate::tokens::span::Spanned;
+use crate::tokens::token_traits::Token;
+
+/// Names of the nodes, for `ParseTree::render`
+pub const NONTERMINALS: &[&str] = &["$accept", "E"];
+
+pub fn parse<K: Token>(
+    tokens: impl IntoIterator<Item = Spanned<K>>,
+) -> Result<ParseTree<K>, ParseError> {
+    let mut p = Descent::new(tokens, false);
+    let tree = parse_e(&mut p)?;
+    p.finish()?;
+    Ok(tree)
+}
+
+/// `E = E "=" E`
+/// `E = E "==" E`
+/// `E = E "+" E`
+/// `E = E Minus E`
+/// `E = E "*" E`
+/// `E = E "/" E`
+/// `E = E "++"`
+/// `E = Minus E`
+/// `E = "(" E ")"`
+/// `E = Identifier`
+/// `E = NumberLiteral`
+pub fn parse_e<K: Token>(p: &mut Descent<K>) -> Result<ParseTree<K>, ParseError> {
+    parse_e_above(p, 0)
+}
+
+/// `E` with the operators that bind at least as tight as `min`
+fn parse_e_above<K: Token>(p: &mut Descent<K>, min: usize) -> Result<ParseTree<K>, ParseError> {
+    let mut left = 'operand: {
+        let mut children = Vec::new();
+        if p.at(&["-"]) {
+            children.push(p.expect("-", "Minus")?);
+            children.push(parse_e_above(p, 4)?);
+            break 'operand ParseTree::Node {
+                symbol: 1,
+                production: 8,
+                children,
+            };
+        }
+        if p.at(&["("]) {
+            children.push(p.expect("(", "\"(\"")?);
+            children.push(parse_e(p)?);
+            children.push(p.expect(")", "\")\"")?);
+            break 'operand ParseTree::Node {
+                symbol: 1,
+                production: 9,
+                children,
+            };
+        }
+        if p.at(&["Identifier"]) {
+            children.push(p.expect("Identifier", "Identifier")?);
+            break 'operand ParseTree::Node {
+                symbol: 1,
+                production: 10,
+                children,
+            };
+        }
+        if p.at(&["NumberLiteral"]) {
+            children.push(p.expect("NumberLiteral", "NumberLiteral")?);
+            break 'operand ParseTree::Node {
+                symbol: 1,
+                production: 11,
+                children,
+            };
+        }
+        return Err(p.error(&["Minus", "\"(\"", "Identifier", "NumberLiteral"]));
+    };
+    let mut last = None;
+    loop {
+        if min <= 1 && p.at(&["="]) {
+            let mut children = vec![left];
+            children.push(p.expect("=", "\"=\"")?);
+            children.push(parse_e_above(p, 1)?);
+            left = ParseTree::Node {
+                symbol: 1,
+                production: 1,
+                children,
+            };
+            last = Some(1);
+            continue;
+        }
+        if min <= 2 && last != Some(2) && p.at(&["=="]) {
+            let mut children = vec![left];
+            children.push(p.expect("==", "\"==\"")?);
+            children.push(parse_e_above(p, 3)?);
+            left = ParseTree::Node {
+                symbol: 1,
+                production: 2,
+                children,
+            };
+            last = Some(2);
+            continue;
+        }
+        if min <= 3 && p.at(&["+"]) {
+            let mut children = vec![left];
+            children.push(p.expect("+", "\"+\"")?);
+            children.push(parse_e_above(p, 4)?);
+            left = ParseTree::Node {
+                symbol: 1,
+                production: 3,
+                children,
+            };
+            last = Some(3);
+            continue;
+        }
+        if min <= 3 && p.at(&["-"]) {
+            let mut children = vec![left];
+            children.push(p.expect("-", "Minus")?);
+            children.push(parse_e_above(p, 4)?);
+            left = ParseTree::Node {
+                symbol: 1,
+                production: 4,
+                children,
+            };
+            last = Some(3);
+            continue;
+        }
+        if min <= 4 && p.at(&["*"]) {
+            let mut children = vec![left];
+            children.push(p.expect("*", "\"*\"")?);
+            children.push(parse_e_above(p, 5)?);
+            left = ParseTree::Node {
+                symbol: 1,
+                production: 5,
+                children,
+            };
+            last = Some(4);
+            continue;
+        }
+        if min <= 4 && p.at(&["/"]) {
+            let mut children = vec![left];
+            children.push(p.expect("/", "\"/\"")?);
+            children.push(parse_e_above(p, 5)?);
+            left = ParseTree::Node {
+                symbol: 1,
+                production: 6,
+                children,
+            };
+            last = Some(4);
+            continue;
+        }
+        if min <= 5 && p.at(&["++"]) {
+            let mut children = vec![left];
+            children.push(p.expect("++", "\"++\"")?);
+            left = ParseTree::Node {
+                symbol: 1,
+                production: 7,
+                children,
+            };
+            last = Some(5);
+            continue;
+        }
+        return Ok(left);
+    }
+}
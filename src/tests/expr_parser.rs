@@ -0,0 +1,193 @@
+use crate::engine::descent::Descent;
+use crate::engine::errors::ParseError;
+use crate::engine::parser::ParseTree;
+use crate::tokens::span::Spanned;
+use crate::tokens::token_traits::Token;
+
+/// Names of the nodes, for `ParseTree::render`
+pub const NONTERMINALS: &[&str] = &[
+    "$accept",
+    "Expr",
+    "Term",
+    "Factor",
+    "Args",
+    "Expr#1",
+    "Term#1",
+    "Factor#1",
+    "Factor#1#1",
+    "Args#1",
+];
+
+pub fn parse<K: Token>(
+    tokens: impl IntoIterator<Item = Spanned<K>>,
+) -> Result<ParseTree<K>, ParseError> {
+    let mut p = Descent::new(tokens, false);
+    let tree = parse_expr(&mut p)?;
+    p.finish()?;
+    Ok(tree)
+}
+
+/// `Expr = Term Expr#1`
+pub fn parse_expr<K: Token>(p: &mut Descent<K>) -> Result<ParseTree<K>, ParseError> {
+    let mut children = Vec::new();
+    if p.at(&["(", "NumberLiteral", "Identifier"]) {
+        children.push(parse_term(p)?);
+        parse_expr_1(p, &mut children)?;
+        return Ok(ParseTree::Node {
+            symbol: 1,
+            production: 3,
+            children,
+        });
+    }
+    Err(p.error(&["\"(\"", "NumberLiteral", "Identifier"]))
+}
+
+/// `Term = Factor Term#1`
+pub fn parse_term<K: Token>(p: &mut Descent<K>) -> Result<ParseTree<K>, ParseError> {
+    let mut children = Vec::new();
+    if p.at(&["(", "NumberLiteral", "Identifier"]) {
+        children.push(parse_factor(p)?);
+        parse_term_1(p, &mut children)?;
+        return Ok(ParseTree::Node {
+            symbol: 2,
+            production: 6,
+            children,
+        });
+    }
+    Err(p.error(&["\"(\"", "NumberLiteral", "Identifier"]))
+}
+
+/// `Factor = "(" Expr ")"`
+/// `Factor = NumberLiteral`
+/// `Factor = Identifier Factor#1`
+pub fn parse_factor<K: Token>(p: &mut Descent<K>) -> Result<ParseTree<K>, ParseError> {
+    let mut children = Vec::new();
+    if p.at(&["("]) {
+        children.push(p.expect("(", "\"(\"")?);
+        children.push(parse_expr(p)?);
+        children.push(p.expect(")", "\")\"")?);
+        return Ok(ParseTree::Node {
+            symbol: 3,
+            production: 7,
+            children,
+        });
+    }
+    if p.at(&["NumberLiteral"]) {
+        children.push(p.expect("NumberLiteral", "NumberLiteral")?);
+        return Ok(ParseTree::Node {
+            symbol: 3,
+            production: 8,
+            children,
+        });
+    }
+    if p.at(&["Identifier"]) {
+        children.push(p.expect("Identifier", "Identifier")?);
+        parse_factor_1(p, &mut children)?;
+        return Ok(ParseTree::Node {
+            symbol: 3,
+            production: 13,
+            children,
+        });
+    }
+    Err(p.error(&["\"(\"", "NumberLiteral", "Identifier"]))
+}
+
+/// `Args = Expr Args#1`
+pub fn parse_args<K: Token>(p: &mut Descent<K>) -> Result<ParseTree<K>, ParseError> {
+    let mut children = Vec::new();
+    if p.at(&["(", "NumberLiteral", "Identifier"]) {
+        children.push(parse_expr(p)?);
+        parse_args_1(p, &mut children)?;
+        return Ok(ParseTree::Node {
+            symbol: 4,
+            production: 16,
+            children,
+        });
+    }
+    Err(p.error(&["\"(\"", "NumberLiteral", "Identifier"]))
+}
+
+/// `Expr#1 = Plus Expr`
+/// `Expr#1 =`
+fn parse_expr_1<K: Token>(
+    p: &mut Descent<K>,
+    children: &mut Vec<ParseTree<K>>,
+) -> Result<(), ParseError> {
+    if p.at(&["+"]) {
+        children.push(p.expect("+", "Plus")?);
+        children.push(parse_expr(p)?);
+        return Ok(());
+    }
+    if p.at(&["$end", ")", ","]) {
+        return Ok(());
+    }
+    Err(p.error(&["$end", "Plus", "\")\"", "\",\""]))
+}
+
+/// `Term#1 = "*" Term`
+/// `Term#1 =`
+fn parse_term_1<K: Token>(
+    p: &mut Descent<K>,
+    children: &mut Vec<ParseTree<K>>,
+) -> Result<(), ParseError> {
+    if p.at(&["*"]) {
+        children.push(p.expect("*", "\"*\"")?);
+        children.push(parse_term(p)?);
+        return Ok(());
+    }
+    if p.at(&["$end", "+", ")", ","]) {
+        return Ok(());
+    }
+    Err(p.error(&["$end", "Plus", "\"*\"", "\")\"", "\",\""]))
+}
+
+/// `Factor#1 = "(" Factor#1#1 ")"`
+/// `Factor#1 =`
+fn parse_factor_1<K: Token>(
+    p: &mut Descent<K>,
+    children: &mut Vec<ParseTree<K>>,
+) -> Result<(), ParseError> {
+    if p.at(&["("]) {
+        children.push(p.expect("(", "\"(\"")?);
+        parse_factor_1_1(p, children)?;
+        children.push(p.expect(")", "\")\"")?);
+        return Ok(());
+    }
+    if p.at(&["$end", "+", "*", ")", ","]) {
+        return Ok(());
+    }
+    Err(p.error(&["$end", "Plus", "\"*\"", "\"(\"", "\")\"", "\",\""]))
+}
+
+/// `Factor#1#1 = Args`
+/// `Factor#1#1 =`
+fn parse_factor_1_1<K: Token>(
+    p: &mut Descent<K>,
+    children: &mut Vec<ParseTree<K>>,
+) -> Result<(), ParseError> {
+    if p.at(&["(", "NumberLiteral", "Identifier"]) {
+        children.push(parse_args(p)?);
+        return Ok(());
+    }
+    if p.at(&[")"]) {
+        return Ok(());
+    }
+    Err(p.error(&["\"(\"", "\")\"", "NumberLiteral", "Identifier"]))
+}
+
+/// `Args#1 = "," Args`
+/// `Args#1 =`
+fn parse_args_1<K: Token>(
+    p: &mut Descent<K>,
+    children: &mut Vec<ParseTree<K>>,
+) -> Result<(), ParseError> {
+    if p.at(&[","]) {
+        children.push(p.expect(",", "\",\"")?);
+        children.push(parse_args(p)?);
+        return Ok(());
+    }
+    if p.at(&[")"]) {
+        return Ok(());
+    }
+    Err(p.error(&["\")\"", "\",\""]))
+}
@@ -1,12 +1,34 @@
 #[cfg(test)]
 mod test {
+    use crate::codegen::grammar::descent_source;
+    use crate::engine::encoding::{Encoding, Transcode, decode};
+    use crate::engine::errors::{
+        ConflictKind, DecodeError, GrammarError, LexError, LexicalErrorKind, LimitError, SinkError,
+    };
+    use crate::engine::grammar::Grammar;
+    use crate::engine::incremental::Document;
+    use crate::engine::interner::{Ident, Interner, Symbol};
     use crate::engine::lexer::Lexer;
+    use crate::engine::limits::{CancelToken, Limits};
+    use crate::engine::normalize::{HashTable, key};
+    use crate::engine::parallel::tokenize_parallel;
+    use crate::engine::parser::Parser;
     use crate::engine::semantic_traits::Walker;
+    use crate::engine::sink::{Callback, TokenSink, WriterSink};
+    use crate::engine::stream::tokenize_reader;
     use crate::langs::java::tokenset::*;
-    use crossbeam::channel::unbounded;
+    use crate::tests::{calc_parser, expr_parser};
+    use crate::tokens::cst::{Cst, NodeId, Visitor, Walk};
+    use crate::tokens::lines::{LineIndex, Position, PositionEncoding};
+    use crate::tokens::printer::{PrintMode, print};
+    use crate::tokens::span::Spanned;
+    use crate::tokens::token_traits::{CommentStyle, Delimeted, Parseable, StringStyle, Token};
+    use crate::tokens::trivia::{TriviaKind, reconstruct};
+    use crossbeam::channel::{bounded, unbounded};
     use procfs::process::Process;
+    use std::sync::{Arc, Mutex};
     use std::thread;
-    use std::time::Instant;
+    use std::time::{Duration, Instant};
 
     fn assert_runtime_and_memory<F: FnOnce()>(f: F, max_time_ms: u128, max_mem_mb: f64) {
         let pid = std::process::id();
@@ -35,22 +57,15 @@ mod test {
         );
     }
 
-    fn run_lexer(input: &str) -> Vec<JavaToken> {
-        let (sender, receiver) = unbounded();
-        let input = input.to_owned();
-
-        let handle = thread::spawn(move || {
-            let mut lexer = Lexer::<JavaTokenSet>::new(&input, sender);
-            lexer.tokenize();
-        });
-
-        let mut tokens = Vec::with_capacity(512);
-        for batch in receiver {
-            tokens.extend(batch);
-        }
+    fn run_lexer(input: &str) -> Vec<JavaToken<'_>> {
+        run_lexer_spanned(input)
+            .into_iter()
+            .map(|t| t.token)
+            .collect()
+    }
 
-        handle.join().expect("Lexer thread panicked");
-        tokens
+    fn run_lexer_spanned(input: &str) -> Vec<JavaSpannedToken<'_>> {
+        Lexer::<JavaTokenSet>::from_str(input).collect()
     }
 
     fn assert_token_present(tokens: &[JavaToken], expected: JavaToken) {
@@ -138,7 +153,7 @@ mod test {
                 );
                 assert!(tokens.iter().any(|t| matches!(
                     t,
-                    JavaToken::Identifier(JavaIdentifier::Float(f)) if *f > 3.0
+                    JavaToken::Identifier(JavaIdentifier::Float(_, f)) if *f > 3.0
                 )));
                 assert!(tokens.iter().any(|t| matches!(
                     t,
@@ -161,8 +176,27 @@ mod test {
             || {
                 let tokens = run_lexer("Map<String, List<Map<Integer, List<String>>>> deepMap;");
                 dbg!(&tokens);
-                assert_token_present(&tokens, JavaToken::Operator(JavaOperator::Lt));
-                assert_token_present(&tokens, JavaToken::Operator(JavaOperator::UBitShiftRight));
+                let angles = |d: JavaDelimeters| {
+                    tokens
+                        .iter()
+                        .filter(|t| **t == JavaToken::Delimeter(d))
+                        .count()
+                };
+                assert_eq!(
+                    (
+                        angles(JavaDelimeters::LAngle),
+                        angles(JavaDelimeters::RAngle)
+                    ),
+                    (4, 4)
+                );
+                assert!(!tokens.iter().any(|t| matches!(
+                    t,
+                    JavaToken::Operator(
+                        JavaOperator::Lt
+                            | JavaOperator::BitShiftRight
+                            | JavaOperator::UBitShiftRight
+                    )
+                )));
             },
             10,
             5.0,
@@ -248,4 +282,1533 @@ mod test {
             8.0,
         );
     }
+
+    // 🔥 Imports go on with the limits of the importer, and a cycle is lexed once
+    #[test]
+    fn import_cycles_and_limits() {
+        let class = JavaToken::Identifier(JavaIdentifier::JavaKeyword(JavaKeyword::Class));
+        let tokens = run_lexer("import testpkg.Cycle;\nint x;");
+        assert_eq!(tokens.iter().filter(|t| **t == class).count(), 1);
+
+        // imported tokens count against the importer's budget
+        let mut lexer = Lexer::<JavaTokenSet>::from_str("int a; import testpkg.Imported;\nint x;");
+        lexer.limits.max_tokens = 5;
+        assert_eq!(lexer.by_ref().count(), 5);
+        assert_eq!(lexer.stopped(), Some(&LimitError::TooManyTokens(5)));
+
+        let mut lexer = Lexer::<JavaTokenSet>::from_str("import testpkg.Imported;\nint x;");
+        lexer.limits.max_depth = 1;
+        lexer.by_ref().for_each(drop);
+        assert!(matches!(
+            lexer.stopped(),
+            Some(LimitError::TooDeep {
+                limit: 1,
+                row: 3,
+                ..
+            })
+        ));
+    }
+
+    // 🔥 Spans point back into the source
+    #[test]
+    fn spans_cover_source_text() {
+        let src = "int x = 42;\n  String s = \"a\\nb\";\n/* multi\nline */ x >>= 1;";
+        let tokens = run_lexer_spanned(src);
+
+        let int = &tokens[0];
+        assert_eq!(
+            int.token,
+            JavaToken::Identifier(JavaIdentifier::JavaKeyword(JavaKeyword::Int))
+        );
+        assert_eq!((int.span.start, int.span.end), (0, 3));
+        assert_eq!((int.span.row, int.span.column), (0, 0));
+
+        let string = tokens
+            .iter()
+            .find(|t| {
+                matches!(
+                    t.token,
+                    JavaToken::Identifier(JavaIdentifier::StringLiteral(_))
+                )
+            })
+            .unwrap();
+        assert_eq!(&src[string.span.start..string.span.end], "\"a\\nb\"");
+        assert_eq!((string.span.row, string.span.column), (1, 13));
+
+        let shift = tokens
+            .iter()
+            .find(|t| t.token == JavaToken::Operator(JavaOperator::BitShiftRightEq))
+            .unwrap();
+        assert_eq!(&src[shift.span.start..shift.span.end], ">>=");
+        assert_eq!((shift.span.row, shift.span.column), (3, 10));
+    }
+
+    // 🔥 Lossless mode round trips byte for byte
+    #[test]
+    fn lossless_round_trip() {
+        let src = "import foo.Bar;\r\n/** doc\n * block */\npublic class A { // tail\n\tint $x = 0x1F;\r}\n// eof comment";
+        let tokens: Vec<JavaSpannedToken> = Lexer::<JavaTokenSet>::from_str_lossless(src).collect();
+
+        assert_eq!(reconstruct(&tokens), src);
+
+        let public = &tokens[0];
+        let kinds: Vec<TriviaKind> = public
+            .lossless
+            .as_ref()
+            .unwrap()
+            .leading
+            .iter()
+            .map(|t| t.kind)
+            .collect();
+        assert!(kinds.contains(&TriviaKind::BlockComment));
+        assert!(kinds.contains(&TriviaKind::Newline));
+
+        let eof = tokens.last().unwrap();
+        assert_eq!(eof.token, JavaToken::EOF);
+        let trailing = &eof.lossless.as_ref().unwrap().leading;
+        assert_eq!(trailing.last().unwrap().kind, TriviaKind::LineComment);
+        // the lone `\r` after `0x1F;` ends a line too
+        assert_eq!(trailing.last().unwrap().span.row, 6);
+    }
+
+    // 🔥 Broken literals and stray chars end up in the diagnostics
+    #[test]
+    fn lexical_diagnostics_are_recorded() {
+        let src =
+            "String s = \"open;\nchar c = 'ab';\nchar e = '';\nint # x = \"\\q\";\n/* never closed";
+        let mut lexer = Lexer::<JavaTokenSet>::from_str(src);
+        lexer.tokenize().unwrap();
+
+        assert!(!lexer.state.valid);
+        let kinds: Vec<(LexicalErrorKind, usize, usize)> = lexer
+            .diagnostics()
+            .iter()
+            .map(|e| (e.kind, e.row, e.column))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (LexicalErrorKind::UnterminatedString, 0, 11),
+                (LexicalErrorKind::UnterminatedChar, 1, 9),
+                // a char ends after one char, the quote after `b` opens another
+                (LexicalErrorKind::UnterminatedChar, 1, 12),
+                (LexicalErrorKind::EmptyChar, 2, 9),
+                (LexicalErrorKind::UnexpectedChar, 3, 4),
+                (LexicalErrorKind::InvalidEscape, 3, 11),
+                (LexicalErrorKind::UnterminatedBlockComment, 4, 0),
+            ]
+        );
+        assert_eq!(
+            lexer.diagnostics()[0].to_string(),
+            "1:12: unterminated string literal, expected `\"`, found `\\n`"
+        );
+        assert_eq!(
+            lexer.diagnostics()[6].to_string(),
+            "5:1: unterminated block comment, expected `*/`, found end of input"
+        );
+    }
+
+    // 🔥 Multibyte chars whose low byte looks like an operator ('Ŀ' is 0xC4 0xBF, '?' is 0x3F)
+    #[test]
+    fn multibyte_chars_are_not_truncated() {
+        let src = "int aĿb = Ŀ;\nboolean ĳ = a ? b : c;";
+        let tokens = run_lexer_spanned(src);
+
+        let qmarks = tokens
+            .iter()
+            .filter(|t| t.token == JavaToken::Operator(JavaOperator::Qmark))
+            .count();
+        assert_eq!(qmarks, 1);
+
+        let idents: Vec<&str> = tokens
+            .iter()
+            .filter(|t| matches!(t.token, JavaToken::Identifier(JavaIdentifier::Unknown(_))))
+            .map(|t| &src[t.span.start..t.span.end])
+            .collect();
+        assert_eq!(idents, vec!["aĿb", "Ŀ", "ĳ", "a", "b", "c"]);
+
+        let last = tokens.last().unwrap();
+        assert_eq!((last.span.row, last.span.column), (1, 21));
+    }
+
+    // 🔥 Plain literals borrow the source, escaped ones own their text
+    #[test]
+    fn literals_borrow_from_source() {
+        use std::borrow::Cow;
+
+        let src = "String a = \"plain\"; String b = \"esc\\t\"; int c = 0x1F;";
+        let tokens = run_lexer(src);
+
+        let literals: Vec<&Cow<str>> = tokens
+            .iter()
+            .filter_map(|t| match t {
+                JavaToken::Identifier(JavaIdentifier::StringLiteral(s))
+                | JavaToken::Identifier(JavaIdentifier::Integer(s, _)) => Some(s),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(literals.len(), 3);
+        assert!(matches!(literals[0], Cow::Borrowed("plain")));
+        assert!(matches!(literals[1], Cow::Owned(s) if s == "esc\t"));
+        assert!(matches!(literals[2], Cow::Borrowed("0x1F")));
+
+        let owned: Vec<JavaToken<'static>> =
+            tokens.into_iter().map(JavaToken::into_owned).collect();
+        assert_eq!(owned.len(), run_lexer(src).len());
+    }
+
+    // 🔥 Iterator, batches and the threaded channel mode all see the same stream
+    #[test]
+    fn pull_and_channel_modes_agree() {
+        let src = "class A { int x = 1 << 2; String s = \"hi\"; }\n".repeat(400);
+
+        let pulled: Vec<JavaSpannedToken> = Lexer::<JavaTokenSet>::from_str(&src).collect();
+
+        let mut lexer = Lexer::<JavaTokenSet>::from_str(&src);
+        let first = lexer.next().unwrap();
+        let batches: Vec<Vec<JavaSpannedToken>> = lexer.batches().collect();
+        assert!(batches.len() > 1);
+        assert!(batches.iter().all(|b| !b.is_empty()));
+        let mut batched = vec![first];
+        batched.extend(batches.into_iter().flatten());
+        assert!(lexer.next().is_none());
+
+        let (sender, receiver) = unbounded();
+        let sent: Vec<JavaSpannedToken> = thread::scope(|s| {
+            s.spawn(|| Lexer::<JavaTokenSet>::new(&src, sender).tokenize().unwrap());
+            receiver.iter().flatten().collect()
+        });
+
+        assert_eq!(pulled.len(), 400 * 16);
+        assert_eq!(pulled, batched);
+        assert_eq!(pulled, sent);
+    }
+
+    // 🔥 Sinks pick the batch size and report failures instead of panicking
+    #[test]
+    fn token_sinks() {
+        let src = "int a = 1; int b = 2; int c = 3;";
+
+        let mut sizes = Vec::new();
+        let sink = Callback(|batch: Vec<JavaSpannedToken>| {
+            sizes.push(batch.len());
+            Ok(())
+        });
+        Lexer::<JavaTokenSet>::new(src, sink.batched(4))
+            .tokenize()
+            .unwrap();
+        assert_eq!(sizes, vec![4, 4, 4, 3]);
+
+        let mut out = Vec::new();
+        Lexer::<JavaTokenSet>::new(src, WriterSink(&mut out))
+            .tokenize()
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 15);
+        assert_eq!(
+            out.lines().next(),
+            Some("1:1 [0..3] Identifier(JavaKeyword(Int))")
+        );
+
+        let (sender, receiver) = bounded(1);
+        drop(receiver);
+        let err = Lexer::<JavaTokenSet>::new(src, sender)
+            .tokenize()
+            .unwrap_err();
+        assert!(matches!(err, LexError::Sink(SinkError::Disconnected)));
+
+        let rejecting =
+            Callback(|_: Vec<JavaSpannedToken>| Err(SinkError::Rejected("full".into())));
+        let err = Lexer::<JavaTokenSet>::new(src, rejecting.batched(2))
+            .tokenize()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "token sink rejected batch: full");
+    }
+
+    // 🔥 Incremental edits end up exactly where a full re-lex would
+    #[test]
+    fn incremental_relex_matches_full_lex() {
+        let body = "class A {\n    int count = 0; // c\n    String s = \"x\";\n}\n".repeat(50);
+        let mut doc = Document::<JavaTokenSet>::new(body.clone());
+
+        // (range, text, whether the change stays local); a brace changes the level, and so the
+        // hash, of every name after it
+        let edits: [(std::ops::Range<usize>, &str, bool); 7] = [
+            (14..19, "total", true),
+            (10..10, "    long ŀong = 1;\n", true),
+            (60..60, "\"", true),
+            (60..61, "", true),
+            (9..9, "{", false),
+            (9..10, "", false),
+            (0..5, "interface", true),
+        ];
+        for (range, text, local) in edits {
+            let relexed = doc.edit(range, text);
+            let fresh = Document::<JavaTokenSet>::new(doc.text().to_string());
+            assert_eq!(doc.tokens(), fresh.tokens(), "after {:?}", text);
+            assert_eq!(doc.diagnostics(), fresh.diagnostics());
+            if local {
+                assert!(relexed.len() < 20, "{:?} relexed {:?}", text, relexed);
+            }
+        }
+
+        let mut broken = Document::<JavaTokenSet>::new("int a = 'x';\nint b = 2;");
+        broken.edit(10..11, "");
+        assert_eq!(
+            broken.diagnostics()[0].kind,
+            LexicalErrorKind::UnterminatedChar
+        );
+        broken.edit(10..10, "'");
+        assert!(broken.diagnostics().is_empty());
+
+        // a char literal ends with its own chars, nothing after it decides where
+        let src = "_'\"unterminat'c'/* blk\n */\rint";
+        for at in (0..=src.len()).filter(|&i| src.is_char_boundary(i)) {
+            for text in ["'", " ", "\n"] {
+                let mut doc = Document::<JavaTokenSet>::new(src);
+                doc.edit(at..at, text);
+                let fresh = Document::<JavaTokenSet>::new(doc.text().to_string());
+                assert_eq!(doc.tokens(), fresh.tokens(), "{:?} at {}", text, at);
+                assert_eq!(
+                    doc.diagnostics(),
+                    fresh.diagnostics(),
+                    "{:?} at {}",
+                    text,
+                    at
+                );
+            }
+        }
+    }
+
+    // 🔥 Chunked lexing on several threads stitches back into the sequential stream
+    #[test]
+    fn parallel_chunks_match_sequential() {
+        let unit = "class B { /* spans\n\n lines */ int f(int a) {\n  String s = \"q\\\n\";\n  char c = '{';\n  return (a + 1);\n} }\n";
+        // the legacy escape eats the closing quote and the paren, the pre-scan counts the paren
+        // and is off by one from there on, so every chunk after it gets lexed again
+        let src = "String u = \"\\u12\"(;\n".to_string()
+            + &unit.repeat(300)
+            + "/* never closed\n int x = 1;\n";
+
+        let mut sequential = Lexer::<JavaTokenSet>::from_str(&src);
+        let expected: Vec<JavaSpannedToken> = sequential.by_ref().collect();
+        let expected_errors = sequential.take_diagnostics();
+
+        for threads in [1, 3, 8] {
+            let mut got: Vec<JavaSpannedToken> = Vec::new();
+            let errors = tokenize_parallel::<JavaTokenSet>(
+                &src,
+                threads,
+                None,
+                &mut (&mut got).batched(100),
+            )
+            .unwrap();
+            assert_eq!(got.len(), expected.len(), "{} threads", threads);
+            assert_eq!(got, expected, "{} threads", threads);
+            assert_eq!(errors, expected_errors);
+        }
+
+        let mut refuse = Callback(|_: Vec<JavaSpannedToken>| Err(SinkError::Disconnected));
+        assert!(tokenize_parallel::<JavaTokenSet>(&src, 4, None, &mut refuse).is_err());
+    }
+
+    // 🔥 Comment styles of any length, nested or not, several per lang
+    #[test]
+    fn comment_styles() {
+        let rust = CommentStyle::Block {
+            open: "/*",
+            close: "*/",
+            nested: true,
+        };
+        let haskell = CommentStyle::Block {
+            open: "{-",
+            close: "-}",
+            nested: true,
+        };
+        let html = CommentStyle::Block {
+            open: "<!--",
+            close: "-->",
+            nested: false,
+        };
+        assert_eq!(rust.measure("/* a /* b */ c */ d"), Ok(17));
+        assert_eq!(rust.measure("/* /* */"), Err(8));
+        assert_eq!(rust.measure("/*/**/*/x"), Ok(8));
+        assert_eq!(haskell.measure("{- {- {- -} -} -}x"), Ok(17));
+        assert_eq!(html.measure("<!-- <!-- --> -->"), Ok(13));
+        assert_eq!(CommentStyle::Line("#").measure("# a\nb"), Ok(4));
+        assert_eq!(CommentStyle::Line("REM").measure("REM a"), Ok(5));
+
+        // Java blocks don't nest, the first terminator closes them
+        let tokens = run_lexer("/* /* */ int a; // x /* \nint b; /*/ */ int c;");
+        let ints = tokens
+            .iter()
+            .filter(|t| **t == JavaToken::Identifier(JavaIdentifier::JavaKeyword(JavaKeyword::Int)))
+            .count();
+        assert_eq!(ints, 3);
+        assert_eq!(
+            <JavaTokenSet>::comment_at("/** doc */"),
+            Some(&<JavaTokenSet>::comments()[1])
+        );
+        assert_eq!(<JavaTokenSet>::comment_at("/ 2"), None);
+    }
+
+    // 🔥 Text blocks, raw and tagged strings close on what their opener asks for
+    #[test]
+    fn string_styles() {
+        let src = "String t = \"\"\"\n  a \"quoted\" {\n  b\\tc\"\"\";\nint x = \"\";";
+        let tokens = run_lexer(src);
+        assert_eq!(
+            tokens[3],
+            JavaToken::Identifier(JavaIdentifier::StringLiteral(
+                "\n  a \"quoted\" {\n  b\tc".into()
+            ))
+        );
+        assert_token_present(
+            &tokens,
+            JavaToken::Identifier(JavaIdentifier::StringLiteral("".into())),
+        );
+
+        let mut lexer = Lexer::<JavaTokenSet>::from_str("String t = \"\"\"\n never closed \" {");
+        let _ = lexer.by_ref().count();
+        let errors = lexer.take_diagnostics();
+        assert_eq!(errors[0].kind, LexicalErrorKind::UnterminatedString);
+        assert_eq!(errors[0].expected.as_deref(), Some("\"\"\""));
+
+        let rust = StringStyle::Padded {
+            prefix: "r",
+            pad: '#',
+            quote: '"',
+        };
+        let cpp = StringStyle::Tagged {
+            prefix: "R\"",
+            open: '(',
+            close: ')',
+            quote: '"',
+        };
+        let python = StringStyle::Quoted {
+            open: "'''",
+            close: "'''",
+            escapes: true,
+            multiline: true,
+        };
+        let measure = |style: &StringStyle, s: &str| style.opens(s).unwrap().measure(s);
+        assert_eq!(measure(&rust, "r##\"a \"# b\"## c"), Ok(13));
+        assert_eq!(measure(&rust, "r\"\\\" x"), Ok(4));
+        assert_eq!(rust.opens("return"), None);
+        assert_eq!(measure(&cpp, "R\"x(a)\" b)x\" c"), Ok(12));
+        assert_eq!(measure(&cpp, "R\"(never"), Err(8));
+        assert_eq!(measure(&python, "'''a\\''' b\n'''"), Ok(14));
+
+        // braces inside text blocks must not throw off the chunk starts
+        let src = "class A {\n String s = \"\"\"\n }}}\n ((\n \"\"\"; int f() { return 1; }\n}\n"
+            .repeat(200);
+        let expected: Vec<JavaSpannedToken> = Lexer::<JavaTokenSet>::from_str(&src).collect();
+        let mut got: Vec<JavaSpannedToken> = Vec::new();
+        tokenize_parallel::<JavaTokenSet>(&src, 4, None, &mut got).unwrap();
+        assert_eq!(got, expected);
+    }
+
+    // 🔥 Expressions embedded in string templates come out as real tokens
+    #[test]
+    fn string_template_modes() {
+        let src = "s = \"a \\{x + f(\"b \\{y}\", new int[]{1})} c\";";
+        let tokens: Vec<_> = Lexer::<JavaTemplateTokenSet>::from_str(src)
+            .map(|t| t.token)
+            .collect();
+        let str_lit =
+            |s: &'static str| JavaToken::Identifier(JavaIdentifier::StringLiteral(s.into()));
+        let start = JavaToken::Delimeter(JavaDelimeters::EmbedStart);
+        let end = JavaToken::Delimeter(JavaDelimeters::EmbedEnd);
+        let shape: Vec<_> = tokens
+            .iter()
+            .filter(|t| {
+                matches!(t, JavaToken::Identifier(JavaIdentifier::StringLiteral(_)))
+                    || **t == start
+                    || **t == end
+            })
+            .cloned()
+            .collect();
+        assert_eq!(
+            shape,
+            [
+                str_lit("a "),
+                start.clone(),
+                str_lit("b "),
+                start,
+                end.clone(),
+                str_lit(""),
+                end,
+                str_lit(" c"),
+            ]
+        );
+        assert_eq!(
+            tokens.last(),
+            Some(&JavaToken::Delimeter(JavaDelimeters::Semicolon))
+        );
+
+        let mut lexer = Lexer::<JavaTemplateTokenSet>::from_str("s = \"a \\{x + 1\n");
+        let _ = lexer.by_ref().count();
+        assert!(lexer.state.mode().is_some());
+        let errors = lexer.take_diagnostics();
+        assert_eq!(errors[0].kind, LexicalErrorKind::UnterminatedString);
+        assert_eq!(errors[0].expected.as_deref(), Some("\""));
+
+        // templates are gone from the JDK, plain Java reads `\{` as a bad escape
+        let mut lexer = Lexer::<JavaTokenSet>::from_str(src);
+        assert!(
+            lexer
+                .by_ref()
+                .all(|t| !matches!(t.token, JavaToken::Delimeter(JavaDelimeters::EmbedStart)))
+        );
+        let errors = lexer.take_diagnostics();
+        assert_eq!(errors[0].kind, LexicalErrorKind::InvalidEscape);
+
+        // the mode stack is part of the checkpoints incremental and chunked lexing resync on
+        let unit = "t = \"\"\"\n \\{ a.b(\"\"\"\n  {\n  \"\"\") }\n \"\"\";\n";
+        let mut doc = Document::<JavaTemplateTokenSet>::new(unit.repeat(20));
+        doc.edit(10..10, "{ c } +");
+        let fresh = Document::<JavaTemplateTokenSet>::new(doc.text().to_string());
+        assert_eq!(doc.tokens(), fresh.tokens());
+        let src = unit.repeat(300);
+        let expected: Vec<JavaSpannedToken> =
+            Lexer::<JavaTemplateTokenSet>::from_str(&src).collect();
+        let mut got: Vec<JavaSpannedToken> = Vec::new();
+        tokenize_parallel::<JavaTemplateTokenSet>(&src, 4, None, &mut got).unwrap();
+        assert_eq!(got, expected);
+
+        // input ending inside an embedded expression is reported once, whatever the chunks
+        for src in [
+            "\"open \\{ a/*}\n\"open \\{ a",
+            "'\"open \\{ a\"open \\{ a\n",
+        ] {
+            let mut whole = Lexer::<JavaTemplateTokenSet>::from_str(src);
+            let expected: Vec<JavaSpannedToken> = whole.by_ref().collect();
+            let mut got: Vec<JavaSpannedToken> = Vec::new();
+            let errors = tokenize_parallel::<JavaTemplateTokenSet>(src, 4, None, &mut got);
+            assert_eq!(got, expected);
+            assert_eq!(errors.unwrap(), whole.take_diagnostics());
+        }
+    }
+
+    // 🔥 Identifiers follow Unicode XID plus Java's `$` and `_`
+    #[test]
+    fn unicode_identifiers() {
+        let src = "int zażółć = 1;\u{3000}String 名前 = x·y; long $tmp_1 = _a; double € = a#b;";
+        let mut lexer = Lexer::<JavaTokenSet>::from_str(src);
+        let texts: Vec<&str> = lexer
+            .by_ref()
+            .map(|t| &src[t.span.start..t.span.end])
+            .collect();
+        for name in ["zażółć", "名前", "x·y", "$tmp_1", "_a", "a", "b"] {
+            assert!(texts.contains(&name), "{} in {:?}", name, texts);
+        }
+        let stray: Vec<(LexicalErrorKind, Option<String>)> = lexer
+            .take_diagnostics()
+            .into_iter()
+            .map(|e| (e.kind, e.found))
+            .collect();
+        // neither is an identifier char
+        assert_eq!(
+            stray,
+            [
+                (LexicalErrorKind::UnexpectedChar, Some("€".into())),
+                (LexicalErrorKind::UnexpectedChar, Some("#".into())),
+            ]
+        );
+        assert!(<JavaTokenSet>::is_ident_start('ł') && !<JavaTokenSet>::is_ident_start('1'));
+        assert!(<JavaTokenSet>::is_ident_continue('1') && !<JavaTokenSet>::is_delimeter(&'ł'));
+
+        // underscores and digits after them name things, only a digit starts a number
+        for name in ["_", "__", "_1", "_1_000"] {
+            assert_eq!(
+                run_lexer(name),
+                [JavaToken::Identifier(JavaIdentifier::Unknown(Ident::new(
+                    name, 0
+                )))]
+            );
+        }
+        assert!(matches!(
+            run_lexer("1_000")[..],
+            [JavaToken::Identifier(JavaIdentifier::Integer(
+                _,
+                JavaBase::Decimal
+            ))]
+        ));
+    }
+
+    // 🔥 Generics next to real shifts and comparisons
+    #[test]
+    fn generic_angles_and_shifts() {
+        let src = "List<List<String>> xs = new ArrayList<>(); int y = a >> 2; x >>>= 1; \
+                   if (i < MAX && j > 0) {} Map<String, java.util.List<? extends T>> m;";
+        let texts: Vec<(&str, JavaToken)> = run_lexer_spanned(src)
+            .into_iter()
+            .map(|t| (&src[t.span.start..t.span.end], t.token))
+            .filter(|(text, _)| text.starts_with(['<', '>']))
+            .collect();
+        let angle = |d| JavaToken::Delimeter(d);
+        let op = |o| JavaToken::Operator(o);
+        let expected = [
+            ("<", angle(JavaDelimeters::LAngle)),
+            ("<", angle(JavaDelimeters::LAngle)),
+            (">", angle(JavaDelimeters::RAngle)),
+            (">", angle(JavaDelimeters::RAngle)),
+            ("<", angle(JavaDelimeters::LAngle)),
+            (">", angle(JavaDelimeters::RAngle)),
+            (">>", op(JavaOperator::BitShiftRight)),
+            (">>>=", op(JavaOperator::UBitShiftRightEq)),
+            ("<", op(JavaOperator::Lt)),
+            (">", op(JavaOperator::Gt)),
+            ("<", angle(JavaDelimeters::LAngle)),
+            ("<", angle(JavaDelimeters::LAngle)),
+            (">", angle(JavaDelimeters::RAngle)),
+            (">", angle(JavaDelimeters::RAngle)),
+        ];
+        assert_eq!(texts, expected);
+    }
+
+    // 🔥 Identifier names survive hashing, threads and owned tokens
+    #[test]
+    fn interned_identifier_names() {
+        let src = "int foo = bar; { long foo = bär_2; }";
+        let idents: Vec<Ident> = run_lexer(src)
+            .into_iter()
+            .map(JavaToken::into_owned)
+            .filter_map(|t| match t {
+                JavaToken::Identifier(JavaIdentifier::Unknown(id)) => Some(id),
+                _ => None,
+            })
+            .collect();
+        let names: Vec<&str> = idents.iter().filter_map(Ident::name).collect();
+        assert_eq!(names, vec!["foo", "bar", "foo", "bär_2"]);
+        // same name, other scope: the hash tells them apart, the symbol is shared
+        assert_ne!(idents[0], idents[2]);
+        assert_eq!(idents[0].symbol, idents[2].symbol);
+        assert_eq!(idents[0].symbol.unwrap().to_string(), "foo");
+        // a hash two names share doesn't make them the same identifier
+        let twin = Ident {
+            symbol: idents[1].symbol,
+            ..idents[0]
+        };
+        assert_ne!(twin, idents[0]);
+
+        let symbols: Vec<Vec<Option<Symbol>>> = thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| ["foo", "bär_2", "quux"].map(Symbol::intern).to_vec()))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        assert!(
+            symbols
+                .iter()
+                .all(|v| v[..2] == [idents[0].symbol, idents[3].symbol])
+        );
+        assert!(symbols.windows(2).all(|w| w[0] == w[1]));
+        assert_eq!(symbols[0][2].unwrap().as_str(), "quux");
+
+        // a full interner keeps the names it has and takes no new ones
+        let small = Interner::with_capacity(16);
+        let a = small.intern("a").unwrap();
+        assert_eq!(small.intern("too long"), None);
+        assert_eq!(small.intern("a"), Some(a));
+        assert_eq!(small.resolve(a), "a");
+        assert_eq!(small.len(), 1);
+    }
+
+    // 🔥 Colliding hashes are reported and moved apart
+    #[test]
+    fn hash_collisions_are_disambiguated() {
+        let mut table = HashTable::default();
+        assert_eq!(table.check(7, key(b"first", 0)), 7);
+        let moved = table.check(7, key(b"second", 2));
+        assert_ne!(moved, 7);
+        // the same identifier keeps its new hash, the first one keeps the old one
+        assert_eq!(table.check(7, key(b"second", 2)), moved);
+        assert_eq!(table.check(7, key(b"first", 0)), 7);
+
+        let report = table.report();
+        assert_eq!(
+            (report.lookups, report.identifiers, report.names),
+            (4, 2, 2)
+        );
+        assert_eq!(report.collisions.len(), 1);
+        assert_eq!(report.collisions[0].first, ("first".to_string(), 0));
+        assert_eq!(report.collisions[0].second, ("second".to_string(), 2));
+        assert_eq!(report.collisions[0].resolved, moved);
+        assert!(
+            report.collisions[0]
+                .to_string()
+                .starts_with("`first` (level 0) and `second` (level 2) share 0x0000000000000007")
+        );
+
+        // any tokenset going through normalize is checked by a lexer given a table
+        let src = "int verifiedName = 1; { verifiedName++; }";
+        let table = Arc::new(Mutex::new(HashTable::default()));
+        let mut lexer = Lexer::<JavaTokenSet>::from_str(src);
+        lexer.hashes = Some(table.clone());
+        let tokens: Vec<JavaToken> = lexer.map(|t| t.token).collect();
+        assert!(
+            tokens.contains(&JavaToken::Identifier(JavaIdentifier::Unknown(Ident::new(
+                "verifiedName",
+                1
+            ))))
+        );
+        // lexers without it leave it alone
+        run_lexer(src);
+        let report = table.lock().unwrap().report();
+        assert_eq!(
+            (report.lookups, report.identifiers, report.names),
+            (2, 2, 1)
+        );
+        assert!(report.collisions.is_empty());
+
+        // streamed input is checked too, and a long name hashes the same verified or not
+        let long = "a".repeat(100);
+        let src = format!("int {} = 1;", long);
+        let seen = table.lock().unwrap().report().names;
+        let mut streamed = Vec::new();
+        tokenize_reader::<JavaTokenSet>(
+            src.as_bytes(),
+            4,
+            &Limits::default(),
+            Some(&table),
+            &mut streamed,
+        )
+        .unwrap();
+        assert_eq!(
+            streamed[1].token,
+            JavaToken::Identifier(JavaIdentifier::Unknown(Ident::new(&long, 0)))
+        );
+        assert!(table.lock().unwrap().report().names > seen);
+    }
+
+    // 🔥 Hostile input hits a limit instead of a panic
+    #[test]
+    fn limits_and_cancellation() {
+        let limited = |src: &'static str, limits: Limits| {
+            let mut lexer = Lexer::<JavaTokenSet>::from_str(src);
+            lexer.limits = limits;
+            let tokens: Vec<JavaSpannedToken> = lexer.by_ref().collect();
+            (tokens.len(), lexer.stopped().cloned())
+        };
+
+        let mut got = 0;
+        let sink = Callback(|batch: Vec<JavaSpannedToken>| {
+            got += batch.len();
+            Ok(())
+        });
+        let mut lexer = Lexer::<JavaTokenSet>::new("int a = 1; int b = 2;", sink.batched(2));
+        lexer.limits.max_tokens = 3;
+        let err = lexer.tokenize().unwrap_err();
+        drop(lexer);
+        assert!(matches!(err, LexError::Limit(LimitError::TooManyTokens(3))));
+        assert_eq!(got, 3);
+
+        let deep = Limits {
+            max_depth: 2,
+            ..Limits::default()
+        };
+        let (_, stopped) = limited("{ {\n  ({ x; }) } }", deep);
+        let stopped = stopped.unwrap();
+        assert_eq!(stopped.to_string(), "2:4: nesting deeper than 2");
+
+        let long = Limits {
+            max_literal: 16,
+            ..Limits::default()
+        };
+        let (count, stopped) = limited("int a; /* never closed, and far too long", long);
+        assert_eq!(count, 3);
+        assert!(matches!(
+            stopped,
+            Some(LimitError::LiteralTooLong {
+                limit: 16,
+                row: 0,
+                column: 7
+            })
+        ));
+
+        // the eaters give up at the budget, the rest of the lexeme is never walked
+        let x = "x".repeat(4096);
+        for src in [
+            format!("int a; \"{}\";", x),
+            format!("int a; // {}\n", x),
+            format!("int a; /* /* {} */", x),
+        ] {
+            let mut lexer = Lexer::<JavaTokenSet>::from_str(&src);
+            lexer.limits.max_literal = 16;
+            assert_eq!(lexer.by_ref().count(), 3);
+            assert!(matches!(
+                lexer.stopped(),
+                Some(LimitError::LiteralTooLong { column: 7, .. })
+            ));
+            assert!(lexer.state.offset <= 7 + 17);
+        }
+
+        let cancel = CancelToken::new();
+        cancel.clone().cancel();
+        let cancelled = Limits {
+            cancel: Some(cancel),
+            ..Limits::default()
+        };
+        assert_eq!(
+            limited("int a;", cancelled),
+            (0, Some(LimitError::Cancelled))
+        );
+
+        let src: &'static str = "x = y;\n".repeat(5000).leak();
+        let late = Limits {
+            timeout: Some(Duration::ZERO),
+            ..Limits::default()
+        };
+        let (count, stopped) = limited(src, late);
+        assert!(count < 20000 && stopped == Some(LimitError::Timeout(Duration::ZERO)));
+
+        // stray closers used to underflow the nesting counters
+        let mut lexer = Lexer::<JavaTokenSet>::from_str("} ) int a = (b); }");
+        assert_eq!(lexer.by_ref().count(), 10);
+        assert!(lexer.stopped().is_none());
+        let kinds: Vec<(LexicalErrorKind, usize)> = lexer
+            .diagnostics()
+            .iter()
+            .map(|e| (e.kind, e.column))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (LexicalErrorKind::UnmatchedDelimiter, 0),
+                (LexicalErrorKind::UnmatchedDelimiter, 2),
+                (LexicalErrorKind::UnmatchedDelimiter, 17),
+            ]
+        );
+    }
+
+    // 🔥 Streaming through tiny windows matches one big lex
+    #[test]
+    fn streamed_input_matches_whole_lex() {
+        /// Hands out at most `step` bytes per read, splitting chars and lines anywhere
+        struct Trickle<'s>(&'s [u8], usize);
+        impl std::io::Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = self.1.min(buf.len()).min(self.0.len());
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        let src = "class Año {\n  /* a comment\n  over lines */ String t = \"\"\"\n  text \\{ f(\n\"in\") }\n  block\"\"\";\n  char c = 'ü'; } }\n"
+            .repeat(6)
+            + "int λ = a >> 2;\nString open = \"never closed";
+        let mut whole = Lexer::<JavaTokenSet>::from_str(&src);
+        whole.resolve_imports = false;
+        let expected: Vec<JavaSpannedToken<'static>> = whole
+            .by_ref()
+            .map(|t| t.map(JavaToken::into_owned))
+            .collect();
+        let expected_errors = whole.take_diagnostics();
+        assert!(!expected_errors.is_empty());
+
+        for (window, step) in [(1, 1), (16, 3), (40, 7), (1 << 16, 1 << 16)] {
+            let mut streamed: Vec<JavaSpannedToken<'static>> = Vec::new();
+            let errors = tokenize_reader::<JavaTokenSet>(
+                Trickle(src.as_bytes(), step),
+                window,
+                &Limits::default(),
+                None,
+                &mut streamed,
+            )
+            .unwrap();
+            assert_eq!(streamed, expected, "window {} read {}", window, step);
+            assert_eq!(errors, expected_errors, "window {} read {}", window, step);
+        }
+
+        let src = "_'\"unterminat'c'/* blk\n */\rint";
+        let mut whole = Lexer::<JavaTokenSet>::from_str(src);
+        whole.resolve_imports = false;
+        let expected: Vec<_> = whole.by_ref().collect();
+        let expected_errors = whole.take_diagnostics();
+        for window in 1..8 {
+            let mut streamed = Vec::new();
+            let errors = tokenize_reader::<JavaTokenSet>(
+                Trickle(src.as_bytes(), 3),
+                window,
+                &Limits::default(),
+                None,
+                &mut streamed,
+            )
+            .unwrap();
+            assert_eq!(streamed, expected, "window {}", window);
+            assert_eq!(errors, expected_errors, "window {}", window);
+        }
+
+        // a template fragment resumed right before its line break keeps its diagnostic
+        let src = "s = \"open \\{ a}\r\nint b;\n";
+        let mut whole = Lexer::<JavaTemplateTokenSet>::from_str(src);
+        whole.resolve_imports = false;
+        let expected: Vec<_> = whole.by_ref().collect();
+        let expected_errors = whole.take_diagnostics();
+        assert_eq!(expected_errors.len(), 1);
+        for window in 1..src.len() {
+            let mut streamed = Vec::new();
+            let errors = tokenize_reader::<JavaTemplateTokenSet>(
+                Trickle(src.as_bytes(), 1),
+                window,
+                &Limits::default(),
+                None,
+                &mut streamed,
+            )
+            .unwrap();
+            assert_eq!(streamed, expected, "window {}", window);
+            assert_eq!(errors, expected_errors, "window {}", window);
+        }
+
+        // a line longer than the window goes out as it is read
+        let line = "int a = b >> 2; ".repeat(512);
+        let mut whole = Lexer::<JavaTokenSet>::from_str(&line);
+        whole.resolve_imports = false;
+        let expected: Vec<_> = whole.map(|t| t.map(JavaToken::into_owned)).collect();
+        let mut streamed = Vec::new();
+        tokenize_reader::<JavaTokenSet>(
+            Trickle(line.as_bytes(), 7),
+            64,
+            &Limits::default(),
+            None,
+            &mut streamed,
+        )
+        .unwrap();
+        assert_eq!(streamed, expected);
+
+        // an open lexeme grows its window only until it is past the limits
+        let comment = format!("int a; /* {}", "x".repeat(1 << 20));
+        let mut reader = Trickle(comment.as_bytes(), 1 << 12);
+        let limits = Limits {
+            max_literal: 1 << 12,
+            ..Limits::default()
+        };
+        let mut streamed = Vec::new();
+        let err = tokenize_reader::<JavaTokenSet>(&mut reader, 64, &limits, None, &mut streamed)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            LexError::Limit(LimitError::LiteralTooLong { .. })
+        ));
+        assert_eq!(streamed.len(), 3);
+        assert!(reader.0.len() > comment.len() / 2);
+
+        let err = tokenize_reader::<JavaTokenSet>(
+            Trickle(b"int a;\nint \xff;\n", 4),
+            8,
+            &Limits::default(),
+            None,
+            &mut Vec::new(),
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, LexError::Input(ref e) if e.kind() == std::io::ErrorKind::InvalidData)
+        );
+    }
+
+    // 🔥 Latin-1, UTF-16 and broken bytes decode instead of crashing
+    #[test]
+    fn input_encodings_are_detected_and_decoded() {
+        /// Hands out a byte per read, so every char arrives split
+        struct OneByte<'s>(&'s [u8]);
+        impl std::io::Read for OneByte<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = buf.len().min(self.0.len()).min(1);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        let src = "class Café { String s = \"naïve €\"; }\n";
+        let utf16 = |bom: &[u8], le: bool| {
+            let mut bytes = bom.to_vec();
+            for unit in src.encode_utf16() {
+                bytes.extend(if le {
+                    unit.to_le_bytes()
+                } else {
+                    unit.to_be_bytes()
+                });
+            }
+            bytes
+        };
+        // Latin-1 has no €, Windows-1252 puts it at 0x80
+        let cp1252: Vec<u8> = src
+            .chars()
+            .map(|c| if c == '€' { 0x80 } else { c as u8 })
+            .collect();
+        let mut utf8_bom = b"\xEF\xBB\xBF".to_vec();
+        utf8_bom.extend(src.as_bytes());
+        for (bytes, encoding) in [
+            (src.as_bytes().to_vec(), Encoding::Utf8),
+            (utf8_bom, Encoding::Utf8),
+            (utf16(b"\xFF\xFE", true), Encoding::Utf16Le),
+            (utf16(b"\xFE\xFF", false), Encoding::Utf16Be),
+            (utf16(b"", true), Encoding::Utf16Le),
+            (cp1252.clone(), Encoding::Windows1252),
+        ] {
+            let decoded = decode(&bytes, None, false).unwrap();
+            assert_eq!((&*decoded.text, decoded.encoding), (src, encoding));
+            assert!(decoded.diagnostics.is_empty());
+
+            let mut text = String::new();
+            let mut reader = Transcode::new(OneByte(&bytes), None, false);
+            std::io::Read::read_to_string(&mut reader, &mut text).unwrap();
+            assert_eq!(text, src);
+        }
+        let latin1 = decode(&cp1252, Some(Encoding::Latin1), false).unwrap();
+        assert_eq!(latin1.text, src.replace('€', "\u{80}"));
+        assert_eq!("cp1252".parse(), Ok(Encoding::Windows1252));
+
+        // UTF-8 with a broken byte after a real UTF-8 char stays UTF-8
+        let broken = b"int \xc3\xa9 = 1;\nint \xff = 2;\n";
+        assert_eq!(
+            decode(broken, None, false).unwrap_err(),
+            DecodeError {
+                encoding: Encoding::Utf8,
+                offset: 16,
+                bytes: vec![0xff],
+            }
+        );
+        let lossy = decode(broken, None, true).unwrap();
+        assert_eq!(lossy.text, "int é = 1;\nint \u{FFFD} = 2;\n");
+        let err = &lossy.diagnostics[0];
+        assert_eq!(
+            (err.kind, err.row, err.column, err.offset),
+            (LexicalErrorKind::InvalidEncoding, 1, 4, 16)
+        );
+        assert_eq!(
+            err.to_string(),
+            "2:5: invalid byte sequence, expected `UTF-8`, found `\\xff`"
+        );
+
+        let mut reader = Transcode::new(&broken[..], None, true);
+        let mut streamed = Vec::new();
+        let errors = tokenize_reader::<JavaTokenSet>(
+            &mut reader,
+            4,
+            &Limits::default(),
+            None,
+            &mut streamed,
+        )
+        .unwrap();
+        assert!(
+            errors
+                .iter()
+                .all(|e| e.kind != LexicalErrorKind::InvalidEncoding)
+        );
+        assert_eq!(reader.decoder().diagnostics(), &lossy.diagnostics[..]);
+        let mut whole = Lexer::<JavaTokenSet>::from_str(&lossy.text);
+        whole.resolve_imports = false;
+        assert_eq!(streamed, whole.by_ref().collect::<Vec<_>>());
+        assert_eq!(errors, whole.take_diagnostics());
+
+        // a `\r\n` read in two pieces is still one line break
+        let crlf = [&b"x".repeat(1100)[..], b"\r\n", &b"y".repeat(100), b"\xff"].concat();
+        let mut reader = Transcode::new(OneByte(&crlf), Some(Encoding::Utf8), true);
+        std::io::Read::read_to_string(&mut reader, &mut String::new()).unwrap();
+        let lossy = decode(&crlf, Some(Encoding::Utf8), true).unwrap();
+        assert_eq!(lossy.diagnostics[0].row, 1);
+        assert_eq!(reader.decoder().diagnostics(), &lossy.diagnostics[..]);
+
+        // a lone surrogate and an odd trailing byte in UTF-16
+        let lossy = decode(b"a\0\x00\xd8b\0c", Some(Encoding::Utf16Le), true).unwrap();
+        assert_eq!(lossy.text, "a\u{FFFD}b\u{FFFD}");
+        assert_eq!(lossy.diagnostics[1].found.as_deref(), Some("\\x63"));
+    }
+
+    // 🔥 CRLF and lone CR files get right rows, and columns in every unit
+    #[test]
+    fn line_endings_and_position_encodings() {
+        let unix = "class A { // note\n  String s = \"😀é\"; int x;\n  char c = 'q';\n}\n";
+        let lex = |src: &str| {
+            let mut lexer = Lexer::<JavaTokenSet>::from_str(src);
+            let tokens: Vec<_> = lexer
+                .by_ref()
+                .map(|t| (t.span.row, t.span.column))
+                .collect();
+            assert_eq!(*lexer.lines(), LineIndex::new(src));
+            (tokens, lexer.take_diagnostics())
+        };
+        for ending in ["\r\n", "\r"] {
+            let src = unix.replace('\n', ending);
+            assert_eq!(lex(&src), lex(unix), "{:?}", ending);
+            let tokens: Vec<JavaSpannedToken> =
+                Lexer::<JavaTokenSet>::from_str_lossless(&src).collect();
+            assert_eq!(reconstruct(&tokens), src);
+            let comment = &tokens[3].lossless.as_ref().unwrap().leading;
+            assert_eq!(
+                comment
+                    .iter()
+                    .map(|t| (t.kind, t.text.as_str()))
+                    .collect::<Vec<_>>(),
+                [
+                    (TriviaKind::Whitespace, " "),
+                    (TriviaKind::LineComment, "// note"),
+                    (TriviaKind::Newline, ending),
+                    (TriviaKind::Whitespace, "  ")
+                ]
+            );
+            let mut got: Vec<JavaSpannedToken> = Vec::new();
+            let many = src.repeat(200);
+            tokenize_parallel::<JavaTokenSet>(&many, 4, None, &mut got).unwrap();
+            let mut sequential = Lexer::<JavaTokenSet>::from_str(&src);
+            let expected: Vec<JavaSpannedToken> = sequential.by_ref().collect();
+            assert_eq!(
+                got[got.len() - expected.len()..]
+                    .iter()
+                    .map(|t| t.span.row - 199 * 4)
+                    .collect::<Vec<_>>(),
+                expected.iter().map(|t| t.span.row).collect::<Vec<_>>()
+            );
+        }
+
+        // `x` after a char taking two UTF-16 units and a two byte one
+        let src = unix.replace('\n', "\r\n");
+        let lines = LineIndex::new(&src);
+        let x = src.find("x;").unwrap();
+        for (encoding, character) in [
+            (PositionEncoding::Utf8, 27),
+            (PositionEncoding::Utf16, 24),
+            (PositionEncoding::Utf32, 23),
+        ] {
+            let at = Position { line: 1, character };
+            assert_eq!(lines.position(&src, x, encoding), at);
+            assert_eq!(lines.offset(&src, at, encoding), Some(x));
+        }
+        // the middle of the emoji, past the end of a line, an unknown line
+        let emoji = src.find('😀').unwrap();
+        assert_eq!(
+            lines
+                .position(&src, emoji + 2, PositionEncoding::Utf16)
+                .character,
+            14
+        );
+        let past = Position {
+            line: 0,
+            character: 99,
+        };
+        assert_eq!(lines.offset(&src, past, PositionEncoding::Utf16), Some(17));
+        assert_eq!(
+            lines.offset(
+                &src,
+                Position {
+                    line: 9,
+                    character: 0
+                },
+                PositionEncoding::Utf16
+            ),
+            None
+        );
+        assert_eq!(lines.len(), 5);
+
+        // a Document keeps its index through edits that make and break `\r\n` pairs
+        let mut doc = Document::<JavaTokenSet>::new(src.clone());
+        for (range, replacement) in [
+            (17..18, ""),
+            (17..17, "\n"),
+            (0..0, "\r"),
+            (30..40, "\r\n\r"),
+            (3..3, "é\n"),
+        ] {
+            doc.edit(range, replacement);
+            assert_eq!(*doc.lines(), LineIndex::new(doc.text()), "{:?}", doc.text());
+        }
+    }
+
+    // 🔥 Printed tokens lex back to the same stream
+    #[test]
+    fn printer_round_trips() {
+        let src = "@Override\npublic void run(List<Map<K, V>> xs) {\n  int a = 0x1F - -b, c = i++ + ++j;\n  char q = '\\'', t = '\\t';\n  s = \"say \\\"hi\\\"\\\\ \\u0001\" + \"n = \\{a.b(1.5e3)} ok\";\n  if (!done && x instanceof Y) { go(); }\n}\n";
+        let tokens = |src: &str| -> Vec<JavaToken<'static>> {
+            Lexer::<JavaTemplateTokenSet>::from_str(src)
+                .map(|t| t.token.into_owned())
+                .collect()
+        };
+        let lexed: Vec<JavaSpannedToken> = Lexer::<JavaTemplateTokenSet>::from_str(src).collect();
+        let canonical = print(&lexed, PrintMode::Canonical);
+        assert_eq!(tokens(&canonical), tokens(src), "{}", canonical);
+        // printing is stable once the spacing is canonical
+        let again: Vec<JavaSpannedToken> =
+            Lexer::<JavaTemplateTokenSet>::from_str(&canonical).collect();
+        assert_eq!(print(&again, PrintMode::Canonical), canonical);
+        assert!(canonical.contains("List<Map<K, V>> xs"), "{}", canonical);
+        assert!(
+            canonical.contains("a = 0x1F - - b, c = i++ + ++j;"),
+            "{}",
+            canonical
+        );
+        assert!(canonical.contains("'\\''"), "{}", canonical);
+        assert!(
+            canonical.contains("\"n = \\{a.b(1.5e3)} ok\""),
+            "{}",
+            canonical
+        );
+        assert!(
+            canonical.contains("{\n        go();\n    }\n}\n"),
+            "{}",
+            canonical
+        );
+
+        // a number stays apart from a tight operator it would take in
+        let numbers = "a = 3.14 .f; b = 1 --; c = 0x1F ++ + 2;";
+        let lexed_numbers: Vec<JavaSpannedToken> =
+            Lexer::<JavaTemplateTokenSet>::from_str(numbers).collect();
+        let printed = print(&lexed_numbers, PrintMode::Canonical);
+        assert_eq!(tokens(&printed), tokens(numbers), "{}", printed);
+        assert!(printed.contains("3.14 .f"), "{}", printed);
+
+        // exact mode gives the source back, and falls back to canonical without trivia
+        let lossless: Vec<JavaSpannedToken> =
+            Lexer::<JavaTemplateTokenSet>::from_str_lossless(src).collect();
+        assert_eq!(print(&lossless, PrintMode::Exact), src);
+        assert_eq!(print(&lexed, PrintMode::Exact), canonical);
+    }
+
+    // 🔥 Grammar rules build LALR tables that parse Java tokens
+    #[test]
+    fn lalr_tables_parse_token_streams() {
+        let mut grammar = Grammar::parse(
+            "Expr = [Expr, Plus, Term];
+            Expr = [Term];
+            # groups are optional
+            Term = [Term, \"*\", Factor];
+            Term = [Factor];
+            Factor = [\"(\", Expr, \")\"];
+            Factor = [NumberLiteral];
+            Factor = [Identifier, [\"(\", [Args], \")\"]];
+            Args = [Expr, [\",\", Args]];",
+        )
+        .unwrap();
+        grammar.tokens.insert("Plus".into(), "+".into());
+        let parser = Parser::new(&grammar).unwrap();
+        let tree = parser
+            .parse(Lexer::<JavaTokenSet>::from_str("a + 2 * f(b, g())"))
+            .unwrap();
+        assert_eq!(
+            parser.render(&tree),
+            "(Expr (Expr (Term (Factor a))) + (Term (Term (Factor 2)) * \
+             (Factor f ( (Args (Expr (Term (Factor b))) , (Args (Expr (Term (Factor g ( )))))) ))))"
+        );
+        let (first, last) = tree.tokens().unwrap();
+        assert_eq!((first.span.start, last.span.end), (0, 17));
+        // the EOF of lossless mode is no terminal of this grammar
+        let lossless = parser
+            .parse(Lexer::<JavaTokenSet>::from_str_lossless(
+                "a + 2 * f(b, g())",
+            ))
+            .unwrap();
+        assert_eq!(parser.render(&lossless), parser.render(&tree));
+
+        let error = parser
+            .parse(Lexer::<JavaTokenSet>::from_str("a +\n * b"))
+            .unwrap_err();
+        assert_eq!((error.row, error.column), (1, 1));
+        assert_eq!(error.found.as_deref(), Some("*"));
+        assert_eq!(error.expected, ["\"(\"", "NumberLiteral", "Identifier"]);
+        let error = parser
+            .parse(Lexer::<JavaTokenSet>::from_str("(a"))
+            .unwrap_err();
+        assert_eq!((error.found, error.offset), (None, 2));
+
+        let ambiguous = Grammar::parse("E = [E, \"+\", E];\nE = [Identifier];").unwrap();
+        let Err(GrammarError::Conflicts(conflicts)) = Parser::new(&ambiguous) else {
+            panic!("ambiguous grammar built");
+        };
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kind, ConflictKind::ShiftReduce);
+        assert_eq!(conflicts[0].lookahead, "\"+\"");
+        assert_eq!(conflicts[0].items, ["E = E \"+\" E .", "E = E . \"+\" E"]);
+        assert_eq!(
+            Grammar::parse("E = [Identifier];\nBad = Identifier;"),
+            Err(GrammarError::Syntax {
+                line: 2,
+                text: "Bad = Identifier;".into()
+            })
+        );
+    }
+
+    // 🔥 Generated descent parsers agree with the LALR tables
+    #[test]
+    fn descent_parsers_are_generated() {
+        let mut grammar = Grammar::parse(
+            "Expr = [Term, [Plus, Expr]];
+            Term = [Factor, [\"*\", Term]];
+            Factor = [\"(\", Expr, \")\"];
+            Factor = [NumberLiteral];
+            Factor = [Identifier, [\"(\", [Args], \")\"]];
+            Args = [Expr, [\",\", Args]];",
+        )
+        .unwrap();
+        grammar.tokens.insert("Plus".into(), "+".into());
+        // the checked in parser is what codegen writes for the grammar today
+        assert_eq!(
+            descent_source(&grammar).unwrap(),
+            include_str!("expr_parser.rs")
+        );
+
+        let lalr = Parser::new(&grammar).unwrap();
+        for src in [
+            "a + 2 * f(b, g())",
+            "(a+b)*c",
+            "f()",
+            "a + ",
+            "a b",
+            "f(,)",
+            "",
+            "((a)",
+        ] {
+            let descent = expr_parser::parse(Lexer::<JavaTokenSet>::from_str(src));
+            let tables = lalr.parse(Lexer::<JavaTokenSet>::from_str(src));
+            match (descent, tables) {
+                (Ok(descent), Ok(tables)) => {
+                    assert_eq!(
+                        descent.render(expr_parser::NONTERMINALS),
+                        lalr.render(&tables),
+                        "{}",
+                        src
+                    )
+                }
+                (descent, tables) => {
+                    assert_eq!(descent.unwrap_err(), tables.unwrap_err(), "{}", src)
+                }
+            }
+        }
+        let error = expr_parser::parse(Lexer::<JavaTokenSet>::from_str("f(,)")).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "1:3: unexpected `,`, expected one of \"(\", \")\", NumberLiteral, Identifier"
+        );
+
+        // left recursion predicts both alternatives on the same tokens
+        let left = Grammar::parse("E = [E, \"+\", Identifier];\nE = [Identifier];").unwrap();
+        let Err(GrammarError::Conflicts(conflicts)) = descent_source(&left) else {
+            panic!("left recursive grammar generated");
+        };
+        assert_eq!(conflicts[0].kind, ConflictKind::Prediction);
+        assert_eq!(
+            conflicts[0].to_string(),
+            "Identifier predicts more than one alternative: E = E \"+\" Identifier | E = Identifier"
+        );
+    }
+
+    // 🔥 Precedence declarations settle operators for both parsers
+    #[test]
+    fn precedence_drives_both_parsers() {
+        let mut grammar = Grammar::parse(
+            "right = \"=\";
+            nonassoc = \"==\";
+            left = \"+\", Minus;
+            left = [\"*\", \"/\"];
+            left = \"++\";
+            E = [E, \"=\", E];
+            E = [E, \"==\", E];
+            E = [E, \"+\", E];
+            E = [E, Minus, E];
+            E = [E, \"*\", E];
+            E = [E, \"/\", E];
+            E = [E, \"++\"];
+            E = [Minus, E];
+            E = [\"(\", E, \")\"];
+            E = [Identifier];
+            E = [NumberLiteral];",
+        )
+        .unwrap();
+        grammar.tokens.insert("Minus".into(), "-".into());
+        assert_eq!(grammar.precedence[2].to_string(), "left = [\"+\", Minus];");
+        // one entry per terminal, however often the rules name it
+        let lowered = grammar.lower().unwrap();
+        assert_eq!(lowered.precedence.len(), lowered.terminals.len());
+        assert_eq!(
+            descent_source(&grammar).unwrap(),
+            include_str!("calc_parser.rs")
+        );
+
+        // without the declarations every operator production conflicts with the others
+        let lalr = Parser::new(&grammar).unwrap();
+        let tree = lalr
+            .parse(Lexer::<JavaTokenSet>::from_str("a - b - c * -d"))
+            .unwrap();
+        assert_eq!(
+            lalr.render(&tree),
+            "(E (E (E a) - (E b)) - (E (E c) * (E - (E d))))"
+        );
+        let tree = lalr
+            .parse(Lexer::<JavaTokenSet>::from_str("a = b = c"))
+            .unwrap();
+        assert_eq!(lalr.render(&tree), "(E (E a) = (E (E b) = (E c)))");
+        for src in [
+            "a + b * c - d",
+            "-a * b++",
+            "- a - b",
+            "x * -y++ / 2",
+            "a == b + c = d",
+            "(a == b) == c",
+            "a == b == c",
+            "a + b == c == d",
+            "a +",
+            "a b",
+            "",
+        ] {
+            let descent = calc_parser::parse(Lexer::<JavaTokenSet>::from_str(src));
+            let tables = lalr.parse(Lexer::<JavaTokenSet>::from_str(src));
+            match (descent, tables) {
+                (Ok(descent), Ok(tables)) => assert_eq!(
+                    descent.render(calc_parser::NONTERMINALS),
+                    lalr.render(&tables),
+                    "{}",
+                    src
+                ),
+                (Err(descent), Err(tables)) => {
+                    assert_eq!(
+                        (descent.offset, descent.found),
+                        (tables.offset, tables.found),
+                        "{}",
+                        src
+                    )
+                }
+                (descent, tables) => panic!("{}: {:?} {:?}", src, descent, tables),
+            }
+        }
+        // nonassoc operators don't chain
+        let error = lalr
+            .parse(Lexer::<JavaTokenSet>::from_str("a == b == c"))
+            .unwrap_err();
+        assert_eq!((error.offset, error.found.as_deref()), (7, Some("==")));
+    }
+
+    // 🔥 Concrete syntax trees from parsers and by hand
+    #[test]
+    fn cst_navigation_and_visitors() {
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        enum Calc {
+            E,
+            Group,
+        }
+        impl Parseable for Calc {
+            fn name(&self) -> &'static str {
+                match self {
+                    Calc::E => "E",
+                    Calc::Group => "Group",
+                }
+            }
+            fn from_symbol(symbol: usize) -> Option<Self> {
+                (calc_parser::NONTERMINALS[symbol] == "E").then_some(Calc::E)
+            }
+        }
+
+        let tree = calc_parser::parse(Lexer::<JavaTokenSet>::from_str("a + b * -c")).unwrap();
+        let rendered = tree.render(calc_parser::NONTERMINALS);
+        let cst = Cst::<_, Calc>::from_parse_tree(tree).unwrap();
+        let root = cst.root();
+        assert_eq!(cst.render(root), rendered);
+        assert_eq!(cst.render(root), "(E (E a) + (E (E b) * (E - (E c))))");
+        let span = cst.span(root).unwrap();
+        assert_eq!((span.start, span.end, span.column), (0, 10, 0));
+        let [left, plus, right] = cst.children(root) else {
+            panic!("{:?}", cst.children(root));
+        };
+        assert_eq!(cst.kind(*left), Some(&Calc::E));
+        assert_eq!(cst.token(*plus).unwrap().token.spelling(), "+");
+        assert_eq!(cst.parent(*plus), Some(root));
+        assert_eq!(cst.next_sibling(*plus), Some(*right));
+        assert_eq!(cst.prev_sibling(*left), None);
+        assert_eq!(cst.next_sibling(*right), None);
+        assert_eq!(cst.span(*right).map(|s| (s.start, s.end)), Some((4, 10)));
+        let c = cst.descendants(root).last().unwrap();
+        assert_eq!(cst.ancestors(c).count(), 4);
+        let spelled: Vec<_> = cst.tokens(*right).map(|t| t.token.spelling()).collect();
+        assert_eq!(spelled, ["b", "*", "-", "c"]);
+
+        struct Trace {
+            seen: Vec<String>,
+            skip: &'static str,
+            stop: &'static str,
+        }
+        impl<'a> Visitor<JavaToken<'a>, Calc> for Trace {
+            fn enter(
+                &mut self,
+                tree: &Cst<JavaToken<'a>, Calc>,
+                node: NodeId,
+                kind: &Calc,
+            ) -> Walk {
+                self.seen.push(format!("({}", kind.name()));
+                let skipped = tree.children(node).iter().any(|&child| {
+                    tree.token(child)
+                        .is_some_and(|t| t.token.spelling() == self.skip)
+                });
+                if skipped { Walk::Skip } else { Walk::Continue }
+            }
+            fn token(
+                &mut self,
+                _: &Cst<JavaToken<'a>, Calc>,
+                _: NodeId,
+                token: &Spanned<JavaToken<'a>>,
+            ) -> Walk {
+                self.seen.push(token.token.spelling().into_owned());
+                if token.token.spelling() == self.stop {
+                    Walk::Stop
+                } else {
+                    Walk::Continue
+                }
+            }
+            fn leave(&mut self, _: &Cst<JavaToken<'a>, Calc>, _: NodeId, _: &Calc) -> Walk {
+                self.seen.push(")".into());
+                Walk::Continue
+            }
+        }
+        let mut trace = Trace {
+            seen: Vec::new(),
+            skip: "",
+            stop: "",
+        };
+        assert_eq!(cst.walk(&mut trace), Walk::Continue);
+        assert_eq!(trace.seen.concat(), "(E(Ea)+(E(Eb)*(E-(Ec))))");
+        // pre and post order of the right operand only, leaving out the product's operands
+        let mut trace = Trace {
+            seen: Vec::new(),
+            skip: "*",
+            stop: "",
+        };
+        assert_eq!(cst.walk_from(*right, &mut trace), Walk::Continue);
+        assert_eq!(trace.seen.concat(), "(E)");
+        let mut trace = Trace {
+            seen: Vec::new(),
+            skip: "",
+            stop: "b",
+        };
+        assert_eq!(cst.walk(&mut trace), Walk::Stop);
+        assert_eq!(trace.seen.concat(), "(E(Ea)+(E(Eb");
+
+        // a language builds its own trees, here one group per pair of brackets
+        let mut cst = Cst::new(Calc::Group);
+        let mut at = cst.root();
+        for token in Lexer::<JavaTokenSet>::from_str("f(a, {b}) c") {
+            match token.token.spelling().as_ref() {
+                "(" | "{" => {
+                    at = cst.push_node(at, Calc::Group);
+                    cst.push_token(at, token);
+                }
+                ")" | "}" => {
+                    cst.push_token(at, token);
+                    at = cst.parent(at).unwrap();
+                }
+                _ => {
+                    cst.push_token(at, token);
+                }
+            }
+        }
+        assert_eq!(
+            cst.render(cst.root()),
+            "(Group f (Group ( a , (Group { b }) )) c)"
+        );
+        let inner = cst.children(cst.root())[1];
+        assert_eq!(cst.span(inner).map(|s| (s.start, s.end)), Some((1, 9)));
+        assert_eq!(
+            Cst::<JavaToken, Calc>::new(Calc::Group).span(NodeId(0)),
+            None
+        );
+    }
 }
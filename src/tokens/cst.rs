@@ -0,0 +1,275 @@
+//! Concrete Syntax Trees (tokens/cst.rs)
+//!
+//! A tree holding every token it was built from, its inner nodes tagged with a kind the language
+//! defines (see `Parseable`). Nodes and tokens live in one arena and refer to each other by
+//! `NodeId`, so parents, children and siblings are a lookup away in any direction. Trees are
+//! built by hand with `push_node` and `push_token`, or taken from what the grammar driven parsers
+//! return (see engine/parser.rs and engine/descent.rs) with `Cst::from_parse_tree`.
+//!
+//! A node spans from the start of its first token to the end of its last one, a node without
+//! tokens has no span. `Cst::walk` goes through a tree depth first, telling a `Visitor` as it
+//! enters a node (pre order), at each token, and as it leaves a node (post order). Any of them
+//! can stop the walk, entering can also skip the node's children.
+use crate::engine::parser::ParseTree;
+use crate::tokens::span::{Span, Spanned};
+use crate::tokens::token_traits::{Parseable, Token};
+
+/// Place of a node or token in its tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(pub usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element<T, N> {
+    Node(N),
+    Token(Spanned<T>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Slot<T, N> {
+    element: Element<T, N>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    /// Among the parent's children
+    index: usize,
+    span: Option<Span>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cst<T, N> {
+    /// The root first, then everything in the order it was pushed
+    slots: Vec<Slot<T, N>>,
+}
+
+/// What a walk does after a visitor call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Walk {
+    Continue,
+    /// Leaves out the children of the node just entered, it is still left
+    Skip,
+    Stop,
+}
+
+/// Calls a walk makes, each one continuing the walk unless overridden
+pub trait Visitor<T, N> {
+    fn enter(&mut self, tree: &Cst<T, N>, node: NodeId, kind: &N) -> Walk {
+        let _ = (tree, node, kind);
+        Walk::Continue
+    }
+    fn token(&mut self, tree: &Cst<T, N>, id: NodeId, token: &Spanned<T>) -> Walk {
+        let _ = (tree, id, token);
+        Walk::Continue
+    }
+    fn leave(&mut self, tree: &Cst<T, N>, node: NodeId, kind: &N) -> Walk {
+        let _ = (tree, node, kind);
+        Walk::Continue
+    }
+}
+
+impl<T, N> Cst<T, N> {
+    /// A tree with nothing but a root of `kind`
+    pub fn new(kind: N) -> Self {
+        Cst {
+            slots: vec![Slot {
+                element: Element::Node(kind),
+                parent: None,
+                children: Vec::new(),
+                index: 0,
+                span: None,
+            }],
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        NodeId(0)
+    }
+
+    /// Adds a node of `kind` after the last child of `parent`
+    pub fn push_node(&mut self, parent: NodeId, kind: N) -> NodeId {
+        self.push(parent, Element::Node(kind), None)
+    }
+
+    /// Adds `token` after the last child of `parent`, the spans of the nodes above growing to
+    /// take it in. Tokens are expected in source order.
+    pub fn push_token(&mut self, parent: NodeId, token: Spanned<T>) -> NodeId {
+        let span = token.span;
+        let id = self.push(parent, Element::Token(token), Some(span));
+        for node in self.ancestors(id).collect::<Vec<_>>() {
+            let slot = &mut self.slots[node.0];
+            slot.span = Some(match slot.span {
+                Some(first) => Span {
+                    end: span.end,
+                    ..first
+                },
+                None => span,
+            });
+        }
+        id
+    }
+
+    fn push(&mut self, parent: NodeId, element: Element<T, N>, span: Option<Span>) -> NodeId {
+        assert!(
+            matches!(self.slots[parent.0].element, Element::Node(_)),
+            "tokens have no children"
+        );
+        let id = NodeId(self.slots.len());
+        let index = self.slots[parent.0].children.len();
+        self.slots[parent.0].children.push(id);
+        self.slots.push(Slot {
+            element,
+            parent: Some(parent),
+            children: Vec::new(),
+            index,
+            span,
+        });
+        id
+    }
+
+    pub fn element(&self, id: NodeId) -> &Element<T, N> {
+        &self.slots[id.0].element
+    }
+
+    /// Kind of `id` if it is a node
+    pub fn kind(&self, id: NodeId) -> Option<&N> {
+        match &self.slots[id.0].element {
+            Element::Node(kind) => Some(kind),
+            Element::Token(_) => None,
+        }
+    }
+
+    pub fn token(&self, id: NodeId) -> Option<&Spanned<T>> {
+        match &self.slots[id.0].element {
+            Element::Token(token) => Some(token),
+            Element::Node(_) => None,
+        }
+    }
+
+    pub fn span(&self, id: NodeId) -> Option<Span> {
+        self.slots[id.0].span
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.slots[id.0].parent
+    }
+
+    /// Nodes and tokens right under `id`, in source order
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self.slots[id.0].children
+    }
+
+    pub fn next_sibling(&self, id: NodeId) -> Option<NodeId> {
+        let parent = self.parent(id)?;
+        self.children(parent)
+            .get(self.slots[id.0].index + 1)
+            .copied()
+    }
+
+    pub fn prev_sibling(&self, id: NodeId) -> Option<NodeId> {
+        let parent = self.parent(id)?;
+        let index = self.slots[id.0].index.checked_sub(1)?;
+        Some(self.children(parent)[index])
+    }
+
+    /// Parent, grandparent and so on up to the root
+    pub fn ancestors(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.parent(id), |&node| self.parent(node))
+    }
+
+    /// `id` and everything under it, in pre order
+    pub fn descendants(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let mut stack = vec![id];
+        std::iter::from_fn(move || {
+            let next = stack.pop()?;
+            stack.extend(self.children(next).iter().rev());
+            Some(next)
+        })
+    }
+
+    /// Tokens under `id`, in source order
+    pub fn tokens(&self, id: NodeId) -> impl Iterator<Item = &Spanned<T>> + '_ {
+        self.descendants(id).filter_map(|node| self.token(node))
+    }
+
+    /// Walks the whole tree, `Walk::Stop` if the visitor stopped it
+    pub fn walk(&self, visitor: &mut impl Visitor<T, N>) -> Walk {
+        self.walk_from(self.root(), visitor)
+    }
+
+    /// Walks `id` and everything under it
+    pub fn walk_from(&self, id: NodeId, visitor: &mut impl Visitor<T, N>) -> Walk {
+        // nodes are pushed again to be left once their children are done
+        let mut stack = vec![(id, false)];
+        while let Some((id, done)) = stack.pop() {
+            let walk = match (&self.slots[id.0].element, done) {
+                (Element::Token(token), _) => visitor.token(self, id, token),
+                (Element::Node(kind), true) => visitor.leave(self, id, kind),
+                (Element::Node(kind), false) => {
+                    let walk = visitor.enter(self, id, kind);
+                    if walk != Walk::Stop {
+                        stack.push((id, true));
+                    }
+                    if walk == Walk::Continue {
+                        stack.extend(self.children(id).iter().rev().map(|&c| (c, false)));
+                    }
+                    walk
+                }
+            };
+            if walk == Walk::Stop {
+                return Walk::Stop;
+            }
+        }
+        Walk::Continue
+    }
+}
+
+impl<T, N: Parseable> Cst<T, N> {
+    /// The tree of a grammar driven parser, `None` if `N` has no kind for one of its nodes.
+    /// Parsers put a node at the root, a lone token becomes the child of a root of `N`'s kind
+    /// for the start symbol.
+    pub fn from_parse_tree(tree: ParseTree<T>) -> Option<Self> {
+        let (symbol, children) = match tree {
+            ParseTree::Node {
+                symbol, children, ..
+            } => (symbol, children),
+            token => (1, vec![token]),
+        };
+        let mut cst = Cst::new(N::from_symbol(symbol)?);
+        let mut stack = vec![(cst.root(), children.into_iter())];
+        while let Some((parent, children)) = stack.last_mut() {
+            let parent = *parent;
+            match children.next() {
+                Some(ParseTree::Token(token)) => {
+                    cst.push_token(parent, token);
+                }
+                Some(ParseTree::Node {
+                    symbol, children, ..
+                }) => {
+                    let node = cst.push_node(parent, N::from_symbol(symbol)?);
+                    stack.push((node, children.into_iter()));
+                }
+                None => {
+                    stack.pop();
+                }
+            }
+        }
+        Some(cst)
+    }
+}
+
+impl<T: Token, N: Parseable> Cst<T, N> {
+    /// `id` as nested lists, `(Name children...)` with tokens spelled out, the way
+    /// `ParseTree::render` has it
+    pub fn render(&self, id: NodeId) -> String {
+        match &self.slots[id.0].element {
+            Element::Token(token) => token.token.spelling().into_owned(),
+            Element::Node(kind) => {
+                let mut out = format!("({}", kind.name());
+                for &child in self.children(id) {
+                    out.push(' ');
+                    out.push_str(&self.render(child));
+                }
+                out.push(')');
+                out
+            }
+        }
+    }
+}
@@ -0,0 +1,201 @@
+//! Lines and Positions (tokens/lines.rs)
+//!
+//! A line ends at `\n`, `\r\n` or a lone `\r`, whichever the file uses. Spans count columns in
+//! chars, editors want them in other units: LSP clients mostly speak UTF-16 code units. A
+//! `LineIndex` keeps the byte offset every line starts at, so a byte offset turns into a line
+//! and a column in any of the three encodings and back with a binary search and a walk over a
+//! single line. The lexer fills one as it goes (`Lexer::lines`), `LineIndex::new` builds it
+//! from any text.
+use crate::tokens::span::Span;
+use std::ops::Range;
+
+/// What a column counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PositionEncoding {
+    /// Bytes
+    Utf8,
+    /// UTF-16 code units, what LSP uses unless the client says otherwise
+    #[default]
+    Utf16,
+    /// Chars, what spans use
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Columns `text` takes
+    pub fn width(self, text: &str) -> usize {
+        match self {
+            PositionEncoding::Utf8 => text.len(),
+            PositionEncoding::Utf16 => text.chars().map(char::len_utf16).sum(),
+            PositionEncoding::Utf32 => text.chars().count(),
+        }
+    }
+}
+
+/// A zero based line and column, as LSP has it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Position {
+    pub line: usize,
+    pub character: usize,
+}
+
+#[inline(always)]
+pub fn is_line_end(b: u8) -> bool {
+    b == b'\n' || b == b'\r'
+}
+
+/// Offset of the first line terminator at or after `from`, the end of `bytes` if none
+pub fn line_end(bytes: &[u8], from: usize) -> usize {
+    bytes[from..]
+        .iter()
+        .position(|&b| is_line_end(b))
+        .map_or(bytes.len(), |i| from + i)
+}
+
+/// Length of the line terminator at `at`, 0 if there is none
+pub fn terminator_len(bytes: &[u8], at: usize) -> usize {
+    match bytes.get(at) {
+        Some(b'\r') if bytes.get(at + 1) == Some(&b'\n') => 2,
+        Some(b'\n' | b'\r') => 1,
+        _ => 0,
+    }
+}
+
+/// Where the lines ended by bytes in `range` start. A `\r\n` ends its line at the `\n`, so a
+/// `\r` at the end of the range only counts if the byte after it is not `\n`.
+pub fn breaks(bytes: &[u8], range: Range<usize>) -> impl Iterator<Item = usize> + '_ {
+    let start = range.start;
+    bytes[range]
+        .iter()
+        .enumerate()
+        .filter(move |&(i, &b)| {
+            b == b'\n' || (b == b'\r' && bytes.get(start + i + 1) != Some(&b'\n'))
+        })
+        .map(move |(i, _)| start + i + 1)
+}
+
+/// Byte offsets the lines of a text start at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineIndex {
+    /// Line of `starts[0]`, an index a lexer filled after resuming knows nothing before it
+    first: usize,
+    starts: Vec<usize>,
+}
+
+impl Default for LineIndex {
+    fn default() -> Self {
+        LineIndex {
+            first: 0,
+            starts: vec![0],
+        }
+    }
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let mut index = LineIndex::default();
+        index.starts.extend(breaks(text.as_bytes(), 0..text.len()));
+        index
+    }
+
+    /// An index that knows lines from `line` on, `line` starting at byte `start`
+    pub fn starting_at(line: usize, start: usize) -> Self {
+        LineIndex {
+            first: line,
+            starts: vec![start],
+        }
+    }
+
+    /// Records the line starting at `start`, lines come in order
+    #[inline(always)]
+    pub fn push(&mut self, start: usize) {
+        if self.starts.last().is_none_or(|&last| start > last) {
+            self.starts.push(start);
+        }
+    }
+
+    /// Lines known to the index, counted from the first one of the text
+    pub fn len(&self) -> usize {
+        self.first + self.starts.len()
+    }
+
+    /// Never, even an empty text has a line
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Byte offset `line` starts at
+    pub fn line_start(&self, line: usize) -> Option<usize> {
+        self.starts.get(line.checked_sub(self.first)?).copied()
+    }
+
+    /// Bytes of `line` in `text`, its terminator left out
+    pub fn line(&self, text: &str, line: usize) -> Option<Range<usize>> {
+        let start = self.line_start(line)?;
+        Some(start..line_end(text.as_bytes(), start.min(text.len())))
+    }
+
+    /// Line `offset` is on. Offsets before the first line known are put on it.
+    pub fn line_of(&self, offset: usize) -> usize {
+        self.first
+            + self
+                .starts
+                .partition_point(|&s| s <= offset)
+                .saturating_sub(1)
+    }
+
+    /// Line and column of byte `offset` in `text`. An offset inside a char is taken back to the
+    /// start of the char, one past the end of `text` to its end.
+    pub fn position(&self, text: &str, offset: usize, encoding: PositionEncoding) -> Position {
+        let mut offset = offset.min(text.len());
+        while !text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let line = self.line_of(offset);
+        let start = self.line_start(line).unwrap_or_default().min(offset);
+        Position {
+            line,
+            character: encoding.width(&text[start..offset]),
+        }
+    }
+
+    /// Byte offset of `position` in `text`. A column past the end of its line means the end of
+    /// it, one inside a char the end of the char. `None` for a line the index doesn't know.
+    pub fn offset(
+        &self,
+        text: &str,
+        position: Position,
+        encoding: PositionEncoding,
+    ) -> Option<usize> {
+        let line = self.line(text, position.line)?;
+        let mut column = 0;
+        for (i, c) in text[line.clone()].char_indices() {
+            if column >= position.character {
+                return Some(line.start + i);
+            }
+            column += encoding.width(c.encode_utf8(&mut [0; 4]));
+        }
+        Some(line.end)
+    }
+
+    /// Where `span` starts and ends
+    pub fn range(&self, text: &str, span: Span, encoding: PositionEncoding) -> Range<Position> {
+        self.position(text, span.start, encoding)..self.position(text, span.end, encoding)
+    }
+
+    /// Brings the index up to date after the bytes in `range` were replaced by `inserted` new
+    /// ones, `text` being the text after the edit. Only the lines around the edit are scanned.
+    pub fn edit(&mut self, text: &str, range: Range<usize>, inserted: usize) {
+        // a `\r` right before the edit may have lost or gained the `\n` after it
+        let from = range.start.saturating_sub(1).max(self.starts[0]);
+        let to = (range.start + inserted + 1).min(text.len());
+        let lo = self.starts.partition_point(|&s| s <= from);
+        let hi = self.starts.partition_point(|&s| s <= range.end + 1);
+        let fresh: Vec<usize> = breaks(text.as_bytes(), from..to.max(from)).collect();
+        let moved = lo + fresh.len();
+        self.starts.splice(lo..hi, fresh);
+        for start in &mut self.starts[moved..] {
+            *start = *start + inserted - range.len();
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Printing Tokens (tokens/printer.rs)
+//!
+//! Turns a token stream back into source. `PrintMode::Exact` writes each token with the leading
+//! trivia and source text lossless mode kept on it, so the input comes back byte for byte.
+//! `PrintMode::Canonical` only needs the tokens: each one is written as `Token::spelling` has
+//! it, with the room around it `Token::spacing` asks for, line breaks and indentation included.
+//! Whatever the spacing, tokens that would run together into other ones are kept apart, so the
+//! canonical text lexes back into the same tokens. Exact mode prints a token without trivia the
+//! canonical way.
+use crate::tokens::span::Spanned;
+use crate::tokens::token_traits::{Delimeted, Token, TokenKind};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PrintMode {
+    /// Normalized spacing, from the tokens alone
+    #[default]
+    Canonical,
+    /// The original text, from lossless trivia
+    Exact,
+}
+
+/// The room canonical printing leaves around a token. A space goes between two tokens when the
+/// first one wants it after and the second before.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Spacing {
+    pub before: bool,
+    pub after: bool,
+    /// The next token goes on a new line
+    pub line_break: bool,
+    /// Opens (1) or closes (-1) a level of indentation, a closer is already printed at the
+    /// outer level
+    pub indent: i8,
+}
+
+impl Spacing {
+    pub const SPACED: Spacing = Spacing {
+        before: true,
+        after: true,
+        line_break: false,
+        indent: 0,
+    };
+    pub const TIGHT: Spacing = Spacing {
+        before: false,
+        after: false,
+        line_break: false,
+        indent: 0,
+    };
+
+    /// What a token of `kind` gets unless its tokenset says otherwise: punctuation sits tight,
+    /// everything else is spaced
+    pub fn of(kind: TokenKind) -> Spacing {
+        match kind {
+            TokenKind::Delimeter | TokenKind::Whitespace => Spacing::TIGHT,
+            _ => Spacing::SPACED,
+        }
+    }
+}
+
+/// Tokens that could run together into a different one if nothing separated them: two words,
+/// two operators (`+` `+`), or a literal and an operator it may take in (`1` `-`, `3.14` `.`).
+/// A number is a literal whatever kind its tokenset gives it, `number` says whether `prev` is one.
+fn would_merge(prev: TokenKind, number: bool, next: TokenKind) -> bool {
+    let word = |k| {
+        matches!(
+            k,
+            TokenKind::Identifier | TokenKind::Keyword | TokenKind::Literal | TokenKind::Unknown
+        )
+    };
+    let literal = prev == TokenKind::Literal || number;
+    (word(prev) && word(next))
+        || (prev == TokenKind::Operator && next == TokenKind::Operator)
+        || (literal && next == TokenKind::Operator)
+}
+
+/// Numbers start with a digit, in every lang the engine lexes
+fn is_number(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Prints tokens pushed one at a time. It holds on to one token, `Token::spelling_between`
+/// needs to see the one after it.
+#[derive(Debug, Clone)]
+pub struct Printer<T: Token> {
+    pub mode: PrintMode,
+    /// Spaces per level of indentation
+    pub indent_width: usize,
+    out: String,
+    pending: Option<Spanned<T>>,
+    prev: Option<T>,
+    /// Kind and spacing of the last token printed the canonical way, and if it was a number
+    last: Option<(TokenKind, Spacing, bool)>,
+    depth: usize,
+}
+
+impl<T: Token> Printer<T> {
+    pub fn new(mode: PrintMode) -> Self {
+        Printer {
+            mode,
+            indent_width: 4,
+            out: String::new(),
+            pending: None,
+            prev: None,
+            last: None,
+            depth: 0,
+        }
+    }
+
+    pub fn push(&mut self, token: Spanned<T>) {
+        if let Some(pending) = self.pending.take() {
+            self.write(&pending, Some(&token.token));
+            self.prev = Some(pending.token);
+        }
+        self.pending = Some(token);
+    }
+
+    /// Everything printed, the last line ended if a token asked for it
+    pub fn finish(mut self) -> String {
+        if let Some(pending) = self.pending.take() {
+            self.write(&pending, None);
+        }
+        if self.last.is_some_and(|(_, s, _)| s.line_break) {
+            self.out.push('\n');
+        }
+        self.out
+    }
+
+    fn write(&mut self, token: &Spanned<T>, next: Option<&T>) {
+        if self.mode == PrintMode::Exact
+            && let Some(lossless) = &token.lossless
+        {
+            lossless.write_to(&mut self.out);
+            self.last = None;
+            return;
+        }
+        let text = token.token.spelling_between(self.prev.as_ref(), next);
+        if text.is_empty() {
+            return;
+        }
+        let kind = token.token.kind();
+        let spacing = token.token.spacing();
+        if spacing.indent < 0 {
+            self.depth = self.depth.saturating_sub(1);
+        }
+        let spaced = match self.last {
+            Some((_, last, _)) if last.line_break => {
+                self.out.push('\n');
+                let width = self.depth * self.indent_width;
+                self.out.extend(std::iter::repeat_n(' ', width));
+                false
+            }
+            Some((last_kind, last, number)) => {
+                (last.after && spacing.before) || would_merge(last_kind, number, kind)
+            }
+            // right after exact text a space is needed where the two would merge
+            None => {
+                !self.out.is_empty()
+                    && !self.out.ends_with(char::is_whitespace)
+                    && self
+                        .prev
+                        .as_ref()
+                        .is_some_and(|p| would_merge(p.kind(), is_number(&p.spelling()), kind))
+            }
+        };
+        if spaced {
+            self.out.push(' ');
+        }
+        self.out.push_str(&text);
+        if spacing.indent > 0 {
+            self.depth += 1;
+        }
+        self.last = Some((kind, spacing, is_number(&text)));
+    }
+}
+
+/// Prints a whole stream
+pub fn print<T: Token>(tokens: &[Spanned<T>], mode: PrintMode) -> String {
+    let mut printer = Printer::new(mode);
+    for token in tokens {
+        printer.push(token.clone());
+    }
+    printer.finish()
+}
+
+/// `text` between `open` and `close` with the escapes of `T` put back where the raw char can't
+/// stay: backslashes, anything that would close the literal early and control chars. A control
+/// char the lang has no escape letter for gets a unicode escape, if the lang has those.
+pub fn quote<T: Delimeted>(text: &str, open: &str, close: &str) -> String {
+    let mut out = String::with_capacity(open.len() + text.len() + close.len());
+    out.push_str(open);
+    for (i, c) in text.char_indices() {
+        let closes = !close.is_empty() && text[i..].starts_with(close);
+        if c == '\\' || closes || c.is_control() {
+            if let Some(letter) = (' '..='~').find(|l| T::is_scape(l) == Some(c)) {
+                out.push('\\');
+                out.push(letter);
+                continue;
+            }
+            if c.is_control()
+                && let Some(width) = T::allowed_unicode_char(&'u')
+            {
+                let _ = write!(out, "\\u{:0width$x}", c as u32, width = width);
+                continue;
+            }
+        }
+        out.push(c);
+    }
+    out.push_str(close);
+    out
+}
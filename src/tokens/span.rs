@@ -0,0 +1,69 @@
+//! Source Spans (tokens/span.rs)
+//!
+//! Location data attached to every token the lexer emits, so tools can point back into the
+//! original file. Offsets are byte based, rows and columns are zero based.
+use crate::tokens::trivia::Lossless;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct Span {
+    /// Byte offset of the first byte of the token
+    pub start: usize,
+    /// Byte offset one past the last byte of the token
+    pub end: usize,
+    pub row: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
+/// Human readable form, 1-based like most compilers print it.
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{} [{}..{}]",
+            self.row + 1,
+            self.column + 1,
+            self.start,
+            self.end
+        )
+    }
+}
+
+/// A token together with the place it came from. This is the unit the lexer sends through the
+/// channel for every Lexable tokenset. `lossless` is only filled when the lexer runs in lossless
+/// mode, and is boxed so the common path pays a single pointer for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned<T> {
+    pub token: T,
+    pub span: Span,
+    pub lossless: Option<Box<Lossless>>,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(token: T, span: Span) -> Self {
+        Spanned {
+            token,
+            span,
+            lossless: None,
+        }
+    }
+
+    /// Converts the token and keeps the location and trivia
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Spanned<U> {
+        Spanned {
+            token: f(self.token),
+            span: self.span,
+            lossless: self.lossless,
+        }
+    }
+}
@@ -1,28 +1,92 @@
 //! Token Traits Interface (tokens/token_traits.rs)
 //!
 //! Needed functions to make the engine work, also use of generics for multi-language support
+use crate::engine::errors::LexError;
+use crate::engine::lexer::ImportScope;
+use crate::engine::sink::TokenSink;
 use crate::engine::states::State;
+use crate::tokens::lines::{line_end, terminator_len};
+use crate::tokens::printer::Spacing;
+use std::borrow::Cow;
 use std::{char, hash::Hash};
 pub trait Token: Clone + std::fmt::Debug + PartialEq {
     fn kind(&self) -> TokenKind;
+    /// End of input marker, lossless mode hangs the trailing trivia on it
+    fn eof() -> Self;
+    /// Canonical source text of the token: its operator or keyword as written, a literal with
+    /// its quotes and escapes put back. Empty for the end of input.
+    fn spelling(&self) -> Cow<'_, str>;
+    /// Spelling next to `prev` and `next`, for tokens whose text depends on their neighbours
+    /// (a string fragment next to an embedded expression has no quote on that side)
+    fn spelling_between(&self, prev: Option<&Self>, next: Option<&Self>) -> Cow<'_, str> {
+        let _ = (prev, next);
+        self.spelling()
+    }
+    /// What a grammar calls the token (see engine/grammar.rs): `EOF` for the end of input, the
+    /// class of identifiers and literals, the spelling of anything else
+    fn terminal(&self) -> Cow<'_, str> {
+        if *self == Self::eof() {
+            return Cow::Borrowed("EOF");
+        }
+        match self.kind() {
+            TokenKind::Identifier | TokenKind::Unknown => Cow::Borrowed("Identifier"),
+            TokenKind::Literal => Cow::Borrowed("Literal"),
+            _ => self.spelling(),
+        }
+    }
+    /// Room canonical printing leaves around the token (see tokens/printer.rs)
+    fn spacing(&self) -> Spacing {
+        Spacing::of(self.kind())
+    }
     //fn to_id(&self) -> String;
 }
 
 pub trait Resolvable {
-    fn resolve_import(
+    /// Imported files outlive nothing of the importer, so their tokens go out detached
+    /// (see `Lexable::into_owned`). They are lexed under `scope` (see `Lexer::for_import`), a
+    /// sink error or a limit they run into aborts the import and is handed back.
+    fn resolve_import<'a>(
         name: String,
-        sender: crossbeam::channel::Sender<Vec<<Self as Lexable>::Token>>,
-    ) where
+        scope: &ImportScope,
+        sink: &mut dyn TokenSink<<Self as Lexable>::Token<'a>>,
+    ) -> Result<(), LexError>
+    where
         Self: Lexable;
 }
 
 /// Main trait for the tokensets, defines the tokentypes in Token and holds the inference logic for
-/// types and enum assignment.
+/// types and enum assignment. The lexer wraps every inferred token in a `Spanned` with its
+/// location, so implementors never deal with positions themselves.
+///
+/// Tokens borrow from the source buffer: `infer_token` gets a `Cow` that is a plain slice of the
+/// input unless the lexer had to rewrite the text (escapes in string and char literals).
 pub trait Lexable {
-    type Token: Clone + std::fmt::Debug + PartialEq + Token;
-    fn infer_token(c: String, state: &mut State<Self>) -> Option<Self::Token>
+    type Token<'a>: Clone + std::fmt::Debug + PartialEq + Token;
+    fn infer_token<'a>(c: Cow<'a, str>, state: &mut State<Self>) -> Option<Self::Token<'a>>
     where
         Self: Sized + Delimeted + Lexable;
+    /// Copies whatever a token borrows so it can outlive the source buffer
+    fn into_owned<'a, 'b>(token: Self::Token<'a>) -> Self::Token<'b>;
+    /// How much of the operator `op` found by maximal munch to take as one token, all of it
+    /// by default. Lets a language split a run it reads differently in context, like `>>`
+    /// closing two type argument lists.
+    fn operator_len(op: &str, _state: &State<Self>) -> usize
+    where
+        Self: Sized + Delimeted + Lexable,
+    {
+        op.len()
+    }
+    /// Token for a marker around an expression embedded in a string (`${`, `}`), none by
+    /// default. The lexer calls it with `Mode::Embed` on top for an opener and right after
+    /// popping it for a closer, so `state.mode()` tells them apart. Like `infer_token` it may
+    /// push and pop modes of its own.
+    fn embed_marker<'a>(marker: Cow<'a, str>, state: &mut State<Self>) -> Option<Self::Token<'a>>
+    where
+        Self: Sized + Delimeted + Lexable,
+    {
+        let _ = (marker, state);
+        None
+    }
 }
 
 /// Describes the language use of delimeters, comments, operators, etc. The lexer engine uses these
@@ -30,19 +94,42 @@ pub trait Lexable {
 pub trait Delimeted
 where
     Self: Sized + Eq + Lexable + Clone + Hash,
-    Self::Token: Token,
 {
-    fn is_delimeter(c: &u8) -> bool;
-    /// Max secuence length for inline comment
-    fn may_trigger_line_comment(c: char) -> Option<usize>;
-    /// Max secuence length for block comment (end secuence / usize))
-    fn may_trigger_block_comment(c: char) -> Option<(Vec<char>, usize)>;
-    /// Match the n chars following the comment trigger -> dispatch eater
-    fn trigger_comment_line(c: &[char]) -> bool;
-    /// Match the n chars following the comment trigger -> dispatch eater
-    fn trigger_comment_block(c: &[char]) -> bool;
+    fn is_delimeter(c: &char) -> bool;
+    /// Chars besides the Unicode identifier ones (XID) the lang allows anywhere in an identifier
+    fn ident_extras() -> &'static [char] {
+        &['_']
+    }
+    /// Whether an identifier (or keyword) can start with `c`
+    fn is_ident_start(c: char) -> bool {
+        unicode_ident::is_xid_start(c) || Self::ident_extras().contains(&c)
+    }
+    /// Whether an identifier started already can go on with `c`
+    fn is_ident_continue(c: char) -> bool {
+        unicode_ident::is_xid_continue(c) || Self::ident_extras().contains(&c)
+    }
+    /// Every comment style of the lang, any number of line and block ones
+    fn comments() -> &'static [CommentStyle];
+    /// The comment opening at the start of `rest`, the longest opener wins when several match
+    /// (`///` over `//`)
+    fn comment_at(rest: &str) -> Option<&'static CommentStyle> {
+        Self::comments()
+            .iter()
+            .filter(|c| rest.starts_with(c.open()))
+            .max_by_key(|c| c.open().len())
+    }
+    /// Every string literal style of the lang
+    fn strings() -> &'static [StringStyle];
+    /// The string literal opening at the start of `rest`, the longest opener wins when several
+    /// match (`"""` over `"`)
+    fn string_at(rest: &str) -> Option<OpenString> {
+        Self::strings()
+            .iter()
+            .filter_map(|s| s.opens(rest))
+            .max_by_key(|o| o.len)
+    }
     /// returns if a string is an operator of the defined lang by the user
-    fn is_operator(c: &[u8]) -> bool;
+    fn is_operator(s: &str) -> bool;
     /// checks if a char is a valid number char
     fn allowed_number_chars(c: &char) -> bool;
     /// checks if a scaped char is a valid unicode char for the lang
@@ -51,8 +138,261 @@ where
     fn is_scape(c: &char) -> Option<char>;
 }
 
-/// TODO: This should be for making ASTs
-pub trait Parseable {}
+/// A way of writing comments. Markers can be of any length, and a nested block comment only ends
+/// once every opener inside it has been closed (`/* /* */ */`, `{- {- -} -}`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CommentStyle {
+    Line(&'static str),
+    Block {
+        open: &'static str,
+        close: &'static str,
+        nested: bool,
+    },
+}
+
+impl CommentStyle {
+    pub fn open(&self) -> &'static str {
+        match self {
+            CommentStyle::Line(open) => open,
+            CommentStyle::Block { open, .. } => open,
+        }
+    }
+
+    /// Length in bytes of the comment starting `rest`, terminator included. Line comments stop
+    /// after their line terminator. `Err` carries the length of an unterminated block comment,
+    /// which is everything left.
+    pub fn measure(&self, rest: &str) -> Result<usize, usize> {
+        self.measure_within(rest, usize::MAX)
+    }
+
+    /// `measure` that stops looking once the comment runs past `budget` bytes. Lengths past the
+    /// budget only say the comment is longer than that.
+    pub fn measure_within(&self, rest: &str, budget: usize) -> Result<usize, usize> {
+        let bytes = rest.as_bytes();
+        let window = bytes.len().min(budget.saturating_add(1));
+        match *self {
+            CommentStyle::Line(_) => {
+                let end = line_end(&bytes[..window], 0);
+                Ok(end + terminator_len(bytes, end))
+            }
+            CommentStyle::Block {
+                open,
+                close,
+                nested,
+            } => {
+                let (open, close) = (open.as_bytes(), close.as_bytes());
+                let mut at = open.len();
+                let mut depth = 1;
+                // one pass, whichever marker comes first decides, a closer where both start
+                while depth > 0 {
+                    let Some(i) = bytes.get(at..window).and_then(|w| {
+                        w.iter()
+                            .position(|&b| b == close[0] || (nested && b == open[0]))
+                    }) else {
+                        // out of input, or out of budget
+                        return Err(if window == bytes.len() {
+                            rest.len()
+                        } else {
+                            window.max(at)
+                        });
+                    };
+                    at += i;
+                    if bytes[at..].starts_with(close) {
+                        depth -= 1;
+                        at += close.len();
+                    } else if nested && bytes[at..].starts_with(open) {
+                        depth += 1;
+                        at += open.len();
+                    } else {
+                        at += 1;
+                    }
+                }
+                Ok(at)
+            }
+        }
+    }
+}
+
+/// A way of writing string literals. The terminator can depend on the opener: a padded string
+/// closes with as many pads as it opened with, a tagged one repeats its tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StringStyle {
+    /// Fixed markers (`"`, `"""`, `'''`). `escapes` turns backslash escapes on, `multiline` lets
+    /// the literal run across newlines.
+    Quoted {
+        open: &'static str,
+        close: &'static str,
+        escapes: bool,
+        multiline: bool,
+    },
+    /// Like `Quoted`, with expressions between `embed` and `embed_close` (`${x}`, `\\{x}`) that
+    /// are lexed as code, see `Mode`
+    Template {
+        open: &'static str,
+        close: &'static str,
+        embed: &'static str,
+        embed_close: &'static str,
+        escapes: bool,
+        multiline: bool,
+    },
+    /// `prefix`, any number of `pad`, `quote`, closed by `quote` and as many pads. Raw and
+    /// multiline (`r#"..."#`)
+    Padded {
+        prefix: &'static str,
+        pad: char,
+        quote: char,
+    },
+    /// `prefix`, a tag, `open`, closed by `close`, the tag and `quote`. Raw and multiline
+    /// (`R"x(...)x"`)
+    Tagged {
+        prefix: &'static str,
+        open: char,
+        close: char,
+        quote: char,
+    },
+}
+
+/// A string literal found at some offset, with the terminator its opener asks for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenString {
+    /// Length in bytes of the opener
+    pub len: usize,
+    pub close: Cow<'static, str>,
+    pub escapes: bool,
+    pub multiline: bool,
+    /// Opener and closer of embedded expressions, for templates
+    pub embed: Option<(&'static str, &'static str)>,
+}
+
+impl StringStyle {
+    /// Bytes every literal of this style starts with
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            StringStyle::Quoted { open, .. } => open,
+            StringStyle::Template { open, .. } => open,
+            StringStyle::Padded { prefix, .. } => prefix,
+            StringStyle::Tagged { prefix, .. } => prefix,
+        }
+    }
+
+    /// Reads the opener at the start of `rest`, if it is one of this style
+    pub fn opens(&self, rest: &str) -> Option<OpenString> {
+        let tail = rest.strip_prefix(self.prefix())?;
+        match *self {
+            StringStyle::Quoted {
+                open,
+                close,
+                escapes,
+                multiline,
+            } => Some(OpenString {
+                len: open.len(),
+                close: Cow::Borrowed(close),
+                escapes,
+                multiline,
+                embed: None,
+            }),
+            StringStyle::Template {
+                open,
+                close,
+                embed,
+                embed_close,
+                escapes,
+                multiline,
+            } => Some(OpenString {
+                len: open.len(),
+                close: Cow::Borrowed(close),
+                escapes,
+                multiline,
+                embed: Some((embed, embed_close)),
+            }),
+            StringStyle::Padded { prefix, pad, quote } => {
+                let pads = tail.chars().take_while(|&c| c == pad).count();
+                let tail = &tail[pads * pad.len_utf8()..];
+                tail.starts_with(quote).then(|| OpenString {
+                    len: prefix.len() + pads * pad.len_utf8() + quote.len_utf8(),
+                    close: Cow::Owned(
+                        std::iter::once(quote)
+                            .chain((0..pads).map(|_| pad))
+                            .collect(),
+                    ),
+                    escapes: false,
+                    multiline: true,
+                    embed: None,
+                })
+            }
+            StringStyle::Tagged {
+                prefix,
+                open,
+                close,
+                quote,
+            } => {
+                // C++ caps the tag at 16 chars and keeps it to plain visible ones
+                let (end, _) = tail.char_indices().take(17).find(|&(_, c)| c == open)?;
+                let tag = &tail[..end];
+                let plain = |c: char| !c.is_whitespace() && ![close, quote, '\\'].contains(&c);
+                tag.chars().all(plain).then(|| OpenString {
+                    len: prefix.len() + tag.len() + open.len_utf8(),
+                    close: Cow::Owned(format!("{}{}{}", close, tag, quote)),
+                    escapes: false,
+                    multiline: true,
+                    embed: None,
+                })
+            }
+        }
+    }
+}
+
+impl OpenString {
+    /// Length in bytes of the literal starting `rest`, opener and terminator included. `Err`
+    /// carries the length of an unterminated one, which ends before the newline when it can't
+    /// span lines, or with the input. Embedded expressions are skipped by counting braces,
+    /// strings inside them are not looked at.
+    pub fn measure(&self, rest: &str) -> Result<usize, usize> {
+        let bytes = rest.as_bytes();
+        let close = self.close.as_bytes();
+        let mut at = self.len;
+        while at < bytes.len() {
+            if let Some((embed, embed_close)) = self.embed
+                && bytes[at..].starts_with(embed.as_bytes())
+            {
+                at += embed.len();
+                let mut depth = 0usize;
+                loop {
+                    match bytes.get(at) {
+                        None => return Err(rest.len()),
+                        _ if depth == 0 && bytes[at..].starts_with(embed_close.as_bytes()) => {
+                            at += embed_close.len();
+                            break;
+                        }
+                        Some(b'{') => depth += 1,
+                        Some(b'}') => depth = depth.saturating_sub(1),
+                        _ => {}
+                    }
+                    at += 1;
+                }
+                continue;
+            }
+            match bytes[at] {
+                _ if bytes[at..].starts_with(close) => return Ok(at + close.len()),
+                b'\n' | b'\r' if !self.multiline => return Err(at),
+                b'\\' if self.escapes => at += 2,
+                _ => at += 1,
+            }
+        }
+        Err(rest.len())
+    }
+}
+
+/// Kinds of the inner nodes of a concrete syntax tree (see tokens/cst.rs)
+pub trait Parseable: Copy + Eq + std::fmt::Debug {
+    fn name(&self) -> &'static str;
+    /// Kind of the nodes grammar driven parsers make for the nonterminal `symbol`, numbered as
+    /// in `Lowered::nonterminals`. Kinds a language builds its trees with by hand have none.
+    fn from_symbol(symbol: usize) -> Option<Self> {
+        let _ = symbol;
+        None
+    }
+}
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TokenKind {
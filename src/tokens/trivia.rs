@@ -0,0 +1,124 @@
+//! Trivia (tokens/trivia.rs)
+//!
+//! Everything the lexer normally throws away (whitespace, newlines, comments and chars no
+//! tokenset claims). In lossless mode it is attached to the token that follows it, and the
+//! stream ends with an EOF token holding whatever trivia trails the last real token.
+use crate::tokens::span::{Span, Spanned};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TriviaKind {
+    Whitespace,
+    /// A single line terminator: `\n`, `\r\n` or a lone `\r`
+    Newline,
+    LineComment,
+    BlockComment,
+    /// Source text consumed without producing a token (stray chars, import paths, ...)
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub span: Span,
+    pub text: String,
+}
+
+/// Extra data carried by a token in lossless mode: the trivia that precedes it and the exact
+/// source text of the token itself.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Lossless {
+    pub leading: Vec<Trivia>,
+    pub text: String,
+}
+
+impl Lossless {
+    pub fn write_to(&self, out: &mut String) {
+        for t in &self.leading {
+            out.push_str(&t.text);
+        }
+        out.push_str(&self.text);
+    }
+}
+
+/// Concatenates a lossless token stream back into the source it came from. Tokens lexed without
+/// trivia contribute nothing.
+pub fn reconstruct<T>(tokens: &[Spanned<T>]) -> String {
+    let mut out = String::new();
+    for t in tokens {
+        if let Some(l) = &t.lossless {
+            l.write_to(&mut out);
+        }
+    }
+    out
+}
+
+/// Moves a position from the start of `text` to its end. A `\r` at the very end of `text` is
+/// taken for a lone one.
+pub fn advance(at: &mut Span, text: &str) {
+    at.start += text.len();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\n' || (c == '\r' && chars.peek() != Some(&'\n')) {
+            at.row += 1;
+            at.column = 0;
+        } else {
+            at.column += 1;
+        }
+    }
+}
+
+/// Splits a gap of source text (no comments inside) into whitespace, newline and skipped runs.
+/// `at` is the position of the first char of the gap and is left pointing past the last one.
+pub fn split_gap(gap: &str, at: &mut Span, out: &mut Vec<Trivia>) {
+    let mut chars = gap.char_indices().peekable();
+    while let Some((i, ch)) = chars.next() {
+        let base = at.start;
+        let start = *at;
+        let kind = match ch {
+            '\n' => TriviaKind::Newline,
+            '\r' => {
+                if let Some((_, '\n')) = chars.peek() {
+                    chars.next();
+                }
+                TriviaKind::Newline
+            }
+            c if c.is_whitespace() => {
+                while let Some(&(_, c)) = chars.peek() {
+                    if !c.is_whitespace() || c == '\n' || c == '\r' {
+                        break;
+                    }
+                    chars.next();
+                }
+                TriviaKind::Whitespace
+            }
+            _ => {
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    chars.next();
+                }
+                TriviaKind::Skipped
+            }
+        };
+        let end = chars.peek().map_or(gap.len(), |&(j, _)| j);
+        let text = &gap[i..end];
+        if kind == TriviaKind::Newline {
+            at.row += 1;
+            at.column = 0;
+        } else {
+            at.column += text.chars().count();
+        }
+        at.start = base + text.len();
+        out.push(Trivia {
+            kind,
+            span: Span {
+                start: start.start,
+                end: at.start,
+                row: start.row,
+                column: start.column,
+            },
+            text: text.to_string(),
+        });
+    }
+}
@@ -0,0 +1,6 @@
+package testpkg;
+
+import testpkg.Cycle;
+
+public class Cycle {
+}
@@ -0,0 +1,7 @@
+package testpkg;
+
+public class Imported {
+    public static int answer() {
+        return 42;
+    }
+}